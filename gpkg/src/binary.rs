@@ -1,5 +1,11 @@
+use crate::failpoint::failpoint;
 use crate::storage::Metadata;
-use std::path::Path;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The directory in the bin directory where native shims keep their sidecar files
+const SIDECAR_DIR: &str = ".shims";
 
 pub struct Binary<P1: AsRef<Path>, P2: AsRef<Path>, NodePath: AsRef<Path>> {
     pub metadata: Metadata,
@@ -31,24 +37,449 @@ impl<P1: AsRef<Path>, P2: AsRef<Path>, P3: AsRef<Path>> Binary<P1, P2, P3> {
                 export PATH={node_binary_path:?}:$PATH
                 {binary_path:?} "$@"
             "#,
-            metadata_json = base64::encode(&serde_json::to_string(&self.metadata).unwrap()),
+            metadata_json = self.encoded_metadata(),
             binary_path = self.target_path.as_ref(),
             node_binary_path = binary_path,
         );
         unindent::unindent(&source)
     }
 
+    /// A batch file for `cmd.exe`, running npm's `.cmd` wrapper of the target when there is one
+    pub fn cmd_src(&self) -> String {
+        let node_path = self.node_binary_path.as_ref();
+        let node_dir = node_path.parent().expect("Got node with no parent");
+        let target_path = self.target_path.as_ref();
+        let source = format!(
+            r#"
+                @ECHO off
+                @REM metadata: {metadata_json}
+                SETLOCAL
+                SET "PATH={node_dir};%PATH%"
+                IF EXIST "{target_path}.cmd" (
+                  "{target_path}.cmd" %*
+                ) ELSE (
+                  "{node_path}" "{target_path}" %*
+                )
+            "#,
+            metadata_json = self.encoded_metadata(),
+            node_dir = cmd_escape(node_dir),
+            node_path = cmd_escape(node_path),
+            target_path = cmd_escape(target_path),
+        );
+        unindent::unindent(&source).replace('\n', "\r\n")
+    }
+
+    /// A PowerShell script, running npm's `.ps1` wrapper of the target when there is one
+    pub fn ps1_src(&self) -> String {
+        let node_path = self.node_binary_path.as_ref();
+        let node_dir = node_path.parent().expect("Got node with no parent");
+        let target_path = self.target_path.as_ref();
+        let source = format!(
+            r#"
+                #!/usr/bin/env pwsh
+                # metadata: {metadata_json}
+                $env:PATH = {node_dir} + [IO.Path]::PathSeparator + $env:PATH
+                if (Test-Path {target_wrapper}) {{
+                  & {target_wrapper} @args
+                }} else {{
+                  & {node_path} {target_path} @args
+                }}
+                exit $LASTEXITCODE
+            "#,
+            metadata_json = self.encoded_metadata(),
+            node_dir = powershell_quote(node_dir),
+            node_path = powershell_quote(node_path),
+            target_path = powershell_quote(target_path),
+            target_wrapper = powershell_quote(&append_extension(target_path, "ps1")),
+        );
+        unindent::unindent(&source)
+    }
+
+    pub fn script_src_for(&self, form: ScriptForm) -> String {
+        match form {
+            ScriptForm::Sh => self.script_src(),
+            ScriptForm::Cmd => self.cmd_src(),
+            ScriptForm::PowerShell => self.ps1_src(),
+        }
+    }
+
+    fn encoded_metadata(&self) -> String {
+        base64::encode(&serde_json::to_string(&self.metadata).unwrap())
+    }
+
+    /// Creates the binary as the kind of shim chosen in `$GPKG_SHIM`
+    pub fn create(self) -> std::io::Result<P1> {
+        match ShimKind::from_env() {
+            ShimKind::Script => self.create_script(),
+            ShimKind::Native => {
+                let shim_executable = native_shim_executable()?;
+                self.create_native(shim_executable)
+            }
+        }
+    }
+
+    /// Creates the binary as the scripts the current platform runs:
+    /// a shell script, plus `.cmd` and `.ps1` scripts on Windows
     pub fn create_script(self) -> std::io::Result<P1> {
-        let src = self.script_src();
-        std::fs::write(&self.symlink_path, &src)?;
-        set_permissions(&self.symlink_path)?;
+        self.create_scripts(ScriptForm::for_platform())
+    }
+
+    pub(crate) fn create_scripts(self, forms: &[ScriptForm]) -> std::io::Result<P1> {
+        remove_binary(&self.symlink_path)?;
+        for form in forms {
+            let path = form.path(&self.symlink_path);
+            std::fs::write(&path, self.script_src_for(*form))?;
+            set_permissions(&path)?;
+        }
 
         Ok(self.symlink_path)
     }
+
+    /// The sidecar file a native shim reads its node and target from
+    pub fn sidecar(&self) -> Sidecar {
+        Sidecar {
+            node_path: self.node_binary_path.as_ref().to_path_buf(),
+            target_path: self.target_path.as_ref().to_path_buf(),
+            metadata: serde_json::to_value(&self.metadata).unwrap(),
+        }
+    }
+
+    /// Creates the binary as a hard link to (or a copy of) the `gpkg-shim` executable,
+    /// with its metadata in a sidecar file
+    pub fn create_native(self, shim_executable: impl AsRef<Path>) -> std::io::Result<P1> {
+        if let Some(dir) = sidecar_path(&self.symlink_path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        remove_binary(&self.symlink_path)?;
+        self.sidecar().write(&self.symlink_path)?;
+        if let Err(error) = std::fs::hard_link(&shim_executable, &self.symlink_path) {
+            debug!("Can't hard link the shim, copying it instead: {}", error);
+            std::fs::copy(&shim_executable, &self.symlink_path)?;
+            set_permissions(&self.symlink_path)?;
+        }
+
+        Ok(self.symlink_path)
+    }
+
+    /// Whether the binary on disk is what [`Binary::create`] would write,
+    /// whichever kind of shim it is
+    pub fn is_up_to_date(&self) -> bool {
+        let sidecar_path = sidecar_path(&self.symlink_path);
+        if sidecar_path.exists() {
+            std::fs::read_to_string(sidecar_path)
+                .is_ok_and(|sidecar| sidecar == self.sidecar().to_json())
+        } else {
+            ScriptForm::for_platform().iter().all(|form| {
+                std::fs::read_to_string(form.path(&self.symlink_path))
+                    .is_ok_and(|script| script == self.script_src_for(*form))
+            })
+        }
+    }
+}
+
+/// The scripts a binary can be made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptForm {
+    /// A POSIX shell script, for Unix and Git Bash
+    Sh,
+    /// A batch file, for `cmd.exe`
+    Cmd,
+    /// A PowerShell script
+    PowerShell,
+}
+
+impl ScriptForm {
+    pub const ALL: [Self; 3] = [Self::Sh, Self::Cmd, Self::PowerShell];
+
+    /// The scripts written on the current platform
+    pub fn for_platform() -> &'static [Self] {
+        if cfg!(windows) {
+            &Self::ALL
+        } else {
+            &[Self::Sh]
+        }
+    }
+
+    fn extension(self) -> Option<&'static str> {
+        match self {
+            Self::Sh => None,
+            Self::Cmd => Some("cmd"),
+            Self::PowerShell => Some("ps1"),
+        }
+    }
+
+    /// The path of this script of the binary at `binary_path`
+    pub fn path(self, binary_path: impl AsRef<Path>) -> PathBuf {
+        match self.extension() {
+            Some(extension) => append_extension(binary_path.as_ref(), extension),
+            None => binary_path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The path of the binary a file in the bin directory is a script of
+    pub fn binary_path(script_path: &Path) -> PathBuf {
+        let file_name = script_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        Self::ALL
+            .iter()
+            .filter_map(|form| form.extension())
+            .find_map(|extension| {
+                file_name
+                    .strip_suffix(extension)
+                    .and_then(|name| name.strip_suffix('.'))
+                    .filter(|name| !name.is_empty())
+            })
+            .map(|name| script_path.with_file_name(name))
+            .unwrap_or_else(|| script_path.to_path_buf())
+    }
+
+    /// The first script of the binary at `binary_path` that exists
+    pub fn find(binary_path: impl AsRef<Path>) -> Option<PathBuf> {
+        Self::ALL
+            .iter()
+            .map(|form| form.path(&binary_path))
+            .find(|path| path.is_file())
+    }
+}
+
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// Escapes `%` in paths written into batch files, where it would expand variables
+fn cmd_escape(path: &Path) -> String {
+    path.display().to_string().replace('%', "%%")
+}
+
+/// A single-quoted PowerShell string, which doesn't expand anything
+fn powershell_quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', "''"))
+}
+
+/// How binaries run their packages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShimKind {
+    /// A `/bin/sh` script with the metadata embedded in a comment
+    Script,
+    /// The `gpkg-shim` executable, reading its metadata from a sidecar file
+    Native,
+}
+
+impl ShimKind {
+    /// The kind of shim set in `GPKG_SHIM`, defaulting to scripts
+    pub fn from_env() -> Self {
+        match std::env::var("GPKG_SHIM").as_deref() {
+            Ok("native") => Self::Native,
+            Ok("script") | Err(_) => Self::Script,
+            Ok(other) => {
+                warn!("Unknown shim kind {:?}, using scripts", other);
+                Self::Script
+            }
+        }
+    }
+}
+
+/// What a native shim needs to run its package, stored next to it
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Sidecar {
+    pub node_path: PathBuf,
+    pub target_path: PathBuf,
+    /// The [`Metadata`] of the binary
+    pub metadata: serde_json::Value,
+}
+
+impl Sidecar {
+    pub fn read(binary_path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let contents = std::fs::read(sidecar_path(binary_path))?;
+        Ok(serde_json::from_slice(&contents)?)
+    }
+
+    pub(crate) fn write(&self, binary_path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(sidecar_path(binary_path), self.to_json())
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// The sidecar file of a native shim: `.shims/<name>.json` in its bin directory
+pub fn sidecar_path(binary_path: impl AsRef<Path>) -> PathBuf {
+    let binary_path = binary_path.as_ref();
+    let file_name = binary_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    binary_path
+        .with_file_name(SIDECAR_DIR)
+        .join(format!("{}.json", file_name))
+}
+
+/// The `gpkg-shim` executable: `$GPKG_SHIM_PATH`, or the one next to the running executable
+fn native_shim_executable() -> std::io::Result<PathBuf> {
+    let path = match std::env::var_os("GPKG_SHIM_PATH") {
+        Some(path) => PathBuf::from(path),
+        None => std::env::current_exe()?
+            .with_file_name(format!("gpkg-shim{}", std::env::consts::EXE_SUFFIX)),
+    };
+    if !path.is_file() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Can't find the gpkg-shim executable at {:?}", path),
+        ));
+    }
+    Ok(path)
+}
+
+/// Binaries written into a staging directory inside the bin directory, and moved into place
+/// with renames once everything else succeeded.
+///
+/// Dropping them before [`StagedBinaries::commit`] leaves the bin directory untouched.
+/// The binaries a commit replaces are kept in the staging directory until the staged binaries
+/// are dropped, so [`StagedBinaries::rollback`] can put them back.
+pub(crate) struct StagedBinaries {
+    bin_dir: PathBuf,
+    staging_dir: tempfile::TempDir,
+    names: Vec<String>,
+    /// The files moved into the bin directory, relative to it
+    committed: Vec<PathBuf>,
+    /// The files of replaced binaries, relative to the bin directory
+    replaced: Vec<PathBuf>,
+}
+
+impl StagedBinaries {
+    pub(crate) fn new(bin_dir: impl AsRef<Path>) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&bin_dir)?;
+        let staging_dir = tempfile::Builder::new()
+            .prefix(".staging")
+            .tempdir_in(&bin_dir)?;
+        Ok(Self {
+            bin_dir: bin_dir.as_ref().to_path_buf(),
+            staging_dir,
+            names: vec![],
+            committed: vec![],
+            replaced: vec![],
+        })
+    }
+
+    /// Writes `binary` into the staging directory, instead of the bin directory
+    pub(crate) fn stage<P2: AsRef<Path>, P3: AsRef<Path>>(
+        &mut self,
+        binary: Binary<PathBuf, P2, P3>,
+    ) -> std::io::Result<()> {
+        let name = binary
+            .symlink_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        failpoint(&format!("stage {}", name))?;
+        let staged = Binary {
+            symlink_path: self.staging_dir.path().join(&name),
+            ..binary
+        };
+        staged.create()?;
+        self.names.push(name);
+        Ok(())
+    }
+
+    /// Removes the binary named `name` from the bin directory on commit,
+    /// keeping its files around for [`StagedBinaries::rollback`] like the ones of replaced binaries
+    pub(crate) fn remove(&mut self, name: &str) {
+        self.names.push(name.to_string());
+    }
+
+    /// Moves the staged binaries into the bin directory, replacing the binaries with the same names.
+    /// Rolls back on failure.
+    pub(crate) fn commit(&mut self) -> std::io::Result<()> {
+        let backup_dir = self.staging_dir.path().join(".replaced");
+        std::fs::create_dir_all(backup_dir.join(SIDECAR_DIR))?;
+        let result = self.names.clone().iter().try_for_each(|name| {
+            failpoint(&format!("commit {}", name))?;
+            let files = binary_files(name);
+            for file in &files {
+                if self.bin_dir.join(file).symlink_metadata().is_ok() {
+                    std::fs::rename(self.bin_dir.join(file), backup_dir.join(file))?;
+                    self.replaced.push(file.clone());
+                }
+            }
+            for file in &files {
+                let staged = self.staging_dir.path().join(file);
+                if staged.symlink_metadata().is_ok() {
+                    if let Some(parent) = self.bin_dir.join(file).parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    std::fs::rename(staged, self.bin_dir.join(file))?;
+                    self.committed.push(file.clone());
+                }
+            }
+            Ok(())
+        });
+        if let Err(error) = result {
+            self.rollback()?;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Removes the committed binaries, and puts back the ones they replaced
+    pub(crate) fn rollback(&mut self) -> std::io::Result<()> {
+        for file in self.committed.drain(..) {
+            debug!("Removing {:?}", file);
+            std::fs::remove_file(self.bin_dir.join(file))?;
+        }
+        let backup_dir = self.staging_dir.path().join(".replaced");
+        for file in self.replaced.drain(..) {
+            debug!("Restoring {:?}", file);
+            std::fs::rename(backup_dir.join(&file), self.bin_dir.join(&file))?;
+        }
+        Ok(())
+    }
+}
+
+/// The files a binary named `name` may consist of, relative to the bin directory
+fn binary_files(name: &str) -> Vec<PathBuf> {
+    let scripts = ScriptForm::ALL.iter().map(|form| form.path(name));
+    scripts.chain(std::iter::once(sidecar_path(name))).collect()
+}
+
+/// Whether any script of a binary, or the sidecar file of a native shim, exists
+pub(crate) fn binary_exists(binary_path: impl AsRef<Path>) -> bool {
+    ScriptForm::find(&binary_path).is_some() || sidecar_path(&binary_path).exists()
+}
+
+/// The file to execute to run a binary: its batch file on Windows, when it has one
+pub(crate) fn runnable_path(binary_path: impl AsRef<Path>) -> PathBuf {
+    let cmd_path = ScriptForm::Cmd.path(&binary_path);
+    if cfg!(windows) && cmd_path.is_file() {
+        cmd_path
+    } else {
+        binary_path.as_ref().to_path_buf()
+    }
+}
+
+/// Removes all the scripts of a binary and its sidecar file, if they exist.
+///
+/// Native shims are hard links to the same executable,
+/// so binaries are always removed before being written again.
+pub(crate) fn remove_binary(binary_path: impl AsRef<Path>) -> std::io::Result<()> {
+    let scripts = ScriptForm::ALL.iter().map(|form| form.path(&binary_path));
+    for path in scripts.chain(std::iter::once(sidecar_path(&binary_path))) {
+        match std::fs::remove_file(&path) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error),
+            _ => {}
+        }
+    }
+    Ok(())
 }
 
 #[cfg(unix)]
-fn set_permissions(script_path: impl AsRef<Path>) -> std::io::Result<()> {
+pub(crate) fn set_permissions(script_path: impl AsRef<Path>) -> std::io::Result<()> {
     use std::os::unix::fs::PermissionsExt;
     let metadata = script_path.as_ref().metadata()?;
     let mut permissions = metadata.permissions();
@@ -58,6 +489,129 @@ fn set_permissions(script_path: impl AsRef<Path>) -> std::io::Result<()> {
 }
 
 #[cfg(windows)]
-fn set_permissions(_script_path: impl AsRef<Path>) -> std::io::Result<()> {
+pub(crate) fn set_permissions(_script_path: impl AsRef<Path>) -> std::io::Result<()> {
     Ok(())
 }
+
+/// Writes the script of `binary_name` from [`crate::storage::LatestMetadata::for_test`] into `bin_dir`
+#[cfg(test)]
+pub(crate) fn create_test_script(bin_dir: &Path, binary_name: &str, package_name: &str) {
+    let metadata = Metadata::V2(crate::storage::LatestMetadata::for_test(
+        package_name,
+        binary_name,
+    ));
+    Binary::new(
+        metadata,
+        bin_dir.join(binary_name),
+        "/installations/target",
+        "/usr/bin/node",
+    )
+    .create_script()
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{LatestMetadata, Metadata};
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    fn binary(bin_dir: &Path, binary_name: &str) -> Binary<PathBuf, &'static str, &'static str> {
+        let metadata = Metadata::V2(LatestMetadata {
+            package_version: Some("4.5.4".to_string()),
+            ..LatestMetadata::for_test("typescript", binary_name)
+        });
+        Binary::new(
+            metadata,
+            bin_dir.join(binary_name),
+            "/installations/typescript/node_modules/.bin/tsc",
+            "/usr/bin/node",
+        )
+    }
+
+    #[test]
+    fn creates_native_shims_with_sidecars() {
+        let dir = TempDir::new("binary").unwrap();
+        let shim_executable = dir.path().join("gpkg-shim");
+        std::fs::write(&shim_executable, "shim").unwrap();
+        let bin_dir = dir.path().join("bin");
+        std::fs::create_dir(&bin_dir).unwrap();
+
+        let path = binary(&bin_dir, "tsc")
+            .create_native(&shim_executable)
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "shim");
+        assert!(binary(&bin_dir, "tsc").is_up_to_date());
+        let metadata = Metadata::read(&path).unwrap().latest();
+        assert_eq!(metadata.package_version.as_deref(), Some("4.5.4"));
+        assert_eq!(
+            Sidecar::read(&path).unwrap().node_path,
+            PathBuf::from("/usr/bin/node")
+        );
+
+        // replacing a native shim with a script leaves the shim executable alone
+        binary(&bin_dir, "tsc").create_script().unwrap();
+        assert_eq!(std::fs::read_to_string(&shim_executable).unwrap(), "shim");
+        assert!(!sidecar_path(&path).exists());
+        assert!(binary(&bin_dir, "tsc").is_up_to_date());
+    }
+
+    #[test]
+    fn creates_windows_scripts() {
+        let dir = TempDir::new("binary").unwrap();
+        let path = binary(dir.path(), "tsc")
+            .create_scripts(&ScriptForm::ALL)
+            .unwrap();
+        let cmd = std::fs::read_to_string(dir.path().join("tsc.cmd")).unwrap();
+        assert!(cmd.starts_with("@ECHO off\r\n@REM metadata: "));
+        assert!(cmd.contains(
+            "\"/usr/bin/node\" \"/installations/typescript/node_modules/.bin/tsc\" %*\r\n"
+        ));
+        let ps1 = std::fs::read_to_string(dir.path().join("tsc.ps1")).unwrap();
+        assert!(ps1.contains("& '/installations/typescript/node_modules/.bin/tsc.ps1' @args"));
+
+        // every form carries the metadata, so any of them is enough to read it
+        for script in ["tsc.ps1", "tsc.cmd", "tsc"] {
+            let metadata = Metadata::read(&path).unwrap().latest();
+            assert_eq!(metadata.binary_name, "tsc");
+            let binaries = Metadata::read_all(dir.path()).unwrap();
+            assert_eq!(binaries.len(), 1);
+            std::fs::remove_file(dir.path().join(script)).unwrap();
+        }
+        assert!(Metadata::read_all(dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn quotes_paths_for_windows_scripts() {
+        let dir = TempDir::new("binary").unwrap();
+        let mut binary = binary(dir.path(), "tsc");
+        binary.target_path = "C:\\Users\\O'Brien\\100%\\tsc";
+        assert!(binary
+            .cmd_src()
+            .contains("\"C:\\Users\\O'Brien\\100%%\\tsc.cmd\" %*"));
+        assert!(binary
+            .ps1_src()
+            .contains("& '/usr/bin/node' 'C:\\Users\\O''Brien\\100%\\tsc' @args"));
+    }
+
+    #[test]
+    fn finds_binaries_of_scripts() {
+        assert_eq!(
+            ScriptForm::binary_path(Path::new("/bin/tsc.cmd")),
+            PathBuf::from("/bin/tsc")
+        );
+        assert_eq!(
+            ScriptForm::binary_path(Path::new("/bin/tsc.ps1")),
+            PathBuf::from("/bin/tsc")
+        );
+        assert_eq!(
+            ScriptForm::binary_path(Path::new("/bin/.cmd")),
+            PathBuf::from("/bin/.cmd")
+        );
+        assert_eq!(
+            ScriptForm::Cmd.path("/bin/tsc"),
+            PathBuf::from("/bin/tsc.cmd")
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use crate::binary::binary_exists;
+use crate::install_package::Errors;
+use crate::storage::Metadata;
+use log::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// What to do when a package wants to create a binary that
+/// is already provided by another package
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    /// Refuse to install the package
+    #[default]
+    Fail,
+    /// Overwrite the binary of the other package
+    Force,
+    /// Keep the binary of the other package, skipping ours
+    Keep,
+    /// Let the given package own the binary
+    Prefer(String),
+}
+
+/// Returns the binaries `package_name` should create in `bin_dir`,
+/// leaving out the ones that belong to other packages according to `policy`
+pub(crate) fn resolve_collisions(
+    package_name: &str,
+    binaries: HashMap<String, String>,
+    bin_dir: impl AsRef<Path>,
+    policy: &CollisionPolicy,
+) -> Result<HashMap<String, String>, Errors> {
+    let mut resolved = HashMap::new();
+
+    for (binary_name, target) in binaries {
+        let script_path = bin_dir.as_ref().join(&binary_name);
+        if !binary_exists(&script_path) {
+            resolved.insert(binary_name, target);
+            continue;
+        }
+
+        let owner = Metadata::read(&script_path)
+            .ok()
+            .map(|metadata| metadata.latest().package_name);
+
+        let owner = match owner {
+            Some(owner) if owner == package_name => {
+                resolved.insert(binary_name, target);
+                continue;
+            }
+            Some(owner) => owner,
+            None if policy == &CollisionPolicy::Force => {
+                resolved.insert(binary_name, target);
+                continue;
+            }
+            None => return Err(Errors::BinaryExists { path: script_path }),
+        };
+
+        match policy {
+            CollisionPolicy::Fail => {
+                return Err(Errors::BinaryCollision { binary_name, owner });
+            }
+            CollisionPolicy::Force => {
+                warn!(
+                    "Overwriting binary {:?} of package {:?}",
+                    binary_name, owner
+                );
+                resolved.insert(binary_name, target);
+            }
+            CollisionPolicy::Prefer(preferred) if preferred == package_name => {
+                warn!(
+                    "Overwriting binary {:?} of package {:?}",
+                    binary_name, owner
+                );
+                resolved.insert(binary_name, target);
+            }
+            CollisionPolicy::Keep | CollisionPolicy::Prefer(_) => {
+                warn!(
+                    "Skipping binary {:?}, which is provided by package {:?}",
+                    binary_name, owner
+                );
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::create_test_script;
+    use pretty_assertions::assert_eq;
+
+    fn binaries(names: &[&str]) -> HashMap<String, String> {
+        names
+            .iter()
+            .map(|name| (name.to_string(), format!("bin/{}", name)))
+            .collect()
+    }
+
+    fn sorted_names(binaries: HashMap<String, String>) -> Vec<String> {
+        let mut names: Vec<_> = binaries.into_keys().collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn fails_on_other_package_binary() {
+        let bin_dir = tempdir::TempDir::new("bin").unwrap();
+        create_test_script(bin_dir.path(), "tsc", "typescript");
+
+        let result = resolve_collisions(
+            "other-typescript",
+            binaries(&["tsc"]),
+            bin_dir.path(),
+            &CollisionPolicy::Fail,
+        );
+
+        match result {
+            Err(Errors::BinaryCollision { binary_name, owner }) => {
+                assert_eq!(binary_name, "tsc");
+                assert_eq!(owner, "typescript");
+            }
+            other => panic!("Expected a collision, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reinstalling_the_same_package_is_not_a_collision() {
+        let bin_dir = tempdir::TempDir::new("bin").unwrap();
+        create_test_script(bin_dir.path(), "tsc", "typescript");
+
+        let resolved = resolve_collisions(
+            "typescript",
+            binaries(&["tsc", "tsserver"]),
+            bin_dir.path(),
+            &CollisionPolicy::Fail,
+        )
+        .unwrap();
+
+        assert_eq!(sorted_names(resolved), vec!["tsc", "tsserver"]);
+    }
+
+    #[test]
+    fn prefer_skips_binaries_of_the_preferred_package() {
+        let bin_dir = tempdir::TempDir::new("bin").unwrap();
+        create_test_script(bin_dir.path(), "tsc", "typescript");
+
+        let policy = CollisionPolicy::Prefer("typescript".to_string());
+        let resolved = resolve_collisions(
+            "other-typescript",
+            binaries(&["tsc", "other-tsc"]),
+            bin_dir.path(),
+            &policy,
+        )
+        .unwrap();
+
+        assert_eq!(sorted_names(resolved), vec!["other-tsc"]);
+    }
+
+    #[test]
+    fn force_overwrites_foreign_files() {
+        let bin_dir = tempdir::TempDir::new("bin").unwrap();
+        std::fs::write(bin_dir.path().join("tsc"), "not a gpkg script").unwrap();
+
+        let fail = resolve_collisions(
+            "typescript",
+            binaries(&["tsc"]),
+            bin_dir.path(),
+            &CollisionPolicy::Fail,
+        );
+        assert!(matches!(fail, Err(Errors::BinaryExists { .. })));
+
+        let resolved = resolve_collisions(
+            "typescript",
+            binaries(&["tsc"]),
+            bin_dir.path(),
+            &CollisionPolicy::Force,
+        )
+        .unwrap();
+        assert_eq!(sorted_names(resolved), vec!["tsc"]);
+    }
+}
@@ -22,6 +22,20 @@ impl<P: AsRef<Path>> DirectoryPortal<P> {
         Self { target, temp_dir }
     }
 
+    /// The same portal, teleporting to `target` instead,
+    /// for targets that depend on what was put into the portal
+    pub fn with_target<Q: AsRef<Path>>(self, target: Q) -> DirectoryPortal<Q> {
+        DirectoryPortal {
+            temp_dir: self.temp_dir,
+            target,
+        }
+    }
+
+    /// The path the portal teleports to
+    pub fn target(&self) -> &Path {
+        self.target.as_ref()
+    }
+
     pub fn teleport(self) -> std::io::Result<P> {
         debug!(
             "Moving directory {:?} into {:?}",
@@ -0,0 +1,703 @@
+use crate::binary::{remove_binary, Binary};
+use crate::file_manifest::remove_manifest;
+use crate::index::Index;
+use crate::install_package::{
+    binary_target, create_binary, installed_version, Errors, InstallationInfo,
+};
+use crate::link_package::unlink_package;
+use crate::node::NodeBinary;
+use crate::node_package_version::NodePackageVersion;
+use crate::rebind_package::rebind_package;
+use crate::registry_config::RegistryConfig;
+use crate::reinstall_package::reinstall_package;
+use crate::storage::{binary_paths, script_node_path, BinEntry, LatestMetadata, Metadata};
+use crate::tarball_cache::TarballCache;
+use crate::upgrade_package::upgrade_package;
+use crate::version_range::VersionRange;
+use log::*;
+use miette::Diagnostic;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// A problem with a shim or an installation, found by [`diagnose`]
+#[derive(Debug, Error, Diagnostic)]
+pub enum Issue {
+    #[error("{path:?} was not created by gpkg")]
+    #[diagnostic(severity(warning), help("Remove it if you don't need it"))]
+    ForeignFile { path: PathBuf },
+
+    #[error("{path:?} is a corrupt gpkg binary: {reason}")]
+    #[diagnostic(help("Run `gpkg doctor --repair` to remove it, then install its package again"))]
+    CorruptShim { path: PathBuf, reason: String },
+
+    #[error("Binary {binary_name:?} of {package_name:?} is pinned to a missing node binary {node_path:?}")]
+    #[diagnostic(help(
+        "Run `gpkg doctor --repair` to rebind it to an available node {}, or `gpkg rebind {} --node <version>`",
+        node_version,
+        package_name
+    ))]
+    MissingNode {
+        binary_name: String,
+        package_name: String,
+        node_version: String,
+        node_path: PathBuf,
+    },
+
+    #[error("Binary {binary_name:?} belongs to {package_name:?}, which is not installed in {installation_path:?}")]
+    #[diagnostic(help(
+        "Run `gpkg doctor --repair` to remove the binary, or `gpkg install {}` to install the package again",
+        package_name
+    ))]
+    MissingInstallation {
+        binary_name: String,
+        package_name: String,
+        installation_path: PathBuf,
+    },
+
+    #[error("Binary {binary_name:?} of {package_name:?} points to a missing file {target_path:?}")]
+    #[diagnostic(help(
+        "Run `gpkg doctor --repair` to reinstall the package, or `gpkg upgrade {}`",
+        package_name
+    ))]
+    MissingTarget {
+        binary_name: String,
+        package_name: String,
+        target_path: PathBuf,
+    },
+
+    #[error("Binary {binary_name:?} is linked to a missing working copy {path:?}")]
+    #[diagnostic(help(
+        "Run `gpkg doctor --repair` or `gpkg unlink {}` to remove its binaries",
+        package_name
+    ))]
+    MissingWorkingCopy {
+        binary_name: String,
+        package_name: String,
+        path: PathBuf,
+    },
+
+    #[error("The script of binary {binary_name:?} is outdated")]
+    #[diagnostic(severity(warning), help("Run `gpkg doctor --repair` to regenerate it"))]
+    OutdatedShim {
+        binary_name: String,
+        package_name: String,
+    },
+
+    #[error("Package {package_name:?} is installed in {path:?}, but has no binaries")]
+    #[diagnostic(
+        severity(warning),
+        help(
+            "Run `gpkg doctor --repair --prune-orphans` or `gpkg uninstall {}` to remove it, \
+             unless its binaries were left to other packages on purpose",
+            package_name
+        )
+    )]
+    OrphanInstallation { package_name: String, path: PathBuf },
+
+    #[error("The index of installed binaries in {path:?} doesn't match the binaries")]
+    #[diagnostic(
+        severity(warning),
+        help("Run `gpkg doctor --repair` or `gpkg reindex` to rebuild it")
+    )]
+    StaleIndex { path: PathBuf },
+}
+
+/// Cross-checks every shim in `bin_dir` against the installations and node binaries they use,
+/// and every installation against the shims that use it
+pub fn diagnose<InstallationDir: AsRef<Path>, BinDir: AsRef<Path>>(
+    installation_dir: InstallationDir,
+    bin_dir: BinDir,
+) -> std::io::Result<Vec<Issue>> {
+    let mut issues = vec![];
+    let mut used_installations = HashSet::new();
+    let mut used_packages = HashSet::new();
+
+    for path in binary_paths(&bin_dir)? {
+        let metadata = match BinEntry::read(path.clone()) {
+            BinEntry::Binary(metadata) => *metadata,
+            BinEntry::Foreign(path) => {
+                issues.push(Issue::ForeignFile { path });
+                continue;
+            }
+            BinEntry::Corrupt { path, reason } => {
+                issues.push(Issue::CorruptShim { path, reason });
+                continue;
+            }
+        };
+        let target_path = metadata.installation_path(&installation_dir);
+        used_installations.insert(target_path.clone());
+        used_packages.insert(metadata.package_name.clone());
+        issues.extend(diagnose_shim(&path, metadata, target_path));
+    }
+
+    let mut installations: Vec<_> = installation_dir
+        .as_ref()
+        .read_dir()?
+        .filter_map(Result::ok)
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        .map(|entry| entry.path())
+        .filter(|path| !used_installations.contains(path))
+        .collect();
+    installations.sort();
+    for path in installations {
+        let package_name = installation_package_name(&path);
+        // versions without binaries of their own can still be switched to with `gpkg use`
+        if used_packages.contains(&package_name) {
+            continue;
+        }
+        issues.push(Issue::OrphanInstallation { package_name, path });
+    }
+
+    if let Some(index) = Index::read(&installation_dir) {
+        if index != Index::scan(&bin_dir)? {
+            issues.push(Issue::StaleIndex {
+                path: Index::path(&installation_dir),
+            });
+        }
+    }
+
+    Ok(issues)
+}
+
+/// The name of the package installed in `path`, which is named `<package>` for the main
+/// installation and `<package>@<version>` for versions installed side by side
+fn installation_package_name(path: &Path) -> String {
+    let directory_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let package_name = match directory_name.rfind('@') {
+        Some(index) if index > 0 => &directory_name[..index],
+        _ => &directory_name,
+    };
+    package_name.replace("__", "/")
+}
+
+fn diagnose_shim(path: &Path, metadata: LatestMetadata, target_path: PathBuf) -> Option<Issue> {
+    let LatestMetadata {
+        binary_name,
+        package_name,
+        ..
+    } = metadata.clone();
+
+    if let Some(dir) = metadata.linked_dir() {
+        if !dir.exists() {
+            return Some(Issue::MissingWorkingCopy {
+                binary_name,
+                package_name,
+                path: dir,
+            });
+        }
+    } else if !target_path.exists() {
+        return Some(Issue::MissingInstallation {
+            binary_name,
+            package_name,
+            installation_path: target_path,
+        });
+    }
+
+    // binaries written before the node path was recorded run whatever their script says,
+    // and when even that is unknown there's nothing to check the node against
+    let node_path = pinned_node_path(path, &metadata);
+    match &node_path {
+        Some(node_path) if !node_path.exists() => {
+            return Some(Issue::MissingNode {
+                binary_name,
+                package_name,
+                node_version: metadata.node_version,
+                node_path: node_path.clone(),
+            })
+        }
+        Some(_) => {}
+        None => debug!("The node of {:?} is unknown", path),
+    }
+
+    if metadata.linked {
+        return None;
+    }
+
+    let binary_target_path = binary_target(
+        metadata.package_manager,
+        &target_path,
+        metadata.package_binary(),
+    );
+    if !binary_target_path.exists() {
+        return Some(Issue::MissingTarget {
+            binary_name,
+            package_name,
+            target_path: binary_target_path,
+        });
+    }
+
+    // the binary as gpkg would write it today, with the recorded metadata,
+    // so older metadata versions count as outdated but timestamps don't
+    let node_path = node_path?;
+    let expected = Binary::new(Metadata::V2(metadata), path, binary_target_path, node_path);
+    if !expected.is_up_to_date() {
+        return Some(Issue::OutdatedShim {
+            binary_name,
+            package_name,
+        });
+    }
+
+    None
+}
+
+/// Fixes the issues that can be fixed automatically, returning a description of every fix.
+///
+/// Packages on a missing node are rebound to an available node of the same version
+/// (or the current one), missing bin files are reinstalled, shims of missing packages
+/// are removed, and outdated scripts are regenerated.
+/// Installations without shims are only removed when `prune_orphans` is set, as their binaries
+/// may have lost a collision with another package on purpose.
+/// Packages are reinstalled from `registries`, unless they recorded where they came from,
+/// going through the tarball `cache` when there is one.
+pub fn repair<InstallationDir: AsRef<Path>, BinDir: AsRef<Path>>(
+    issues: &[Issue],
+    installation_dir: InstallationDir,
+    bin_dir: BinDir,
+    registries: &RegistryConfig,
+    cache: Option<&TarballCache>,
+    prune_orphans: bool,
+) -> Result<Vec<String>, Errors> {
+    let mut repaired = vec![];
+    let mut handled_packages = HashSet::new();
+    let mut handled_installations = HashSet::new();
+
+    for issue in issues {
+        match issue {
+            Issue::ForeignFile { .. } => {}
+            Issue::CorruptShim { path, .. } => {
+                remove_binary(path)?;
+                repaired.push(format!("Removed corrupt binary {:?}", path));
+            }
+            Issue::MissingNode {
+                package_name,
+                node_version,
+                ..
+            } => {
+                if !handled_packages.insert(package_name.clone()) {
+                    continue;
+                }
+                let node = NodeBinary::resolve(node_version).or_else(|_| NodeBinary::current())?;
+                rebind_package(package_name, &node, &installation_dir, &bin_dir)?;
+                repaired.push(format!(
+                    "Rebound {} to node {} ({})",
+                    package_name,
+                    node.version,
+                    node.path.display()
+                ));
+            }
+            Issue::MissingInstallation {
+                binary_name,
+                package_name,
+                ..
+            } => {
+                remove_binary(bin_dir.as_ref().join(binary_name))?;
+                repaired.push(format!(
+                    "Removed binary {} of missing package {}",
+                    binary_name, package_name
+                ));
+            }
+            Issue::MissingTarget {
+                binary_name,
+                package_name,
+                ..
+            } => {
+                let metadata = read_metadata(&bin_dir.as_ref().join(binary_name))?;
+                if !handled_installations.insert(metadata.installation_path(&installation_dir)) {
+                    continue;
+                }
+                reinstall(&metadata, &installation_dir, &bin_dir, registries, cache)?;
+                repaired.push(match &metadata.installation {
+                    Some(installation) => {
+                        format!("Reinstalled {} in {}", package_name, installation)
+                    }
+                    None => format!("Reinstalled {}", package_name),
+                });
+            }
+            Issue::MissingWorkingCopy { package_name, .. } => {
+                if !handled_packages.insert(package_name.clone()) {
+                    continue;
+                }
+                unlink_package(package_name, &installation_dir, &bin_dir)?;
+                repaired.push(format!("Unlinked {}", package_name));
+            }
+            Issue::OutdatedShim { binary_name, .. } => {
+                regenerate(binary_name, &installation_dir, &bin_dir)?;
+                repaired.push(format!("Regenerated the script of {}", binary_name));
+            }
+            Issue::OrphanInstallation { package_name, path } if prune_orphans => {
+                std::fs::remove_dir_all(path)?;
+                remove_manifest(path)?;
+                repaired.push(format!("Removed orphan installation of {}", package_name));
+            }
+            Issue::OrphanInstallation { .. } | Issue::StaleIndex { .. } => {}
+        }
+    }
+
+    // the fixes above don't all go through the index, so it's rebuilt once they're done
+    let index_was_stale = issues
+        .iter()
+        .any(|issue| matches!(issue, Issue::StaleIndex { .. }));
+    if index_was_stale || !repaired.is_empty() {
+        Index::rebuild(&installation_dir, &bin_dir)?;
+    }
+    if index_was_stale {
+        repaired.push("Rebuilt the index".to_string());
+    }
+
+    Ok(repaired)
+}
+
+/// The node binary a binary is pinned to: the recorded one, or the one its script runs
+fn pinned_node_path(script_path: &Path, metadata: &LatestMetadata) -> Option<PathBuf> {
+    metadata
+        .node_path
+        .clone()
+        .or_else(|| script_node_path(script_path))
+}
+
+fn read_metadata(script_path: &Path) -> std::io::Result<LatestMetadata> {
+    Ok(Metadata::read(script_path)
+        .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?
+        .latest())
+}
+
+/// Installs the same version of the package of a binary again, from where it came from.
+/// Versions installed side by side are installed again from their lockfiles,
+/// as upgrading only ever replaces the main installation.
+fn reinstall(
+    metadata: &LatestMetadata,
+    installation_dir: impl AsRef<Path>,
+    bin_dir: impl AsRef<Path>,
+    registries: &RegistryConfig,
+    cache: Option<&TarballCache>,
+) -> Result<(), Errors> {
+    let package_name = metadata.package_name.as_str();
+    if metadata.installation.is_some() {
+        let version_range = metadata
+            .package_version
+            .as_deref()
+            .and_then(VersionRange::parse);
+        reinstall_package(
+            package_name,
+            version_range.as_ref(),
+            installation_dir,
+            bin_dir,
+            registries,
+            cache,
+        )?;
+        return Ok(());
+    }
+    let spec = metadata
+        .source
+        .clone()
+        .or_else(|| installed_version(&installation_dir, package_name));
+    let requested: NodePackageVersion = match spec {
+        Some(spec) => format!("{}@{}", package_name, spec),
+        None => package_name.to_string(),
+    }
+    .parse()
+    .map_err(|_| Errors::PackageNotInstalled(package_name.to_string()))?;
+    let script_path = bin_dir.as_ref().join(&metadata.binary_name);
+    let node = match pinned_node_path(&script_path, metadata) {
+        Some(node_path) => NodeBinary::from_path(node_path)?,
+        None => NodeBinary::current()?,
+    };
+    debug!("Reinstalling {} using node {}", requested, node.version);
+    upgrade_package(
+        &requested,
+        &node,
+        installation_dir,
+        bin_dir,
+        None,
+        registries,
+        cache,
+    )
+}
+
+/// Writes the script of a binary again, from its metadata
+fn regenerate(
+    binary_name: &str,
+    installation_dir: impl AsRef<Path>,
+    bin_dir: impl AsRef<Path>,
+) -> Result<(), Errors> {
+    let script_path = bin_dir.as_ref().join(binary_name);
+    let metadata = read_metadata(&script_path)?;
+    let node = NodeBinary {
+        path: pinned_node_path(&script_path, &metadata).unwrap_or_default(),
+        version: metadata.node_version.clone(),
+    };
+    let installation = InstallationInfo::from_metadata(&metadata, &node);
+    let target_path = metadata.installation_path(installation_dir);
+    create_binary(binary_name, &installation, target_path, bin_dir)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install_package::{installation_path, versioned_installation_path};
+    use crate::package_manager::PackageManagerKind;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    struct Setup {
+        installation_dir: TempDir,
+        bin_dir: TempDir,
+    }
+
+    impl Setup {
+        fn new() -> Self {
+            Self {
+                installation_dir: TempDir::new("installations").unwrap(),
+                bin_dir: TempDir::new("bin").unwrap(),
+            }
+        }
+
+        /// Creates an installation of `package_name` with a single `bin/<binary>.js` file,
+        /// and its script
+        fn install(&self, package_name: &str, binary_name: &str, node_path: &str) {
+            let target_path = installation_path(self.installation_dir.path(), package_name);
+            let bin_dir = target_path.join("node_modules/.bin");
+            std::fs::create_dir_all(&bin_dir).unwrap();
+            std::fs::write(bin_dir.join(binary_name), "").unwrap();
+            let node = NodeBinary {
+                path: PathBuf::from(node_path),
+                version: "v14.0.0".to_string(),
+            };
+            let installation = InstallationInfo {
+                package_name,
+                node: &node,
+                package_manager: PackageManagerKind::Native,
+                package_version: Some("1.0.0".to_string()),
+                installation: None,
+                aliases: Default::default(),
+                requested: Some("latest".to_string()),
+                source: None,
+                registry: None,
+                integrity: None,
+            };
+            create_binary(binary_name, &installation, target_path, self.bin_dir.path()).unwrap();
+        }
+
+        fn diagnose(&self) -> Vec<Issue> {
+            diagnose(self.installation_dir.path(), self.bin_dir.path()).unwrap()
+        }
+
+        fn repair(&self, prune_orphans: bool) -> Vec<String> {
+            let issues = self.diagnose();
+            repair(
+                &issues,
+                self.installation_dir.path(),
+                self.bin_dir.path(),
+                &RegistryConfig::default(),
+                None,
+                prune_orphans,
+            )
+            .unwrap()
+        }
+    }
+
+    #[test]
+    fn healthy_installations_have_no_issues() {
+        let setup = Setup::new();
+        setup.install("qnm", "qnm", "/usr/bin/node");
+        // a version without binaries can still be switched to
+        std::fs::create_dir_all(versioned_installation_path(
+            setup.installation_dir.path(),
+            "qnm",
+            "0.9.0",
+        ))
+        .unwrap();
+        assert!(setup.diagnose().is_empty());
+    }
+
+    #[test]
+    fn finds_and_repairs_orphans() {
+        let setup = Setup::new();
+        setup.install("qnm", "qnm", "/usr/bin/node");
+        setup.install("@scope/tool", "tool", "/usr/bin/node");
+        std::fs::remove_dir_all(installation_path(setup.installation_dir.path(), "qnm")).unwrap();
+        std::fs::remove_file(setup.bin_dir.path().join("tool")).unwrap();
+        let versioned_path =
+            versioned_installation_path(setup.installation_dir.path(), "@scope/tool", "0.9.0");
+        std::fs::create_dir_all(&versioned_path).unwrap();
+        std::fs::write(setup.bin_dir.path().join("foreign"), "#!/bin/sh").unwrap();
+        let corrupt_path = setup.bin_dir.path().join("broken");
+        std::fs::write(&corrupt_path, "#!/bin/sh\n# metadata: e30=").unwrap();
+
+        let issues: Vec<String> = setup.diagnose().iter().map(ToString::to_string).collect();
+        assert_eq!(issues.len(), 5);
+        assert!(issues[0].ends_with("is a corrupt gpkg binary: its metadata is not valid: missing field `metadata_version` at line 1 column 2"));
+        assert!(issues[1].ends_with("was not created by gpkg"));
+        assert!(issues[2].starts_with("Binary \"qnm\" belongs to \"qnm\", which is not installed"));
+        assert!(issues[3].starts_with("Package \"@scope/tool\" is installed"));
+        assert!(issues[4].contains(&format!("{:?}", versioned_path)));
+
+        // installations are only removed when asked to
+        assert_eq!(
+            setup.repair(false),
+            vec![
+                format!("Removed corrupt binary {:?}", corrupt_path),
+                "Removed binary qnm of missing package qnm".to_string(),
+            ]
+        );
+        assert_eq!(setup.diagnose().len(), 3);
+        assert_eq!(
+            setup.repair(true),
+            vec![
+                "Removed orphan installation of @scope/tool".to_string(),
+                "Removed orphan installation of @scope/tool".to_string()
+            ]
+        );
+        assert_eq!(setup.diagnose().len(), 1);
+    }
+
+    #[test]
+    fn finds_missing_nodes_and_targets() {
+        let setup = Setup::new();
+        setup.install("qnm", "qnm", "/missing/node");
+        setup.install("tool", "tool", "/usr/bin/node");
+        let target = installation_path(setup.installation_dir.path(), "tool");
+        std::fs::remove_file(target.join("node_modules/.bin/tool")).unwrap();
+
+        let issues = setup.diagnose();
+        assert!(
+            matches!(&issues[0], Issue::MissingNode { package_name, .. } if package_name == "qnm")
+        );
+        assert!(
+            matches!(&issues[1], Issue::MissingTarget { package_name, .. } if package_name == "tool")
+        );
+    }
+
+    #[test]
+    fn reinstalls_versions_installed_side_by_side() {
+        use crate::install_package::{
+            install_package, package_version_in, BinaryNames, InstallOptions,
+        };
+        use crate::test_registry::{TestPackage, TestRegistry};
+        use std::str::FromStr;
+
+        let registry = TestRegistry::new();
+        let bin = [("tool", "#!/usr/bin/env node\nconsole.log('tool')")];
+        for version in ["1.0.0", "2.0.0"] {
+            registry.publish(TestPackage {
+                bin: &bin,
+                ..TestPackage::new("tool", version)
+            });
+        }
+        let setup = Setup::new();
+        let node = NodeBinary::current().expect("Can't find node");
+        for (requested, binary_names) in [
+            ("tool@2", BinaryNames::Unchanged),
+            ("tool@1", BinaryNames::As("tool1".to_string())),
+        ] {
+            install_package(
+                &NodePackageVersion::from_str(requested).unwrap(),
+                &node,
+                setup.installation_dir.path(),
+                setup.bin_dir.path(),
+                &InstallOptions {
+                    package_manager: PackageManagerKind::Native,
+                    binary_names,
+                    registries: registry.config(),
+                    ..InstallOptions::default()
+                },
+            )
+            .expect("Can't install");
+        }
+        let main_path = installation_path(setup.installation_dir.path(), "tool");
+        let versioned_path =
+            versioned_installation_path(setup.installation_dir.path(), "tool", "1.0.0");
+        let target = binary_target(PackageManagerKind::Native, &versioned_path, "tool");
+        std::fs::remove_file(&target).unwrap();
+        assert!(matches!(
+            &setup.diagnose()[..],
+            [Issue::MissingTarget { binary_name, .. }] if binary_name == "tool1"
+        ));
+
+        let issues = setup.diagnose();
+        let repaired = repair(
+            &issues,
+            setup.installation_dir.path(),
+            setup.bin_dir.path(),
+            &registry.config(),
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(repaired, vec!["Reinstalled tool in tool@1.0.0"]);
+        assert!(target.exists());
+        assert!(setup.diagnose().is_empty());
+        assert_eq!(
+            package_version_in(&versioned_path, "tool").as_deref(),
+            Some("1.0.0")
+        );
+        assert_eq!(
+            package_version_in(&main_path, "tool").as_deref(),
+            Some("2.0.0")
+        );
+    }
+
+    #[test]
+    fn reads_unrecorded_nodes_from_the_scripts() {
+        let setup = Setup::new();
+        setup.install("qnm", "qnm", "/usr/bin/node");
+        setup.install("tool", "tool", "/missing/node");
+        setup.install("other", "other", "/usr/bin/node");
+        for binary_name in ["qnm", "tool", "other"] {
+            let script_path = setup.bin_dir.path().join(binary_name);
+            let mut metadata = read_metadata(&script_path).unwrap();
+            metadata.node_path = None;
+            Metadata::V2(metadata).rewrite(&script_path).unwrap();
+        }
+        // a script whose node can't be told is left alone
+        let script_path = setup.bin_dir.path().join("other");
+        let script = std::fs::read_to_string(&script_path).unwrap();
+        let script: Vec<_> = script
+            .lines()
+            .filter(|line| !line.starts_with("export PATH="))
+            .collect();
+        std::fs::write(&script_path, script.join("\n")).unwrap();
+
+        let issues = setup.diagnose();
+        assert_eq!(issues.len(), 1);
+        assert!(matches!(
+            &issues[0],
+            Issue::MissingNode { package_name, node_path, .. }
+                if package_name == "tool" && node_path == Path::new("/missing/node")
+        ));
+    }
+
+    #[test]
+    fn regenerates_outdated_scripts() {
+        let setup = Setup::new();
+        setup.install("qnm", "qnm", "/usr/bin/node");
+        let script_path = setup.bin_dir.path().join("qnm");
+        let script = std::fs::read_to_string(&script_path).unwrap();
+        std::fs::write(&script_path, script.replace("\"$@\"", "$@")).unwrap();
+
+        assert!(matches!(
+            &setup.diagnose()[..],
+            [Issue::OutdatedShim { .. }]
+        ));
+        assert_eq!(setup.repair(false), vec!["Regenerated the script of qnm"]);
+        assert!(setup.diagnose().is_empty());
+        assert!(std::fs::read_to_string(&script_path)
+            .unwrap()
+            .contains("\"$@\""));
+    }
+
+    #[test]
+    fn rebuilds_stale_indexes() {
+        let setup = Setup::new();
+        setup.install("qnm", "qnm", "/usr/bin/node");
+        Index::rebuild(setup.installation_dir.path(), setup.bin_dir.path()).unwrap();
+        setup.install("typescript", "tsc", "/usr/bin/node");
+
+        assert!(matches!(&setup.diagnose()[..], [Issue::StaleIndex { .. }]));
+        assert_eq!(setup.repair(false), vec!["Rebuilt the index"]);
+        assert!(setup.diagnose().is_empty());
+    }
+}
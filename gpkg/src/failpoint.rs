@@ -0,0 +1,44 @@
+//! Failures tests can inject into the stages of multi-step operations,
+//! to check that they clean up after themselves
+
+#[cfg(test)]
+thread_local! {
+    static FAIL_AT: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Fails when a test asked the current thread to fail at `stage`, and does nothing otherwise
+pub(crate) fn failpoint(stage: &str) -> std::io::Result<()> {
+    #[cfg(test)]
+    if FAIL_AT.with(|fail_at| fail_at.borrow().as_deref() == Some(stage)) {
+        return Err(std::io::Error::other(format!(
+            "Injected failure at {}",
+            stage
+        )));
+    }
+    let _ = stage;
+    Ok(())
+}
+
+/// Makes the current thread fail at `stage`, or nowhere
+#[cfg(test)]
+pub(crate) fn fail_at(stage: Option<&str>) {
+    FAIL_AT.with(|fail_at| *fail_at.borrow_mut() = stage.map(str::to_string));
+}
+
+/// The contents of every file under `dir`, keyed by their paths,
+/// to compare a directory before and after a failed operation
+#[cfg(test)]
+pub(crate) fn snapshot(
+    dir: &std::path::Path,
+) -> std::collections::BTreeMap<std::path::PathBuf, Vec<u8>> {
+    let mut files = std::collections::BTreeMap::new();
+    for entry in dir.read_dir().unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(snapshot(&path));
+        } else {
+            files.insert(path.clone(), std::fs::read(&path).unwrap());
+        }
+    }
+    files
+}
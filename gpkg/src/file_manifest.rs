@@ -0,0 +1,349 @@
+use crate::index::Index;
+use crate::install_package::{package_installations, Installation};
+use crate::package_manager::PackageManagerKind;
+use log::*;
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// The directory next to the installations, and to the index, holding the hashes of their files.
+/// Manifests are kept outside the installations so they are never hashed or shipped with them.
+pub const MANIFESTS_DIR: &str = ".manifests";
+
+/// The file inside an installation where earlier versions of gpkg kept its manifest
+const LEGACY_MANIFEST_FILE: &str = ".gpkg-manifest.json";
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Errors {
+    #[error("Can't read {path:?}")]
+    #[diagnostic()]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("{path:?} has no file manifest")]
+    #[diagnostic(help(
+        "Installations made before gpkg recorded file hashes have none. \
+         Reinstall the package, or run `gpkg verify --update` to trust its current files"
+    ))]
+    MissingManifest { path: PathBuf },
+
+    #[error("Can't parse {path:?}")]
+    #[diagnostic()]
+    InvalidManifest {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+/// The hashes of the files of an installation, taken right after it was installed
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct FileManifest {
+    /// The lockfile written by the package manager, relative to the installation
+    #[serde(default)]
+    pub lockfile: Option<String>,
+    /// The `sha256-<hex>` hash of every file, keyed by its path relative to the installation.
+    /// Symbolic links are recorded as `symlink:<target>`.
+    pub files: BTreeMap<String, String>,
+}
+
+/// A difference between an installation and its [`FileManifest`]
+#[derive(Debug, Serialize, PartialEq, Eq, Clone)]
+#[serde(tag = "change", content = "path", rename_all = "lowercase")]
+pub enum FileChange {
+    Modified(String),
+    Added(String),
+    Missing(String),
+}
+
+impl FileChange {
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Modified(path) | Self::Added(path) | Self::Missing(path) => path,
+        }
+    }
+}
+
+impl FileManifest {
+    /// Hashes every file in `installation_path`,
+    /// except a manifest left there by an earlier version of gpkg
+    pub fn compute(installation_path: &Path) -> Result<Self, Errors> {
+        let mut files = BTreeMap::new();
+        hash_dir(installation_path, installation_path, &mut files)?;
+        files.remove(LEGACY_MANIFEST_FILE);
+        Ok(Self {
+            lockfile: None,
+            files,
+        })
+    }
+
+    /// Reads the manifest of `installation_path`,
+    /// falling back to the one an earlier version of gpkg kept inside it
+    pub fn read(installation_path: &Path) -> Result<Self, Errors> {
+        let paths = [
+            manifest_path(installation_path),
+            installation_path.join(LEGACY_MANIFEST_FILE),
+        ];
+        for path in paths {
+            let contents = match std::fs::read(&path) {
+                Ok(contents) => contents,
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(source) => return Err(Errors::Io { path, source }),
+            };
+            return serde_json::from_slice(&contents)
+                .map_err(|source| Errors::InvalidManifest { path, source });
+        }
+        Err(Errors::MissingManifest {
+            path: installation_path.to_path_buf(),
+        })
+    }
+
+    pub fn write(&self, installation_path: &Path) -> Result<(), Errors> {
+        let path = manifest_path(installation_path);
+        let contents = serde_json::to_string_pretty(self).expect("Can't serialize the manifest");
+        path.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&path, contents))
+            .map_err(|source| Errors::Io { path, source })?;
+
+        let legacy_path = installation_path.join(LEGACY_MANIFEST_FILE);
+        remove_file(&legacy_path).map_err(|source| Errors::Io {
+            path: legacy_path,
+            source,
+        })
+    }
+}
+
+/// Where the manifest of the installation at `installation_path` is kept
+pub fn manifest_path(installation_path: &Path) -> PathBuf {
+    let installation_name = installation_path.file_name().unwrap_or_default();
+    installation_path
+        .with_file_name(MANIFESTS_DIR)
+        .join(format!("{}.json", installation_name.to_string_lossy()))
+}
+
+/// Removes the manifest of `installation_path`, if it has one
+pub fn remove_manifest(installation_path: &Path) -> std::io::Result<()> {
+    remove_file(&manifest_path(installation_path))
+}
+
+fn remove_file(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+impl FileManifest {
+    /// How the files in `current` differ from the recorded ones, sorted by path
+    pub fn changes(&self, current: &FileManifest) -> Vec<FileChange> {
+        let mut changes = vec![];
+        for (path, hash) in &self.files {
+            match current.files.get(path) {
+                Some(current_hash) if current_hash != hash => {
+                    changes.push(FileChange::Modified(path.clone()))
+                }
+                Some(_) => {}
+                None => changes.push(FileChange::Missing(path.clone())),
+            }
+        }
+        for path in current.files.keys() {
+            if !self.files.contains_key(path) {
+                changes.push(FileChange::Added(path.clone()));
+            }
+        }
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        changes
+    }
+}
+
+/// Records the hashes of the files in a freshly installed `installation_path`,
+/// along with the lockfile `package_manager` wrote there
+pub fn record_manifest(
+    installation_path: &Path,
+    package_manager: PackageManagerKind,
+) -> Result<(), Errors> {
+    debug!("Recording the file manifest of {:?}", installation_path);
+    let mut manifest = FileManifest::compute(installation_path)?;
+    manifest.lockfile = package_manager
+        .lockfile_names()
+        .iter()
+        .find(|name| manifest.files.contains_key(**name))
+        .map(|name| name.to_string());
+    if manifest.lockfile.is_none() {
+        warn!(
+            "{} didn't write a lockfile into {:?}",
+            package_manager, installation_path
+        );
+    }
+    manifest.write(installation_path)
+}
+
+/// Re-hashes the files of `installation_path` and compares them with its manifest
+pub fn verify_installation(installation_path: &Path) -> Result<Vec<FileChange>, Errors> {
+    let recorded = FileManifest::read(installation_path)?;
+    let current = FileManifest::compute(installation_path)?;
+    Ok(recorded.changes(&current))
+}
+
+/// An installation of a package, as seen by `gpkg verify`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageInstallation {
+    pub package_name: String,
+    pub package_manager: PackageManagerKind,
+    pub installation: Installation,
+}
+
+/// Every installation of the installed packages, or only of `package_name`.
+/// Linked packages are skipped, as their files live in a working copy.
+pub fn installations_to_verify(
+    installation_dir: impl AsRef<Path>,
+    bin_dir: impl AsRef<Path>,
+    package_name: Option<&str>,
+) -> std::io::Result<Vec<PackageInstallation>> {
+    let packages: BTreeMap<_, _> = Index::load(&installation_dir, bin_dir)?
+        .into_binaries()
+        .into_iter()
+        .filter(|metadata| !metadata.linked)
+        .filter(|metadata| package_name.is_none_or(|name| name == metadata.package_name))
+        .map(|metadata| (metadata.package_name, metadata.package_manager))
+        .collect();
+
+    let mut installations = vec![];
+    for (package_name, package_manager) in packages {
+        for installation in package_installations(&installation_dir, &package_name)? {
+            installations.push(PackageInstallation {
+                package_name: package_name.clone(),
+                package_manager,
+                installation,
+            });
+        }
+    }
+    Ok(installations)
+}
+
+fn hash_dir(root: &Path, dir: &Path, files: &mut BTreeMap<String, String>) -> Result<(), Errors> {
+    let to_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| Errors::Io { path, source }
+    };
+    for entry in dir.read_dir().map_err(to_error(dir))? {
+        let path = entry.map_err(to_error(dir))?.path();
+        let file_type = path
+            .symlink_metadata()
+            .map_err(to_error(&path))?
+            .file_type();
+        if file_type.is_dir() {
+            hash_dir(root, &path, files)?;
+            continue;
+        }
+        let hash = if file_type.is_symlink() {
+            let target = path.read_link().map_err(to_error(&path))?;
+            format!("symlink:{}", target.to_string_lossy().replace('\\', "/"))
+        } else {
+            hash_file(&path)?
+        };
+        files.insert(relative_path(root, &path), hash);
+    }
+    Ok(())
+}
+
+/// The `sha256-<hex>` hash of the file at `path`, as recorded in a [`FileManifest`]
+pub fn hash_file(path: &Path) -> Result<String, Errors> {
+    let contents = std::fs::read(path).map_err(|source| Errors::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let digest: String = Sha256::digest(&contents)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok(format!("sha256-{}", digest))
+}
+
+/// `path` relative to `root`, with `/` separators on every platform
+fn relative_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    #[test]
+    fn reports_changed_files() {
+        let installation = TempDir::new("installation").unwrap();
+        let path = installation.path();
+        let package_dir = path.join("node_modules").join("tool");
+        std::fs::create_dir_all(&package_dir).unwrap();
+        std::fs::write(package_dir.join("cli.js"), "console.log('hi')").unwrap();
+        std::fs::write(package_dir.join("package.json"), "{}").unwrap();
+        std::fs::write(path.join("package-lock.json"), "{}").unwrap();
+
+        record_manifest(path, PackageManagerKind::Npm).unwrap();
+        let manifest = FileManifest::read(path).unwrap();
+        assert_eq!(manifest.lockfile.as_deref(), Some("package-lock.json"));
+        assert_eq!(manifest.files.len(), 3);
+        assert_eq!(verify_installation(path).unwrap(), vec![]);
+
+        std::fs::write(package_dir.join("cli.js"), "steal()").unwrap();
+        std::fs::remove_file(package_dir.join("package.json")).unwrap();
+        std::fs::write(package_dir.join("hook.js"), "steal()").unwrap();
+        assert_eq!(
+            verify_installation(path).unwrap(),
+            vec![
+                FileChange::Modified("node_modules/tool/cli.js".to_string()),
+                FileChange::Added("node_modules/tool/hook.js".to_string()),
+                FileChange::Missing("node_modules/tool/package.json".to_string()),
+            ]
+        );
+
+        remove_manifest(path).unwrap();
+        assert!(matches!(
+            verify_installation(path),
+            Err(Errors::MissingManifest { .. })
+        ));
+    }
+
+    #[test]
+    fn keeps_manifests_outside_installations() {
+        let installations = TempDir::new("installations").unwrap();
+        let path = installations.path().join("tool");
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("package-lock.json"), "{}").unwrap();
+        let legacy = FileManifest {
+            lockfile: Some("package-lock.json".to_string()),
+            files: FileManifest::compute(&path).unwrap().files,
+        };
+        std::fs::write(
+            path.join(LEGACY_MANIFEST_FILE),
+            serde_json::to_string(&legacy).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(FileManifest::read(&path).unwrap(), legacy);
+        assert_eq!(verify_installation(&path).unwrap(), vec![]);
+
+        record_manifest(&path, PackageManagerKind::Npm).unwrap();
+        assert_eq!(
+            manifest_path(&path),
+            installations.path().join(".manifests").join("tool.json")
+        );
+        assert!(manifest_path(&path).exists());
+        assert!(!path.join(LEGACY_MANIFEST_FILE).exists());
+        assert_eq!(FileManifest::read(&path).unwrap().files.len(), 1);
+        assert_eq!(verify_installation(&path).unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::storage::{binary_paths, BinEntry, LatestMetadata};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Bumped whenever the index format or [`LatestMetadata`] changes,
+/// so indexes written by older versions are rebuilt from the scripts
+const INDEX_VERSION: u32 = 3;
+
+/// The metadata of every binary in the bin directory, kept in `installations/index.json`
+/// so listing and looking up binaries doesn't need to parse every script.
+///
+/// The scripts still embed their metadata and remain the source of truth:
+/// a missing, corrupt or outdated index is rebuilt from them.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Index {
+    index_version: u32,
+    binaries: BTreeMap<String, LatestMetadata>,
+    /// Scripts written by gpkg whose metadata can't be read, with the reason
+    corrupt: BTreeMap<String, String>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Self {
+            index_version: INDEX_VERSION,
+            binaries: BTreeMap::new(),
+            corrupt: BTreeMap::new(),
+        }
+    }
+}
+
+impl Index {
+    pub fn path(installation_dir: impl AsRef<Path>) -> PathBuf {
+        installation_dir.as_ref().join("index.json")
+    }
+
+    /// Reads the index of `installation_dir`, rebuilding it from the scripts in `bin_dir`
+    /// when it's missing, unreadable or was written by another version of gpkg
+    pub fn load(
+        installation_dir: impl AsRef<Path>,
+        bin_dir: impl AsRef<Path>,
+    ) -> std::io::Result<Self> {
+        match Self::read(&installation_dir) {
+            Some(index) => Ok(index),
+            None => {
+                debug!("Rebuilding the index from {:?}", bin_dir.as_ref());
+                Self::rebuild(installation_dir, bin_dir)
+            }
+        }
+    }
+
+    /// Reads the index as it is on disk, without falling back to the scripts
+    pub fn read(installation_dir: impl AsRef<Path>) -> Option<Self> {
+        let path = Self::path(installation_dir);
+        let contents = std::fs::read(&path).ok()?;
+        match serde_json::from_slice::<Self>(&contents) {
+            Ok(index) if index.index_version == INDEX_VERSION => Some(index),
+            Ok(index) => {
+                debug!("Ignoring index version {}", index.index_version);
+                None
+            }
+            Err(error) => {
+                warn!("Ignoring corrupt index {:?}: {}", path, error);
+                None
+            }
+        }
+    }
+
+    /// Reads the metadata of every script in `bin_dir`, skipping files that aren't gpkg binaries
+    /// and recording the scripts that are corrupt
+    pub fn scan(bin_dir: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut index = Self::default();
+        if !bin_dir.as_ref().exists() {
+            return Ok(index);
+        }
+        for path in binary_paths(&bin_dir)? {
+            let binary_name = path.file_name().unwrap_or_default().to_string_lossy();
+            index.refresh(&bin_dir, &binary_name);
+        }
+        Ok(index)
+    }
+
+    /// Rebuilds the index from the scripts in `bin_dir` and saves it
+    pub fn rebuild(
+        installation_dir: impl AsRef<Path>,
+        bin_dir: impl AsRef<Path>,
+    ) -> std::io::Result<Self> {
+        let index = Self::scan(bin_dir)?;
+        index.save(installation_dir)?;
+        Ok(index)
+    }
+
+    /// Writes the index into a temporary file that is then renamed into place,
+    /// so an interrupted write never leaves a partial index behind
+    pub fn save(&self, installation_dir: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::create_dir_all(&installation_dir)?;
+        let mut file = tempfile::Builder::new()
+            .prefix(".index")
+            .tempfile_in(&installation_dir)?;
+        serde_json::to_writer_pretty(&mut file, self)?;
+        file.persist(Self::path(installation_dir))
+            .map_err(|error| error.error)?;
+        Ok(())
+    }
+
+    /// Re-reads the metadata of a single script, dropping the binary when its script is gone
+    fn refresh(&mut self, bin_dir: impl AsRef<Path>, binary_name: &str) {
+        self.binaries.remove(binary_name);
+        self.corrupt.remove(binary_name);
+        match BinEntry::read(bin_dir.as_ref().join(binary_name)) {
+            BinEntry::Binary(metadata) => {
+                self.binaries.insert(binary_name.to_string(), *metadata);
+            }
+            BinEntry::Foreign(_) => {}
+            BinEntry::Corrupt { path, reason } => {
+                warn!("{:?} is a corrupt gpkg binary: {}", path, reason);
+                self.corrupt.insert(binary_name.to_string(), reason);
+            }
+        }
+    }
+
+    pub fn get(&self, binary_name: &str) -> Option<&LatestMetadata> {
+        self.binaries.get(binary_name)
+    }
+
+    /// All the binaries, sorted by name
+    pub fn binaries(&self) -> impl Iterator<Item = &LatestMetadata> {
+        self.binaries.values()
+    }
+
+    /// The names of the corrupt scripts with the reason they can't be read, sorted by name
+    pub fn corrupt_binaries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.corrupt
+            .iter()
+            .map(|(binary_name, reason)| (binary_name.as_str(), reason.as_str()))
+    }
+
+    pub fn into_binaries(self) -> Vec<LatestMetadata> {
+        self.binaries.into_values().collect()
+    }
+
+    /// The binaries of a single package, sorted by name
+    pub fn package_binaries<'a>(
+        &'a self,
+        package_name: &'a str,
+    ) -> impl Iterator<Item = &'a LatestMetadata> {
+        self.binaries()
+            .filter(move |metadata| metadata.package_name == package_name)
+    }
+}
+
+/// Records the current state of the scripts of `binary_names` in the index:
+/// existing scripts are read again, and binaries whose script is gone are removed
+pub fn update_index<I: IntoIterator<Item = S>, S: AsRef<str>>(
+    installation_dir: impl AsRef<Path>,
+    bin_dir: impl AsRef<Path>,
+    binary_names: I,
+) -> std::io::Result<()> {
+    let mut index = Index::load(&installation_dir, &bin_dir)?;
+    for binary_name in binary_names {
+        index.refresh(&bin_dir, binary_name.as_ref());
+    }
+    index.save(installation_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::create_test_script;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    fn names<'a>(binaries: impl Iterator<Item = &'a LatestMetadata>) -> Vec<&'a str> {
+        binaries
+            .map(|metadata| metadata.binary_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn rebuilds_missing_and_corrupt_indexes() {
+        let installation_dir = TempDir::new("installations").unwrap();
+        let bin_dir = TempDir::new("bin").unwrap();
+        create_test_script(bin_dir.path(), "tsc", "typescript");
+        create_test_script(bin_dir.path(), "tsserver", "typescript");
+        std::fs::write(bin_dir.path().join("foreign"), "#!/bin/sh").unwrap();
+        std::fs::write(bin_dir.path().join("corrupt"), "# metadata: e30=").unwrap();
+
+        let index = Index::load(installation_dir.path(), bin_dir.path()).unwrap();
+        assert_eq!(names(index.binaries()), vec!["tsc", "tsserver"]);
+        assert_eq!(
+            index
+                .corrupt_binaries()
+                .map(|(binary_name, _)| binary_name)
+                .collect::<Vec<_>>(),
+            vec!["corrupt"]
+        );
+        assert_eq!(Index::read(installation_dir.path()), Some(index));
+
+        std::fs::write(Index::path(installation_dir.path()), "{").unwrap();
+        assert_eq!(Index::read(installation_dir.path()), None);
+        let index = Index::load(installation_dir.path(), bin_dir.path()).unwrap();
+        assert_eq!(names(index.package_binaries("typescript")).len(), 2);
+    }
+
+    #[test]
+    fn updates_changed_binaries() {
+        let installation_dir = TempDir::new("installations").unwrap();
+        let bin_dir = TempDir::new("bin").unwrap();
+        create_test_script(bin_dir.path(), "tsc", "typescript");
+        Index::rebuild(installation_dir.path(), bin_dir.path()).unwrap();
+
+        create_test_script(bin_dir.path(), "eslint", "eslint");
+        std::fs::remove_file(bin_dir.path().join("tsc")).unwrap();
+        // not scanned until asked to
+        let index = Index::load(installation_dir.path(), bin_dir.path()).unwrap();
+        assert_eq!(names(index.binaries()), vec!["tsc"]);
+
+        update_index(installation_dir.path(), bin_dir.path(), ["eslint", "tsc"]).unwrap();
+        let index = Index::load(installation_dir.path(), bin_dir.path()).unwrap();
+        assert_eq!(names(index.binaries()), vec!["eslint"]);
+        assert_eq!(
+            index
+                .get("eslint")
+                .map(|metadata| metadata.package_name.as_str()),
+            Some("eslint")
+        );
+    }
+}
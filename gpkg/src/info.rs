@@ -0,0 +1,279 @@
+use crate::binary::{binary_exists, Sidecar};
+use crate::index::Index;
+use crate::install_package::{self, binary_target, package_installations, InstalledPackage};
+use crate::package_json::PackageManifest;
+use crate::package_manager::PackageManagerKind;
+use crate::storage::{BinEntry, LatestMetadata};
+use crate::version_range::VersionRange;
+use miette::Diagnostic;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Errors {
+    #[error(transparent)]
+    #[diagnostic()]
+    IoError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Package(#[from] install_package::Errors),
+
+    #[error("Binary {0:?} is not installed")]
+    #[diagnostic(help("Run `gpkg list` to see the installed binaries"))]
+    BinaryNotInstalled(String),
+
+    #[error("File {0:?} was not created by gpkg")]
+    #[diagnostic()]
+    ForeignBinary(PathBuf),
+
+    #[error("{path:?} is a corrupt gpkg binary: {reason}")]
+    #[diagnostic(help("Run `gpkg doctor --repair` to remove it"))]
+    CorruptBinary { path: PathBuf, reason: String },
+}
+
+/// Where a binary in the bin directory leads
+#[derive(Debug, Serialize)]
+pub struct BinaryInfo {
+    pub binary_name: String,
+    /// The file in the bin directory
+    pub shim_path: PathBuf,
+    /// Whether the binary is a native shim, configured by a sidecar file
+    pub native_shim: bool,
+    /// The file the binary runs
+    pub target_path: Option<PathBuf>,
+    pub node_path: Option<PathBuf>,
+    pub node_version: String,
+    pub package_name: String,
+    pub package_version: Option<String>,
+    /// The installation (or working copy, for linked packages) the target is in
+    pub package_path: PathBuf,
+    pub linked: bool,
+}
+
+/// Describes the binary named `binary_name` in `bin_dir`
+pub fn binary_info(
+    installation_dir: impl AsRef<Path>,
+    bin_dir: impl AsRef<Path>,
+    binary_name: &str,
+) -> Result<BinaryInfo, Errors> {
+    let shim_path = bin_dir.as_ref().join(binary_name);
+    if !binary_exists(&shim_path) {
+        return Err(Errors::BinaryNotInstalled(binary_name.to_string()));
+    }
+    let metadata = match BinEntry::read(shim_path.clone()) {
+        BinEntry::Binary(metadata) => *metadata,
+        BinEntry::Foreign(path) => return Err(Errors::ForeignBinary(path)),
+        BinEntry::Corrupt { path, reason } => return Err(Errors::CorruptBinary { path, reason }),
+    };
+    let package_path = package_path(&installation_dir, &metadata);
+    let target_path = if metadata.linked {
+        InstalledPackage::read(&package_path)
+            .ok()
+            .and_then(|package| package.binaries().remove(metadata.package_binary()))
+            .map(|bin_path| package_path.join(bin_path))
+    } else {
+        Some(binary_target(
+            metadata.package_manager,
+            &package_path,
+            metadata.package_binary(),
+        ))
+    };
+    Ok(BinaryInfo {
+        binary_name: metadata.binary_name,
+        native_shim: Sidecar::read(&shim_path).is_ok(),
+        shim_path,
+        target_path,
+        node_path: metadata.node_path,
+        node_version: metadata.node_version,
+        package_name: metadata.package_name,
+        package_version: metadata.package_version,
+        package_path,
+        linked: metadata.linked,
+    })
+}
+
+/// What is known about an installed version of a package, from its `package.json` and binaries
+#[derive(Debug, Serialize)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    pub license: Option<String>,
+    /// The installation, or the working copy of a linked package
+    pub path: PathBuf,
+    pub linked: bool,
+    /// The binaries that run this version, sorted
+    pub binaries: Vec<String>,
+    /// The size of the installation, in bytes
+    pub disk_size: u64,
+    pub node_version: Option<String>,
+    pub package_manager: Option<PackageManagerKind>,
+    /// What was requested after `name@` when it was installed
+    pub requested: Option<String>,
+    pub source: Option<String>,
+    pub installed_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+/// Describes every installed version of `package_name`, or the ones in `version` when it's given
+pub fn package_info(
+    installation_dir: impl AsRef<Path>,
+    bin_dir: impl AsRef<Path>,
+    package_name: &str,
+    version: Option<&VersionRange>,
+) -> Result<Vec<PackageInfo>, Errors> {
+    let index = Index::load(&installation_dir, &bin_dir)?;
+    let binaries: Vec<_> = index.package_binaries(package_name).collect();
+
+    let mut paths: Vec<PathBuf> = package_installations(&installation_dir, package_name)?
+        .into_iter()
+        .filter(|installation| version.is_none_or(|range| installation.matches(range)))
+        .map(|installation| installation.path)
+        .collect();
+    if paths.is_empty() {
+        if let Some(dir) = binaries.iter().find_map(|metadata| metadata.linked_dir()) {
+            paths.push(dir);
+        }
+    }
+    if paths.is_empty() {
+        return Err(install_package::Errors::PackageNotInstalled(package_name.to_string()).into());
+    }
+
+    let mut infos = vec![];
+    for path in paths {
+        let path_binaries: Vec<_> = binaries
+            .iter()
+            .filter(|metadata| package_path(&installation_dir, metadata) == path)
+            .collect();
+        let linked = path_binaries.iter().any(|metadata| metadata.linked);
+        let package_dir = if linked {
+            path.clone()
+        } else {
+            path.join("node_modules").join(package_name)
+        };
+        let manifest = read_manifest(&package_dir);
+        // a version without binaries of its own has no metadata to tell how it was installed
+        let metadata = path_binaries.first();
+        infos.push(PackageInfo {
+            name: package_name.to_string(),
+            version: manifest.as_ref().map(|manifest| manifest.version.clone()),
+            description: manifest.as_ref().and_then(|m| m.description.clone()),
+            homepage: manifest.as_ref().and_then(|m| m.homepage.clone()),
+            license: manifest.as_ref().and_then(PackageManifest::license),
+            binaries: path_binaries
+                .iter()
+                .map(|metadata| metadata.binary_name.clone())
+                .collect(),
+            disk_size: directory_size(&path)?,
+            node_version: metadata.map(|metadata| metadata.node_version.clone()),
+            package_manager: metadata.map(|metadata| metadata.package_manager),
+            requested: metadata.and_then(|metadata| metadata.requested.clone()),
+            source: metadata.and_then(|metadata| metadata.source.clone()),
+            installed_at: metadata.and_then(|metadata| metadata.installed_at.clone()),
+            updated_at: metadata.and_then(|metadata| metadata.updated_at.clone()),
+            path,
+            linked,
+        });
+    }
+    Ok(infos)
+}
+
+/// The installation of a binary, or the working copy of a linked one
+fn package_path(installation_dir: impl AsRef<Path>, metadata: &LatestMetadata) -> PathBuf {
+    metadata
+        .linked_dir()
+        .unwrap_or_else(|| metadata.installation_path(installation_dir))
+}
+
+fn read_manifest(package_dir: &Path) -> Option<PackageManifest> {
+    let package_json = std::fs::read(package_dir.join("package.json")).ok()?;
+    serde_json::from_slice(&package_json).ok()
+}
+
+/// The total size of the files in `path`, without following symlinks
+fn directory_size(path: &Path) -> std::io::Result<u64> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in path.read_dir()? {
+        size += directory_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::update_index;
+    use crate::install_package::{create_binary, installation_path, InstallationInfo};
+    use crate::node::NodeBinary;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    #[test]
+    fn describes_binaries_and_packages() {
+        let installation_dir = TempDir::new("installations").unwrap();
+        let bin_dir = TempDir::new("bin").unwrap();
+        let path = installation_path(installation_dir.path(), "@acme/tool");
+        let package_dir = path.join("node_modules/@acme/tool");
+        std::fs::create_dir_all(&package_dir).unwrap();
+        let package_json = r#"{
+            "name": "@acme/tool",
+            "version": "1.2.0",
+            "description": "Does things",
+            "license": {"type": "MIT"},
+            "bin": {"tool": "cli.js"}
+        }"#;
+        std::fs::write(package_dir.join("package.json"), package_json).unwrap();
+        let node = NodeBinary {
+            path: PathBuf::from("/usr/bin/node"),
+            version: "v14.0.0".to_string(),
+        };
+        let installation = InstallationInfo {
+            package_name: "@acme/tool",
+            node: &node,
+            package_manager: PackageManagerKind::Npm,
+            package_version: Some("1.2.0".to_string()),
+            installation: None,
+            aliases: Default::default(),
+            requested: Some("^1".to_string()),
+            source: None,
+            registry: None,
+            integrity: None,
+        };
+        create_binary("tool", &installation, &path, bin_dir.path()).unwrap();
+        update_index(installation_dir.path(), bin_dir.path(), ["tool"]).unwrap();
+
+        let info = binary_info(installation_dir.path(), bin_dir.path(), "tool").unwrap();
+        assert_eq!(info.target_path, Some(path.join("node_modules/.bin/tool")));
+        assert_eq!(info.package_path, path);
+        assert_eq!(info.package_version.as_deref(), Some("1.2.0"));
+        assert!(!info.native_shim);
+        assert!(matches!(
+            binary_info(installation_dir.path(), bin_dir.path(), "missing"),
+            Err(Errors::BinaryNotInstalled(_))
+        ));
+
+        let infos =
+            package_info(installation_dir.path(), bin_dir.path(), "@acme/tool", None).unwrap();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].version.as_deref(), Some("1.2.0"));
+        assert_eq!(infos[0].description.as_deref(), Some("Does things"));
+        assert_eq!(infos[0].license.as_deref(), Some("MIT"));
+        assert_eq!(infos[0].binaries, vec!["tool"]);
+        assert_eq!(infos[0].requested.as_deref(), Some("^1"));
+        assert_eq!(infos[0].disk_size, package_json.len() as u64);
+        assert!(package_info(
+            installation_dir.path(),
+            bin_dir.path(),
+            "@acme/tool",
+            VersionRange::parse("2").as_ref()
+        )
+        .is_err());
+    }
+}
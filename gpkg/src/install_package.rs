@@ -1,41 +1,62 @@
-use crate::binary::Binary;
+use crate::binary::{Binary, StagedBinaries};
+use crate::collision::{resolve_collisions, CollisionPolicy};
 use crate::directory_portal::DirectoryPortal;
+use crate::failpoint::failpoint;
+use crate::file_manifest::{self, record_manifest};
+use crate::index::update_index;
+use crate::local_package::{self, LocalPackage};
+use crate::lockfile::{self, LockedInstallation};
+use crate::node::{self, NodeBinary};
 use crate::node_package_version::NodePackageVersion;
-use crate::npm;
-use crate::package_json::{PackageEngines, PackageRoot};
-use crate::storage::{LatestMetadata, Metadata};
+use crate::package_json::{PackageBinary, PackageEngines, PackageManifest, PackageRoot};
+use crate::package_manager::{self, PackageManagerKind};
+use crate::registry_config::{self, RegistryConfig};
+use crate::storage::{timestamp, LatestMetadata, Metadata};
+use crate::tarball_cache::TarballCache;
+use crate::version_range::VersionRange;
 use log::*;
 use miette::{Diagnostic, NamedSource, SourceOffset, SourceSpan};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::Path;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 #[derive(Serialize, Deserialize)]
 pub struct InstalledPackage {
     name: String,
+    #[serde(default)]
+    version: Option<String>,
     bin: PackageBinary,
 }
 
 impl InstalledPackage {
-    fn binaries(self) -> HashMap<String, String> {
-        match self.bin {
-            PackageBinary::Single(state) => {
-                let mut bin = HashMap::new();
-                bin.insert(self.name, state);
-                bin
+    /// Reads the `package.json` file of the package in `package_dir`
+    pub fn read(package_dir: impl AsRef<Path>) -> Result<Self, Errors> {
+        let package_json_path = package_dir.as_ref().join("package.json");
+        let package_json = std::fs::read_to_string(&package_json_path)?;
+        serde_json::from_str(&package_json).map_err(|error| {
+            let len = package_json.len();
+            let x = SourceOffset::from_location(&package_json, error.line(), error.column());
+            Errors::SerdeError {
+                json: NamedSource::new(package_json_path.display().to_string(), package_json),
+                snip: (0, len).into(),
+                highlight: (x.offset(), 1).into(),
+                error,
             }
-            PackageBinary::Multiple(state) => state,
-        }
+        })
     }
-}
 
-#[derive(Serialize, Deserialize)]
-#[serde(untagged)]
-enum PackageBinary {
-    Single(String),
-    Multiple(HashMap<String, String>),
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    pub(crate) fn binaries(&self) -> HashMap<String, String> {
+        self.bin.binaries(&self.name)
+    }
 }
 
 fn package_metadata_for_requested_package(
@@ -59,24 +80,29 @@ fn package_metadata_for_requested_package(
     }
 }
 
-fn infer_current_node_version() -> std::io::Result<String> {
-    let cmd = Command::new("node")
-        .arg("--version")
-        .stdout(Stdio::piped())
-        .output()?;
-    let version = std::str::from_utf8(&cmd.stdout)
-        .ok()
-        .ok_or(std::io::ErrorKind::UnexpectedEof)?
-        .trim()
-        .to_string();
-    Ok(version)
-}
-
 #[derive(Debug, Error, Diagnostic)]
 pub enum Errors {
     #[error(transparent)]
     #[diagnostic()]
     IoError(#[from] std::io::Error),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Node(#[from] node::Errors),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    PackageManager(#[from] package_manager::Errors),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    LocalPackage(#[from] local_package::Errors),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    RegistryConfig(#[from] registry_config::Errors),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    FileManifest(#[from] file_manifest::Errors),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Lockfile(#[from] lockfile::Errors),
     #[error("Can't parse package.json")]
     #[diagnostic(help("Please check what's going on in the package!"))]
     SerdeError {
@@ -88,112 +114,616 @@ pub enum Errors {
         #[highlight(snip, label = "here")]
         highlight: SourceSpan,
     },
-    #[diagnostic()]
+    #[diagnostic(help(
+        "Use `gpkg upgrade` to change its version, or `--as` or `--bin-suffix` to install another version side by side"
+    ))]
     #[error("Package {0:?} is already installed")]
     PackageAlreadyInstalled(String),
+    #[diagnostic(help("Use `--bin-suffix` to rename all of its binaries"))]
+    #[error(
+        "Package {package_name:?} has several binaries ({binary_names}), so `--as` can't name them"
+    )]
+    AmbiguousAlias {
+        package_name: String,
+        binary_names: String,
+    },
+    #[diagnostic(help("Install it first using `gpkg install`"))]
+    #[error("Package {0:?} is not installed")]
+    PackageNotInstalled(String),
+    #[diagnostic(help(
+        "Linked packages run their working copy. Use `gpkg unlink` to remove the link"
+    ))]
+    #[error("Package {0:?} is linked to a working copy")]
+    PackageLinked(String),
+    #[diagnostic(help("Link it first using `gpkg link` in its directory"))]
+    #[error("Package {0:?} is not linked")]
+    PackageNotLinked(String),
+    #[diagnostic(help(
+        "Use `--force` to overwrite it, or `--prefer <package>` to choose which package owns it"
+    ))]
+    #[error("Binary {binary_name:?} is already provided by package {owner:?}")]
+    BinaryCollision { binary_name: String, owner: String },
+    #[diagnostic(help("Remove the file or use `--force` to overwrite it"))]
+    #[error("File {path:?} already exists and is not managed by gpkg")]
+    BinaryExists { path: PathBuf },
+}
+
+/// The names the binaries of a package are exposed as in the bin directory
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum BinaryNames {
+    /// The names from the package
+    #[default]
+    Unchanged,
+    /// The only binary of the package, under another name
+    As(String),
+    /// Every binary of the package, with a suffix appended to its name
+    Suffix(String),
+}
+
+impl BinaryNames {
+    /// Maps the names the binaries of `installed_package` are exposed as to their names in the package
+    fn apply(
+        &self,
+        installed_package: &InstalledPackage,
+    ) -> Result<HashMap<String, String>, Errors> {
+        let binaries = installed_package.binaries();
+        match self {
+            Self::Unchanged => Ok(binaries
+                .into_keys()
+                .map(|binary_name| (binary_name.clone(), binary_name))
+                .collect()),
+            Self::As(alias) if binaries.len() == 1 => Ok(binaries
+                .into_keys()
+                .map(|binary_name| (alias.clone(), binary_name))
+                .collect()),
+            Self::As(_) => {
+                let mut binary_names: Vec<_> = binaries.into_keys().collect();
+                binary_names.sort();
+                Err(Errors::AmbiguousAlias {
+                    package_name: installed_package.name().to_string(),
+                    binary_names: binary_names.join(", "),
+                })
+            }
+            Self::Suffix(suffix) => Ok(binaries
+                .into_keys()
+                .map(|binary_name| (format!("{}{}", binary_name, suffix), binary_name))
+                .collect()),
+        }
+    }
+}
+
+/// How [`install_package`] installs a package
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    pub collision_policy: CollisionPolicy,
+    pub package_manager: PackageManagerKind,
+    pub binary_names: BinaryNames,
+    /// The registries packages are downloaded from
+    pub registries: RegistryConfig,
+    /// The cache the native installer keeps tarballs in, and installs offline from
+    pub cache: Option<TarballCache>,
+    /// Install exactly this dependency tree, using its package manager,
+    /// instead of resolving the requested package again
+    pub locked: Option<LockedInstallation>,
 }
 
+/// Installs a package, and creates its binaries in `bin_dir`.
+///
+/// With [`BinaryNames::Unchanged`] the package becomes the main installation,
+/// otherwise the installed version is kept side by side with the other versions
+/// of the package, in a directory of its own.
 pub fn install_package<InstallationDir: AsRef<Path>, BinDir: AsRef<Path>>(
     requested_package: &NodePackageVersion,
+    node: &NodeBinary,
     installation_dir: InstallationDir,
     bin_dir: BinDir,
+    options: &InstallOptions,
 ) -> Result<(), Errors> {
-    let node_version = infer_current_node_version()?;
-    debug!("Current node version: {}", node_version);
-    let node_binary_path = get_node_binary_location();
-    debug!(
-        "Current node binary path: {}",
-        node_binary_path.as_path().display()
-    );
-    let package = package_metadata_for_requested_package(
-        requested_package.name(),
-        requested_package.version(),
-        &node_version,
-    );
-    let package_json_contents = serde_json::to_string_pretty(&package).unwrap();
-    let target_path = installation_dir
+    let InstallOptions {
+        collision_policy,
+        package_manager,
+        binary_names,
+        registries,
+        cache,
+        locked,
+    } = options;
+    let package_manager = locked
         .as_ref()
-        .join(requested_package.name().replace("/", "__"));
-    if target_path.exists() {
+        .map_or(*package_manager, |locked| locked.package_manager);
+    let package_manager = package_manager_for(package_manager, cache.as_ref());
+    debug!("Using node {} from {}", node.version, node.path.display());
+    let (requested_package, local_package) = resolve_local_package(requested_package)?;
+    let requested_package = &requested_package;
+    let side_by_side = binary_names != &BinaryNames::Unchanged;
+    let target_path = installation_path(&installation_dir, requested_package.name());
+    if !side_by_side && target_path.exists() {
         return Err(Errors::PackageAlreadyInstalled(
             requested_package.name().to_string(),
         ));
     }
-    let portal = DirectoryPortal::new_in(std::env::temp_dir(), &target_path);
-    std::fs::write(portal.join("package.json"), package_json_contents)
-        .expect("Can't write package.json file");
+    // staged next to the installations, so teleporting it is a rename on the same filesystem
+    std::fs::create_dir_all(&installation_dir)?;
+    let portal = DirectoryPortal::new_in(&installation_dir, target_path);
+    let installed_package = match locked {
+        Some(locked) => prepare_locked_installation(
+            requested_package.name(),
+            locked,
+            node,
+            package_manager,
+            registries,
+            cache.as_ref(),
+            &portal,
+        )?,
+        None => prepare_installation(
+            requested_package,
+            local_package.as_ref(),
+            node,
+            package_manager,
+            registries,
+            cache.as_ref(),
+            &portal,
+        )?,
+    };
+    let portal = if side_by_side {
+        let version = installed_package.version().unwrap_or("0.0.0");
+        let target_path =
+            versioned_installation_path(&installation_dir, requested_package.name(), version);
+        if target_path.exists() {
+            return Err(Errors::PackageAlreadyInstalled(format!(
+                "{}@{}",
+                requested_package.name(),
+                version
+            )));
+        }
+        portal.with_target(target_path)
+    } else {
+        portal
+    };
+    let binaries = resolve_collisions(
+        requested_package.name(),
+        binary_names.apply(&installed_package)?,
+        &bin_dir,
+        collision_policy,
+    )?;
 
-    npm::install(&portal).expect("Can't access status code");
+    let mut installation = InstallationInfo::new(
+        requested_package,
+        &installed_package,
+        local_package.as_ref(),
+        node,
+        package_manager,
+        registries,
+    );
+    if side_by_side {
+        installation.installation = portal
+            .target()
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().to_string());
+        installation.aliases = binaries
+            .iter()
+            .filter(|(binary_name, package_binary)| binary_name != package_binary)
+            .map(|(binary_name, package_binary)| (binary_name.clone(), package_binary.clone()))
+            .collect();
+    }
+    let mut staged = StagedBinaries::new(&bin_dir)?;
+    let mut binary_names: Vec<_> = binaries.keys().collect();
+    binary_names.sort();
+    for binary_name in binary_names {
+        staged.stage(binary_script(
+            binary_name,
+            &installation,
+            portal.target(),
+            &bin_dir,
+        ))?;
+    }
 
-    let installed_package_json_path = portal
+    failpoint("teleport")?;
+    let teleport_path = portal.teleport()?;
+    let result = (|| -> Result<(), Errors> {
+        failpoint("manifest")?;
+        record_manifest(&teleport_path, package_manager)?;
+        staged.commit()?;
+        failpoint("index")?;
+        update_index(&installation_dir, &bin_dir, binaries.keys())?;
+        Ok(())
+    })();
+    if let Err(error) = result {
+        warn!("Install failed, rolling back: {}", error);
+        if let Err(rollback_error) = staged.rollback() {
+            warn!("Can't restore the binaries: {}", rollback_error);
+        }
+        if let Err(rollback_error) = std::fs::remove_dir_all(&teleport_path) {
+            warn!("Can't remove {:?}: {}", teleport_path, rollback_error);
+        }
+        if let Err(rollback_error) = file_manifest::remove_manifest(&teleport_path) {
+            warn!(
+                "Can't remove the manifest of {:?}: {}",
+                teleport_path, rollback_error
+            );
+        }
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+/// The package manager to install with: offline installs go through the native installer,
+/// the only one reading gpkg's tarball cache
+pub(crate) fn package_manager_for(
+    package_manager: PackageManagerKind,
+    cache: Option<&TarballCache>,
+) -> PackageManagerKind {
+    let is_offline = cache.is_some_and(TarballCache::is_offline);
+    if is_offline && package_manager != PackageManagerKind::Native {
+        debug!(
+            "Installing offline using the native installer instead of {}",
+            package_manager
+        );
+        PackageManagerKind::Native
+    } else {
+        package_manager
+    }
+}
+
+/// The directory in which a package named `package_name` is installed
+pub fn installation_path(installation_dir: impl AsRef<Path>, package_name: &str) -> PathBuf {
+    installation_dir
+        .as_ref()
+        .join(package_name.replace("/", "__"))
+}
+
+/// The directory in which `version` of a package is installed side by side with its other versions
+pub fn versioned_installation_path(
+    installation_dir: impl AsRef<Path>,
+    package_name: &str,
+    version: &str,
+) -> PathBuf {
+    installation_path(installation_dir, &format!("{}@{}", package_name, version))
+}
+
+/// An installed version of a package
+#[derive(Debug, Clone, PartialEq)]
+pub struct Installation {
+    pub path: PathBuf,
+    /// The version in its `package.json`
+    pub version: Option<String>,
+    /// Whether it's the main installation, named after the package,
+    /// as opposed to a version installed side by side
+    pub is_main: bool,
+}
+
+impl Installation {
+    /// Whether the installed version is in `range`
+    pub fn matches(&self, range: &VersionRange) -> bool {
+        self.semver().is_some_and(|version| range.matches(&version))
+    }
+
+    pub fn semver(&self) -> Option<semver::Version> {
+        self.version.as_deref()?.parse().ok()
+    }
+
+    /// What the binaries of the installation record as [`LatestMetadata::installation`]
+    pub fn directory_name(&self) -> Option<String> {
+        if self.is_main {
+            return None;
+        }
+        Some(self.path.file_name()?.to_string_lossy().to_string())
+    }
+}
+
+/// Every installation of a package, the main one and the versions installed side by side,
+/// sorted by directory
+pub fn package_installations(
+    installation_dir: impl AsRef<Path>,
+    package_name: &str,
+) -> std::io::Result<Vec<Installation>> {
+    let main_path = installation_path(&installation_dir, package_name);
+    let main_name = main_path.file_name().unwrap_or_default().to_string_lossy();
+    let versioned_prefix = format!("{}@", main_name);
+    let mut installations = vec![];
+    if !installation_dir.as_ref().exists() {
+        return Ok(installations);
+    }
+    for entry in installation_dir.as_ref().read_dir()? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let is_main = file_name == main_name;
+        if !is_main && !file_name.starts_with(&versioned_prefix) {
+            continue;
+        }
+        let path = entry.path();
+        installations.push(Installation {
+            version: package_version_in(&path, package_name),
+            path,
+            is_main,
+        });
+    }
+    installations.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(installations)
+}
+
+/// What the binaries of an installed package record about its installation
+pub(crate) struct InstallationInfo<'a> {
+    pub package_name: &'a str,
+    pub node: &'a NodeBinary,
+    pub package_manager: PackageManagerKind,
+    /// The version that was installed
+    pub package_version: Option<String>,
+    /// The directory of a version installed side by side, see [`LatestMetadata::installation`]
+    pub installation: Option<String>,
+    /// The names of the binaries in the package, keyed by the name they are exposed as,
+    /// for binaries that are exposed under another name
+    pub aliases: HashMap<String, String>,
+    /// What was requested after `name@`
+    pub requested: Option<String>,
+    /// Where the package was installed from, when it's not the registry
+    pub source: Option<String>,
+    /// The registry the package was downloaded from, when it was configured or gpkg talked to it directly
+    pub registry: Option<String>,
+    /// The content hash of a package installed from a local directory or tarball
+    pub integrity: Option<String>,
+}
+
+impl<'a> InstallationInfo<'a> {
+    pub(crate) fn new(
+        requested_package: &'a NodePackageVersion,
+        installed_package: &InstalledPackage,
+        local_package: Option<&LocalPackage>,
+        node: &'a NodeBinary,
+        package_manager: PackageManagerKind,
+        registries: &RegistryConfig,
+    ) -> Self {
+        let is_from_registry = requested_package.is_from_registry();
+        Self {
+            package_name: requested_package.name(),
+            node,
+            package_manager,
+            package_version: installed_package.version.clone(),
+            installation: None,
+            aliases: HashMap::new(),
+            requested: Some(requested_package.version()),
+            source: if is_from_registry {
+                None
+            } else {
+                Some(requested_package.version())
+            },
+            // other package managers fall back to their own configuration
+            registry: requested_package
+                .registry_name()
+                .and_then(|name| match package_manager {
+                    PackageManagerKind::Native => Some(registries.url(name)),
+                    _ => registries.configured_url(name),
+                })
+                .map(str::to_string),
+            integrity: local_package.map(|local_package| local_package.integrity.clone()),
+        }
+    }
+
+    /// The installation a binary with `metadata` belongs to
+    pub(crate) fn from_metadata(metadata: &'a LatestMetadata, node: &'a NodeBinary) -> Self {
+        Self {
+            package_name: &metadata.package_name,
+            node,
+            package_manager: metadata.package_manager,
+            package_version: metadata.package_version.clone(),
+            installation: metadata.installation.clone(),
+            aliases: metadata
+                .package_binary
+                .iter()
+                .map(|package_binary| (metadata.binary_name.clone(), package_binary.clone()))
+                .collect(),
+            requested: metadata.requested.clone(),
+            source: metadata.source.clone(),
+            registry: metadata.registry.clone(),
+            integrity: metadata.integrity.clone(),
+        }
+    }
+
+    /// The name in the package of the binary exposed as `binary_name`
+    pub(crate) fn package_binary<'b>(&'b self, binary_name: &'b str) -> &'b str {
+        self.aliases
+            .get(binary_name)
+            .map(String::as_str)
+            .unwrap_or(binary_name)
+    }
+}
+
+/// Reads the package of a local directory or tarball source, if `requested_package` is one.
+/// A name that was guessed from the path is replaced by the one in its `package.json`.
+pub(crate) fn resolve_local_package(
+    requested_package: &NodePackageVersion,
+) -> Result<(NodePackageVersion, Option<LocalPackage>), Errors> {
+    let local_package = LocalPackage::read(requested_package)?;
+    let requested_package = match &local_package {
+        Some(local_package) if requested_package.has_inferred_name() => requested_package
+            .clone()
+            .with_name(&local_package.manifest.name),
+        _ => requested_package.clone(),
+    };
+    Ok((requested_package, local_package))
+}
+
+/// The version of `package_name` installed in `installation_dir`, read from its `package.json`
+pub fn installed_version(installation_dir: impl AsRef<Path>, package_name: &str) -> Option<String> {
+    package_version_in(
+        installation_path(installation_dir, package_name),
+        package_name,
+    )
+}
+
+/// The version of `package_name` in the installation at `installation_path`
+pub fn package_version_in(
+    installation_path: impl AsRef<Path>,
+    package_name: &str,
+) -> Option<String> {
+    let package_json_path = installation_path
+        .as_ref()
         .join("node_modules")
-        .join(requested_package.name())
+        .join(package_name)
         .join("package.json");
-    let installed_package_json = std::fs::read_to_string(&installed_package_json_path)
-        .expect("Can't open package.json file");
-    let installed_package: InstalledPackage = serde_json::from_str(&installed_package_json)
-        .map_err(|error| {
-            let len = installed_package_json.len();
-            let x =
-                SourceOffset::from_location(&installed_package_json, error.line(), error.column());
-            Errors::SerdeError {
-                json: NamedSource::new(
-                    requested_package.name().to_string(),
-                    installed_package_json,
-                ),
-                snip: (0, len).into(),
-                highlight: (x.offset(), 1).into(),
-                error,
-            }
-        })?;
+    let package_json = std::fs::read(package_json_path).ok()?;
+    let manifest: PackageManifest = serde_json::from_slice(&package_json).ok()?;
+    Some(manifest.version)
+}
 
-    let teleport_path = portal.teleport()?;
+/// Writes the `package.json` for `requested_package` into `portal`, installs it
+/// and returns the parsed `package.json` of the installed package.
+///
+/// A `local_package` is copied into the portal and installed from there,
+/// so the installation doesn't depend on the original files.
+/// The configured `registries` are written into an `.npmrc` file next to the `package.json`.
+pub(crate) fn prepare_installation(
+    requested_package: &NodePackageVersion,
+    local_package: Option<&LocalPackage>,
+    node: &NodeBinary,
+    package_manager: PackageManagerKind,
+    registries: &RegistryConfig,
+    cache: Option<&TarballCache>,
+    portal: impl AsRef<Path>,
+) -> Result<InstalledPackage, Errors> {
+    let portal = portal.as_ref();
+    let version = match local_package {
+        Some(local_package) => {
+            let file_name = local_package.file_name();
+            std::fs::write(portal.join(&file_name), &local_package.tarball)?;
+            format!("file:./{}", file_name)
+        }
+        None => requested_package.version(),
+    };
+    let package =
+        package_metadata_for_requested_package(requested_package.name(), &version, &node.version);
+    let package_json_contents = serde_json::to_string_pretty(&package).unwrap();
+    std::fs::write(portal.join("package.json"), package_json_contents)?;
+    if !registries.is_empty() {
+        std::fs::write(portal.join(".npmrc"), registries.npmrc())?;
+    }
 
-    for binary_name in installed_package.binaries().keys() {
-        let metadata = Metadata::V1(LatestMetadata {
-            binary_name: binary_name.to_string(),
-            package_name: requested_package.name().to_string(),
-            node_version: node_version.to_string(),
-        });
-        let target_binary_path = teleport_path
-            .join("node_modules")
-            .join(".bin")
-            .join(binary_name);
-        let script_path = bin_dir.as_ref().join(binary_name);
-        let binary = Binary::new(
-            metadata,
-            &script_path,
-            &target_binary_path,
-            &node_binary_path,
-        );
-        binary.create_script().expect("Can't create script");
+    package_manager
+        .installer(registries, cache)
+        .install(portal, node)?;
+
+    let installed_package =
+        InstalledPackage::read(portal.join("node_modules").join(requested_package.name()))?;
+
+    Ok(installed_package)
+}
+
+/// Writes the `package.json` and the lockfile of `locked` into `portal`, installs exactly
+/// the dependency tree they describe and returns the parsed `package.json` of `package_name`
+pub(crate) fn prepare_locked_installation(
+    package_name: &str,
+    locked: &LockedInstallation,
+    node: &NodeBinary,
+    package_manager: PackageManagerKind,
+    registries: &RegistryConfig,
+    cache: Option<&TarballCache>,
+    portal: impl AsRef<Path>,
+) -> Result<InstalledPackage, Errors> {
+    let portal = portal.as_ref();
+    locked.write_into(portal)?;
+    if !registries.is_empty() {
+        std::fs::write(portal.join(".npmrc"), registries.npmrc())?;
+    }
+
+    package_manager
+        .installer(registries, cache)
+        .install_frozen(portal, node)?;
+
+    let installed_package = InstalledPackage::read(portal.join("node_modules").join(package_name))?;
+
+    Ok(installed_package)
+}
+
+/// Creates a script in `bin_dir` for every binary in `binaries`,
+/// pointing into the package installed at `installation_path`
+pub(crate) fn create_binaries(
+    installation: &InstallationInfo,
+    binaries: &HashMap<String, String>,
+    installation_path: impl AsRef<Path>,
+    bin_dir: impl AsRef<Path>,
+) -> std::io::Result<()> {
+    for binary_name in binaries.keys() {
+        failpoint(&format!("create {}", binary_name))?;
+        create_binary(binary_name, installation, &installation_path, &bin_dir)?;
     }
 
     Ok(())
 }
 
-// Still not sure whether to add `fnm exec {version} {node args}`
-// command in fnm, or to keep this hard Node binary string here
-fn get_node_binary_location() -> std::path::PathBuf {
-    let stdout = Command::new(if cfg!(windows) { "where" } else { "which" })
-        .arg("node")
-        .output()
-        .expect("Can't read output from 'which' command")
-        .stdout;
-    let location = std::str::from_utf8(&stdout)
-        .expect("Can't decode result from which command")
-        .split('\n')
-        .next()
-        .expect("Got an empty result")
-        .trim();
-    debug!("`which node` returned location {:?}", &location);
-    std::fs::canonicalize(location).expect("Can't canonicalize node path")
+/// Creates the script for a single binary of a package installed in `installation_path`
+pub(crate) fn create_binary(
+    binary_name: &str,
+    installation: &InstallationInfo,
+    installation_path: impl AsRef<Path>,
+    bin_dir: impl AsRef<Path>,
+) -> std::io::Result<PathBuf> {
+    binary_script(binary_name, installation, installation_path, bin_dir).create()
+}
+
+/// The script [`create_binary`] writes for a single binary.
+///
+/// When the script already exists for the same package, its installation time is kept.
+pub(crate) fn binary_script(
+    binary_name: &str,
+    installation: &InstallationInfo,
+    installation_path: impl AsRef<Path>,
+    bin_dir: impl AsRef<Path>,
+) -> Binary<PathBuf, PathBuf, PathBuf> {
+    let InstallationInfo {
+        package_name,
+        node,
+        package_manager,
+        ..
+    } = *installation;
+    let script_path = bin_dir.as_ref().join(binary_name);
+    let installed_at = Metadata::read(&script_path)
+        .ok()
+        .map(Metadata::latest)
+        .filter(|previous| previous.package_name == package_name)
+        .and_then(|previous| previous.installed_at);
+    let now = timestamp();
+    let metadata = Metadata::V2(LatestMetadata {
+        binary_name: binary_name.to_string(),
+        package_name: package_name.to_string(),
+        package_version: installation.package_version.clone(),
+        installation: installation.installation.clone(),
+        package_binary: installation.aliases.get(binary_name).cloned(),
+        requested: installation.requested.clone(),
+        source: installation.source.clone(),
+        registry: installation.registry.clone(),
+        integrity: installation.integrity.clone(),
+        node_version: node.version.to_string(),
+        node_path: Some(node.path.clone()),
+        package_manager,
+        linked: false,
+        installed_at: Some(installed_at.unwrap_or_else(|| now.clone())),
+        updated_at: Some(now),
+    });
+    let target_binary_path = binary_target(
+        package_manager,
+        installation_path,
+        installation.package_binary(binary_name),
+    );
+    Binary::new(metadata, script_path, target_binary_path, node.path.clone())
+}
+
+/// The file a binary of a package installed in `installation_path` runs
+pub(crate) fn binary_target(
+    package_manager: PackageManagerKind,
+    installation_path: impl AsRef<Path>,
+    binary_name: &str,
+) -> PathBuf {
+    package_manager
+        .package_manager()
+        .bin_dir(installation_path.as_ref())
+        .join(binary_name)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
+    use std::process::Command;
     use std::str::FromStr;
 
     #[test]
@@ -204,8 +734,16 @@ mod tests {
         let bin_dir = tempdir::TempDir::new("bin").unwrap();
         let package = NodePackageVersion::from_str("qnm@1.0.1").unwrap();
 
-        install_package(&package, installation_dir.path(), bin_dir.path())
-            .expect("Can't install qnm");
+        let node = NodeBinary::current().expect("Can't find node");
+
+        install_package(
+            &package,
+            &node,
+            installation_dir.path(),
+            bin_dir.path(),
+            &InstallOptions::default(),
+        )
+        .expect("Can't install qnm");
 
         let only_child = bin_dir
             .path()
@@ -224,4 +762,228 @@ mod tests {
             .trim();
         assert_eq!(version, "1.0.1");
     }
+
+    #[test]
+    fn renames_binaries() {
+        let package: InstalledPackage = serde_json::from_str(
+            r#"{"name": "typescript", "bin": {"tsc": "bin/tsc", "tsserver": "bin/tsserver"}}"#,
+        )
+        .unwrap();
+        let mut renamed: Vec<_> = BinaryNames::Suffix("4".to_string())
+            .apply(&package)
+            .unwrap()
+            .into_iter()
+            .collect();
+        renamed.sort();
+        assert_eq!(
+            renamed,
+            vec![
+                ("tsc4".to_string(), "tsc".to_string()),
+                ("tsserver4".to_string(), "tsserver".to_string())
+            ]
+        );
+        assert!(matches!(
+            BinaryNames::As("tsc4".to_string()).apply(&package),
+            Err(Errors::AmbiguousAlias { .. })
+        ));
+
+        let package: InstalledPackage =
+            serde_json::from_str(r#"{"name": "eslint", "bin": "bin/eslint.js"}"#).unwrap();
+        assert_eq!(
+            BinaryNames::As("eslint8".to_string())
+                .apply(&package)
+                .unwrap()
+                .get("eslint8")
+                .map(String::as_str),
+            Some("eslint")
+        );
+    }
+
+    #[test]
+    fn installs_local_directories() {
+        let source_dir = tempdir::TempDir::new("source").unwrap();
+        let package_dir = source_dir.path().join("tool");
+        std::fs::create_dir_all(package_dir.join("bin")).unwrap();
+        std::fs::write(
+            package_dir.join("package.json"),
+            r#"{"name": "@acme/tool", "version": "1.2.0", "bin": "bin/tool.js"}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            package_dir.join("bin/tool.js"),
+            "#!/usr/bin/env node\nconsole.log('local')",
+        )
+        .unwrap();
+        let installation_dir = tempdir::TempDir::new("installations").unwrap();
+        let bin_dir = tempdir::TempDir::new("bin").unwrap();
+        let package = NodePackageVersion::from_str(&package_dir.display().to_string()).unwrap();
+
+        let node = NodeBinary::current().expect("Can't find node");
+
+        install_package(
+            &package,
+            &node,
+            installation_dir.path(),
+            bin_dir.path(),
+            &InstallOptions {
+                package_manager: PackageManagerKind::Native,
+                ..InstallOptions::default()
+            },
+        )
+        .expect("Can't install the local package");
+        std::fs::write(package_dir.join("bin/tool.js"), "console.log('changed')").unwrap();
+
+        let script_path = bin_dir.path().join("tool");
+        let metadata = Metadata::read(&script_path).unwrap().latest();
+        assert_eq!(metadata.package_name, "@acme/tool");
+        assert_eq!(
+            metadata.source,
+            Some(format!("file:{}", package_dir.display()))
+        );
+        assert!(metadata.integrity.unwrap().starts_with("sha512-"));
+
+        let stdout = Command::new(&script_path)
+            .output()
+            .expect("Can't read output from command")
+            .stdout;
+        assert_eq!(std::str::from_utf8(&stdout).unwrap().trim(), "local");
+    }
+
+    #[test]
+    fn installs_from_configured_registries() {
+        use crate::file_manifest::{verify_installation, FileManifest};
+        use crate::registry_config::RegistryAuth;
+        use crate::test_registry::{TestPackage, TestRegistry};
+        use crate::upgrade_package::upgrade_package;
+
+        let registry = TestRegistry::new();
+        let bin = [("tool", "#!/usr/bin/env node\nconsole.log('tool')")];
+        for version in ["1.0.0", "1.1.0"] {
+            registry.publish(TestPackage {
+                bin: &bin,
+                ..TestPackage::new("@acme/tool", version)
+            });
+        }
+        let url = registry.path().display().to_string();
+        let mut registries = RegistryConfig::default();
+        registries.scopes.insert("@acme".to_string(), url.clone());
+        registries.registries.insert(
+            url.clone(),
+            RegistryAuth {
+                token_env: "GPKG_TEST_REGISTRY_TOKEN".to_string(),
+            },
+        );
+        registries.tokens.insert(
+            "GPKG_TEST_REGISTRY_TOKEN".to_string(),
+            "secret-token".to_string(),
+        );
+        let installation_dir = tempdir::TempDir::new("installations").unwrap();
+        let bin_dir = tempdir::TempDir::new("bin").unwrap();
+        let node = NodeBinary::current().expect("Can't find node");
+
+        install_package(
+            &NodePackageVersion::from_str("@acme/tool@1.0.0").unwrap(),
+            &node,
+            installation_dir.path(),
+            bin_dir.path(),
+            &InstallOptions {
+                package_manager: PackageManagerKind::Native,
+                registries,
+                ..InstallOptions::default()
+            },
+        )
+        .expect("Can't install from the scope registry");
+
+        let path = installation_path(installation_dir.path(), "@acme/tool");
+        let npmrc = std::fs::read_to_string(path.join(".npmrc")).unwrap();
+        assert!(npmrc.contains(&format!("@acme:registry={}/", url)));
+        assert!(npmrc.contains(":_authToken=${GPKG_TEST_REGISTRY_TOKEN}"));
+        let script_path = bin_dir.path().join("tool");
+        let script = std::fs::read_to_string(&script_path).unwrap();
+        assert!(!script.contains("secret-token") && !npmrc.contains("secret-token"));
+        let metadata = Metadata::read(&script_path).unwrap().latest();
+        assert_eq!(metadata.registry.as_deref(), Some(url.as_str()));
+
+        // the upgrade goes to the recorded registry, even though nothing is configured anymore
+        upgrade_package(
+            &NodePackageVersion::from_str("@acme/tool").unwrap(),
+            &node,
+            installation_dir.path(),
+            bin_dir.path(),
+            None,
+            &RegistryConfig::default(),
+            None,
+        )
+        .expect("Can't upgrade from the recorded registry");
+        assert_eq!(
+            installed_version(installation_dir.path(), "@acme/tool").as_deref(),
+            Some("1.1.0")
+        );
+        // both installs recorded the lockfile and the hashes of what they installed
+        let manifest = FileManifest::read(&path).unwrap();
+        assert_eq!(manifest.lockfile.as_deref(), Some("package-lock.json"));
+        assert_eq!(verify_installation(&path).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn rolls_back_failed_installs() {
+        use crate::failpoint::{fail_at, snapshot};
+        use crate::test_registry::{TestPackage, TestRegistry};
+
+        let registry = TestRegistry::new();
+        let other_bin = [("c", "#!/usr/bin/env node\nconsole.log('other')")];
+        let bin = [
+            ("a", "#!/usr/bin/env node\nconsole.log('a')"),
+            ("b", "#!/usr/bin/env node\nconsole.log('b')"),
+            ("c", "#!/usr/bin/env node\nconsole.log('c')"),
+            ("d", "#!/usr/bin/env node\nconsole.log('d')"),
+        ];
+        registry
+            .publish(TestPackage {
+                bin: &other_bin,
+                ..TestPackage::new("other", "1.0.0")
+            })
+            .publish(TestPackage {
+                bin: &bin,
+                ..TestPackage::new("tool", "1.0.0")
+            });
+        let installation_dir = tempdir::TempDir::new("installations").unwrap();
+        let bin_dir = tempdir::TempDir::new("bin").unwrap();
+        let node = NodeBinary::current().expect("Can't find node");
+        let install = |package: &str| {
+            install_package(
+                &NodePackageVersion::from_str(package).unwrap(),
+                &node,
+                installation_dir.path(),
+                bin_dir.path(),
+                &InstallOptions {
+                    collision_policy: CollisionPolicy::Force,
+                    package_manager: PackageManagerKind::Native,
+                    registries: registry.config(),
+                    ..InstallOptions::default()
+                },
+            )
+        };
+        install("other").expect("Can't install");
+        let installations = snapshot(installation_dir.path());
+        let binaries = snapshot(bin_dir.path());
+
+        for stage in ["stage c", "teleport", "manifest", "commit c", "index"] {
+            fail_at(Some(stage));
+            assert!(install("tool").is_err(), "{} didn't fail", stage);
+            assert_eq!(
+                snapshot(installation_dir.path()),
+                installations,
+                "{}",
+                stage
+            );
+            assert_eq!(snapshot(bin_dir.path()), binaries, "{}", stage);
+        }
+
+        fail_at(None);
+        install("tool").expect("Can't install after the failures");
+        let metadata = Metadata::read(bin_dir.path().join("c")).unwrap().latest();
+        assert_eq!(metadata.package_name, "tool");
+        assert!(bin_dir.path().join("d").exists());
+    }
 }
@@ -1,7 +1,33 @@
 mod binary;
+pub mod collision;
 pub mod directory_portal;
+pub mod doctor;
+mod failpoint;
+pub mod file_manifest;
+pub mod index;
+pub mod info;
 pub mod install_package;
+pub mod link_package;
+pub mod local_package;
+pub mod lockfile;
+pub mod migrate;
+pub mod native_installer;
+pub mod node;
 pub mod node_package_version;
-mod npm;
+pub mod outdated;
 mod package_json;
+pub mod package_manager;
+pub mod rebind_package;
+pub mod registry;
+pub mod registry_config;
+pub mod reinstall_package;
+pub mod run_package;
 pub mod storage;
+pub mod tarball_cache;
+#[cfg(test)]
+mod test_registry;
+pub mod toolset;
+pub mod uninstall_package;
+pub mod upgrade_package;
+pub mod use_package;
+pub mod version_range;
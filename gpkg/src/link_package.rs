@@ -0,0 +1,216 @@
+use crate::binary::{remove_binary, set_permissions, Binary};
+use crate::collision::{resolve_collisions, CollisionPolicy};
+use crate::index::{update_index, Index};
+use crate::install_package::{installation_path, Errors, InstalledPackage};
+use crate::node::NodeBinary;
+use crate::package_manager::PackageManagerKind;
+use crate::storage::{timestamp, LatestMetadata, Metadata};
+use log::*;
+use std::path::Path;
+
+/// A package whose binaries were linked into the bin directory
+#[derive(Debug)]
+pub struct LinkedPackage {
+    pub name: String,
+    pub binaries: Vec<String>,
+}
+
+/// Links the binaries of the package in `package_dir` into `bin_dir`, like `npm link`.
+///
+/// The scripts run the bin files of the working copy directly, pinned to `node`,
+/// so changes to the package are picked up without reinstalling it.
+pub fn link_package<InstallationDir: AsRef<Path>, BinDir: AsRef<Path>>(
+    package_dir: &Path,
+    node: &NodeBinary,
+    installation_dir: InstallationDir,
+    bin_dir: BinDir,
+    collision_policy: &CollisionPolicy,
+) -> Result<LinkedPackage, Errors> {
+    let package_dir = package_dir.canonicalize()?;
+    let package = InstalledPackage::read(&package_dir)?;
+    if installation_path(&installation_dir, package.name()).exists() {
+        return Err(Errors::PackageAlreadyInstalled(package.name().to_string()));
+    }
+
+    let binaries = resolve_collisions(
+        package.name(),
+        package.binaries(),
+        &bin_dir,
+        collision_policy,
+    )?;
+
+    // binaries that were removed from the package since it was last linked
+    let mut stale = vec![];
+    for metadata in Index::load(&installation_dir, &bin_dir)?.package_binaries(package.name()) {
+        if !binaries.contains_key(&metadata.binary_name) {
+            debug!("Removing stale link {}", metadata.binary_name);
+            remove_binary(bin_dir.as_ref().join(&metadata.binary_name))?;
+            stale.push(metadata.binary_name.clone());
+        }
+    }
+
+    let mut linked = vec![];
+    for (binary_name, binary_path) in binaries {
+        let target_path = package_dir.join(&binary_path);
+        debug!("Linking {} to {:?}", binary_name, target_path);
+        if target_path.exists() {
+            set_permissions(&target_path)?;
+        } else {
+            warn!("{:?} does not exist yet", target_path);
+        }
+        let now = timestamp();
+        let metadata = Metadata::V2(LatestMetadata {
+            binary_name: binary_name.clone(),
+            package_name: package.name().to_string(),
+            package_version: package.version().map(str::to_string),
+            installation: None,
+            package_binary: None,
+            requested: None,
+            source: Some(format!("file:{}", package_dir.display())),
+            registry: None,
+            integrity: None,
+            node_version: node.version.to_string(),
+            node_path: Some(node.path.clone()),
+            package_manager: PackageManagerKind::default(),
+            linked: true,
+            installed_at: Some(now.clone()),
+            updated_at: Some(now),
+        });
+        let script_path = bin_dir.as_ref().join(&binary_name);
+        Binary::new(metadata, script_path, &target_path, &node.path).create()?;
+        linked.push(binary_name);
+    }
+    linked.sort();
+    update_index(installation_dir, bin_dir, linked.iter().chain(&stale))?;
+
+    Ok(LinkedPackage {
+        name: package.name().to_string(),
+        binaries: linked,
+    })
+}
+
+/// Removes the binaries of a package linked with [`link_package`].
+/// Returns the names of the removed binaries.
+pub fn unlink_package<InstallationDir: AsRef<Path>, BinDir: AsRef<Path>>(
+    package_name: &str,
+    installation_dir: InstallationDir,
+    bin_dir: BinDir,
+) -> Result<Vec<String>, Errors> {
+    let linked: Vec<_> = Index::load(&installation_dir, &bin_dir)?
+        .into_binaries()
+        .into_iter()
+        .filter(|metadata| metadata.package_name == package_name)
+        .collect();
+    if linked.is_empty() || linked.iter().any(|metadata| !metadata.linked) {
+        return Err(Errors::PackageNotLinked(package_name.to_string()));
+    }
+
+    let mut removed = vec![];
+    for metadata in linked {
+        remove_binary(bin_dir.as_ref().join(&metadata.binary_name))?;
+        removed.push(metadata.binary_name);
+    }
+    update_index(installation_dir, bin_dir, &removed)?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::path::PathBuf;
+    use tempdir::TempDir;
+
+    fn node() -> NodeBinary {
+        NodeBinary {
+            path: PathBuf::from("/usr/bin/node"),
+            version: "v14.0.0".to_string(),
+        }
+    }
+
+    fn create_package(dir: &Path, bin: &str) {
+        std::fs::write(
+            dir.join("package.json"),
+            format!(
+                r#"{{"name": "my-cli", "version": "0.0.0", "bin": {}}}"#,
+                bin
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn links_and_unlinks_working_copies() {
+        let package_dir = TempDir::new("my-cli").unwrap();
+        let installation_dir = TempDir::new("installations").unwrap();
+        let bin_dir = TempDir::new("bin").unwrap();
+        create_package(
+            package_dir.path(),
+            r#"{"my-cli": "cli.js", "mc": "cli.js"}"#,
+        );
+        std::fs::write(package_dir.path().join("cli.js"), "").unwrap();
+
+        let linked = link_package(
+            package_dir.path(),
+            &node(),
+            installation_dir.path(),
+            bin_dir.path(),
+            &CollisionPolicy::Fail,
+        )
+        .unwrap();
+        assert_eq!(linked.binaries, vec!["mc", "my-cli"]);
+
+        let metadata = Metadata::read_all(bin_dir.path()).unwrap();
+        assert!(metadata.iter().all(|metadata| metadata.linked));
+        assert_eq!(
+            metadata[0].linked_dir(),
+            Some(package_dir.path().canonicalize().unwrap())
+        );
+        let script = std::fs::read_to_string(bin_dir.path().join("mc")).unwrap();
+        assert!(script.contains(&package_dir.path().join("cli.js").display().to_string()));
+
+        // relinking drops binaries that are no longer in the package
+        create_package(package_dir.path(), r#"{"mc": "cli.js"}"#);
+        link_package(
+            package_dir.path(),
+            &node(),
+            installation_dir.path(),
+            bin_dir.path(),
+            &CollisionPolicy::Fail,
+        )
+        .unwrap();
+        assert!(!bin_dir.path().join("my-cli").exists());
+
+        assert_eq!(
+            unlink_package("my-cli", installation_dir.path(), bin_dir.path()).unwrap(),
+            vec!["mc"]
+        );
+        assert!(Metadata::read_all(bin_dir.path()).unwrap().is_empty());
+        assert!(Index::load(installation_dir.path(), bin_dir.path())
+            .unwrap()
+            .into_binaries()
+            .is_empty());
+        assert!(matches!(
+            unlink_package("my-cli", installation_dir.path(), bin_dir.path()),
+            Err(Errors::PackageNotLinked(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_to_link_installed_packages() {
+        let package_dir = TempDir::new("my-cli").unwrap();
+        let installation_dir = TempDir::new("installations").unwrap();
+        let bin_dir = TempDir::new("bin").unwrap();
+        create_package(package_dir.path(), r#""cli.js""#);
+        std::fs::create_dir(installation_dir.path().join("my-cli")).unwrap();
+
+        let result = link_package(
+            package_dir.path(),
+            &node(),
+            installation_dir.path(),
+            bin_dir.path(),
+            &CollisionPolicy::Fail,
+        );
+        assert!(matches!(result, Err(Errors::PackageAlreadyInstalled(_))));
+    }
+}
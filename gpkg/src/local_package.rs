@@ -0,0 +1,552 @@
+use crate::node_package_version::{NodePackageVersion, PackageSource};
+use crate::package_json::PackageManifest;
+use crate::registry::integrity;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use miette::Diagnostic;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Files npm never packs, in `.gitignore` syntax
+const IGNORED_FILES: &[&str] = &[
+    "node_modules",
+    ".git",
+    ".svn",
+    ".hg",
+    "CVS",
+    ".npmignore",
+    ".gitignore",
+    ".npmrc",
+    ".DS_Store",
+    "._*",
+    ".*.swp",
+    "*.orig",
+    "npm-debug.log",
+    "/.lock-wscript",
+    "/.wafpickle-*",
+    "/build/config.gypi",
+    "/archived-packages/",
+    "/package-lock.json",
+    "/yarn.lock",
+    "/pnpm-lock.yaml",
+];
+
+/// Top-level files npm always packs besides `package.json`, whatever `files` and `.npmignore` say,
+/// by their name without an extension
+const ALWAYS_INCLUDED_FILES: &[&str] = &["readme", "copying", "license", "licence"];
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Errors {
+    #[error("Can't read {path:?}")]
+    #[diagnostic()]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("{path:?} doesn't contain a package.json file")]
+    #[diagnostic(help("Local packages need a package.json file with a name and a version"))]
+    MissingManifest { path: PathBuf },
+
+    #[error("Can't parse the package.json file of {path:?}")]
+    #[diagnostic()]
+    InvalidManifest {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("Can't parse the file patterns in {path:?}")]
+    #[diagnostic(help("`files` in package.json and `.npmignore` use the `.gitignore` syntax"))]
+    InvalidPatterns {
+        path: PathBuf,
+        #[source]
+        source: ignore::Error,
+    },
+}
+
+/// A package installed from a local directory or tarball.
+///
+/// Directories are packed into a tarball, so the installation is a copy
+/// that doesn't change when the working copy does.
+pub struct LocalPackage {
+    pub manifest: PackageManifest,
+    pub tarball: Vec<u8>,
+    /// The `sha512-<base64>` hash of `tarball`
+    pub integrity: String,
+}
+
+impl LocalPackage {
+    /// Reads the package of a local directory or tarball source.
+    /// Returns `None` for packages that come from anywhere else.
+    pub fn read(requested_package: &NodePackageVersion) -> Result<Option<Self>, Errors> {
+        let (path, tarball) = match requested_package.source() {
+            PackageSource::Directory(path) => (path.clone(), pack_directory(path)?),
+            PackageSource::Tarball(path) if !path.contains("://") => {
+                let path = PathBuf::from(path);
+                let tarball = std::fs::read(&path).map_err(|source| Errors::Io {
+                    path: path.clone(),
+                    source,
+                })?;
+                (path, tarball)
+            }
+            _ => return Ok(None),
+        };
+
+        let manifest = read_tarball_manifest(&path, &tarball)?;
+        Ok(Some(Self {
+            manifest,
+            integrity: integrity(&tarball),
+            tarball,
+        }))
+    }
+
+    /// The file name the tarball is stored as in an installation
+    pub fn file_name(&self) -> String {
+        format!(
+            "{}-{}.tgz",
+            self.manifest.name.trim_start_matches('@').replace('/', "-"),
+            self.manifest.version
+        )
+    }
+}
+
+/// Packs a directory into a gzipped npm tarball, with all files under `package/`.
+///
+/// Files are picked like `npm pack` does: by the `files` field of the `package.json`,
+/// and by the `.npmignore` files (or `.gitignore` without them) of every directory.
+///
+/// The tarball only depends on the file names, contents and executable bits,
+/// so packing the same directory twice gives the same bytes and the same hash.
+pub fn pack_directory(dir: &Path) -> Result<Vec<u8>, Errors> {
+    let to_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| Errors::Io { path, source }
+    };
+    let manifest_path = dir.join("package.json");
+    if !manifest_path.is_file() {
+        return Err(Errors::MissingManifest {
+            path: dir.to_path_buf(),
+        });
+    }
+    let manifest = std::fs::read(&manifest_path).map_err(to_error(&manifest_path))?;
+    let manifest: PackageManifest =
+        serde_json::from_slice(&manifest).map_err(|source| Errors::InvalidManifest {
+            path: dir.to_path_buf(),
+            source,
+        })?;
+
+    let rules = PackRules::new(dir, &manifest)?;
+    let mut files = vec![];
+    rules.collect_files(dir, &mut vec![], &mut files)?;
+    files.sort();
+
+    let encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for relative in files {
+        let path = dir.join(&relative);
+        let contents = std::fs::read(&path).map_err(to_error(&path))?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(if is_executable(&path) { 0o755 } else { 0o644 });
+        header.set_mtime(0);
+        header.set_cksum();
+        let entry_path = Path::new("package").join(&relative);
+        builder
+            .append_data(&mut header, entry_path, &contents[..])
+            .map_err(to_error(&path))?;
+    }
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(to_error(dir))
+}
+
+/// The rules picking the files `npm pack` puts into the tarball of a directory
+struct PackRules {
+    root: PathBuf,
+    /// The files npm never packs
+    ignored: Gitignore,
+    /// The `files` field of the `package.json`, when it has one
+    files: Option<Gitignore>,
+    /// The `main` and `bin` files, which are always packed
+    entry_points: Vec<PathBuf>,
+}
+
+impl PackRules {
+    fn new(root: &Path, manifest: &PackageManifest) -> Result<Self, Errors> {
+        let manifest_path = root.join("package.json");
+        let ignored = patterns(root, &manifest_path, IGNORED_FILES.iter().copied())?;
+        let files = match &manifest.files {
+            Some(files) => Some(patterns(
+                root,
+                &manifest_path,
+                files.iter().map(|file| file.trim_start_matches("./")),
+            )?),
+            None => None,
+        };
+        let bin_paths: Vec<String> = manifest
+            .bin
+            .as_ref()
+            .map(|bin| bin.binaries(&manifest.name).into_values().collect())
+            .unwrap_or_default();
+        let entry_points = manifest
+            .main
+            .iter()
+            .chain(&bin_paths)
+            .map(|path| normalize(path))
+            .collect();
+        Ok(Self {
+            root: root.to_path_buf(),
+            ignored,
+            files,
+            entry_points,
+        })
+    }
+
+    /// Collects the paths of the packed files in `dir`, relative to the root,
+    /// with `ignore_files` holding the ignore rules of the parent directories
+    fn collect_files(
+        &self,
+        dir: &Path,
+        ignore_files: &mut Vec<Gitignore>,
+        files: &mut Vec<PathBuf>,
+    ) -> Result<(), Errors> {
+        let to_error = |source| Errors::Io {
+            path: dir.to_path_buf(),
+            source,
+        };
+        // Like npm, the `files` field replaces the ignore file of the root directory
+        let has_ignore_file = dir != self.root || self.files.is_none();
+        let ignore_file = [".npmignore", ".gitignore"]
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file());
+        let ignore_file = match ignore_file {
+            Some(path) if has_ignore_file => Some(ignore_file_patterns(dir, &path)?),
+            _ => None,
+        };
+        let pushed = ignore_file.is_some();
+        ignore_files.extend(ignore_file);
+
+        let mut entries = dir
+            .read_dir()
+            .and_then(|entries| entries.collect::<std::io::Result<Vec<_>>>())
+            .map_err(to_error)?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let file_type = entry.file_type().map_err(to_error)?;
+            let path = entry.path();
+            let relative = path.strip_prefix(&self.root).unwrap_or(&path).to_path_buf();
+            if file_type.is_dir() {
+                let has_entry_point = self
+                    .entry_points
+                    .iter()
+                    .any(|entry| entry.starts_with(&relative));
+                if has_entry_point || !self.is_ignored(&path, true, ignore_files) {
+                    self.collect_files(&path, ignore_files, files)?;
+                }
+            } else if file_type.is_file()
+                && (self.is_always_included(&relative) || self.is_packed(&path, ignore_files))
+            {
+                files.push(relative);
+            }
+        }
+
+        if pushed {
+            ignore_files.pop();
+        }
+        Ok(())
+    }
+
+    fn is_always_included(&self, relative: &Path) -> bool {
+        let is_top_level = relative.components().count() == 1;
+        let name = relative.to_string_lossy().to_lowercase();
+        let stem = name.split('.').next().unwrap_or_default();
+        (is_top_level && (name == "package.json" || ALWAYS_INCLUDED_FILES.contains(&stem)))
+            || self.entry_points.iter().any(|entry| entry == relative)
+    }
+
+    fn is_packed(&self, path: &Path, ignore_files: &[Gitignore]) -> bool {
+        if self.is_ignored(path, false, ignore_files) {
+            return false;
+        }
+        match &self.files {
+            Some(files) => files.matched_path_or_any_parents(path, false).is_ignore(),
+            None => true,
+        }
+    }
+
+    /// Whether `path` or one of its directories is never packed,
+    /// or ignored by the closest ignore file with a rule for it
+    fn is_ignored(&self, path: &Path, is_dir: bool, ignore_files: &[Gitignore]) -> bool {
+        if self
+            .ignored
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+        {
+            return true;
+        }
+        let closest = ignore_files
+            .iter()
+            .rev()
+            .map(|ignore_file| ignore_file.matched_path_or_any_parents(path, is_dir))
+            .find(|matched| !matched.is_none());
+        matches!(closest, Some(Match::Ignore(_)))
+    }
+}
+
+/// Parses `.gitignore`-like `lines` from `source`, relative to `root`
+fn patterns<'a>(
+    root: &Path,
+    source: &Path,
+    lines: impl Iterator<Item = &'a str>,
+) -> Result<Gitignore, Errors> {
+    let to_error = |source_error| Errors::InvalidPatterns {
+        path: source.to_path_buf(),
+        source: source_error,
+    };
+    let mut builder = GitignoreBuilder::new(root);
+    for line in lines {
+        builder
+            .add_line(Some(source.to_path_buf()), line)
+            .map_err(to_error)?;
+    }
+    builder.build().map_err(to_error)
+}
+
+fn ignore_file_patterns(dir: &Path, path: &Path) -> Result<Gitignore, Errors> {
+    let contents = std::fs::read_to_string(path).map_err(|source| Errors::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    patterns(dir, path, contents.lines())
+}
+
+/// A relative path from `package.json`, like `./bin/tool.js`, as a path relative to the package
+fn normalize(path: &str) -> PathBuf {
+    Path::new(path)
+        .components()
+        .filter(|component| !matches!(component, std::path::Component::CurDir))
+        .collect()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    false
+}
+
+/// Reads the `package.json` file at the top level of a gzipped npm tarball
+fn read_tarball_manifest(path: &Path, tarball: &[u8]) -> Result<PackageManifest, Errors> {
+    let to_error = |source| Errors::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(tarball));
+    for entry in archive.entries().map_err(to_error)? {
+        let mut entry = entry.map_err(to_error)?;
+        let entry_path = entry.path().map_err(to_error)?.into_owned();
+        let is_manifest =
+            entry_path.components().count() == 2 && entry_path.ends_with("package.json");
+        if is_manifest {
+            let mut contents = vec![];
+            entry.read_to_end(&mut contents).map_err(to_error)?;
+            return serde_json::from_slice(&contents).map_err(|source| Errors::InvalidManifest {
+                path: path.to_path_buf(),
+                source,
+            });
+        }
+    }
+
+    Err(Errors::MissingManifest {
+        path: path.to_path_buf(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    fn create_package(dir: &Path) {
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        std::fs::create_dir_all(dir.join("node_modules/left-pad")).unwrap();
+        std::fs::write(
+            dir.join("package.json"),
+            r#"{"name": "@acme/tool", "version": "1.2.0", "bin": "bin/tool.js"}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("bin/tool.js"), "console.log('tool')").unwrap();
+        std::fs::write(dir.join("node_modules/left-pad/index.js"), "").unwrap();
+    }
+
+    fn entries(tarball: &[u8]) -> Vec<String> {
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(tarball));
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn packs_directories_deterministically() {
+        let dir = TempDir::new("local_package").unwrap();
+        create_package(dir.path());
+
+        let tarball = pack_directory(dir.path()).unwrap();
+        assert_eq!(
+            entries(&tarball),
+            vec!["package/bin/tool.js", "package/package.json"]
+        );
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(dir.path().join("bin/tool.js"), "console.log('tool')").unwrap();
+        assert_eq!(
+            integrity(&pack_directory(dir.path()).unwrap()),
+            integrity(&tarball)
+        );
+    }
+
+    fn write_files(dir: &Path, files: &[(&str, &str)]) {
+        for (path, contents) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+    }
+
+    #[test]
+    fn packs_like_npm_without_files() {
+        let dir = TempDir::new("local_package").unwrap();
+        write_files(
+            dir.path(),
+            &[
+                ("package.json", r#"{"name": "tool", "version": "1.0.0"}"#),
+                ("package-lock.json", "{}"),
+                (".npmrc", "//registry/:_authToken=secret"),
+                (".gitignore", "dist\n"),
+                (".npmignore", "*.log\ntest/\n"),
+                ("index.js", ""),
+                ("debug.log", ""),
+                ("dist/index.js", ""),
+                ("test/index.test.js", ""),
+                ("lib/.npmignore", "fixtures\n"),
+                ("lib/tool.js", ""),
+                ("lib/fixtures/big.json", ""),
+                ("lib/.gitignore", "*.js\n"),
+            ],
+        );
+
+        let tarball = pack_directory(dir.path()).unwrap();
+        assert_eq!(
+            entries(&tarball),
+            vec![
+                "package/dist/index.js",
+                "package/index.js",
+                "package/lib/tool.js",
+                "package/package.json",
+            ]
+        );
+
+        std::fs::remove_file(dir.path().join(".npmignore")).unwrap();
+        let tarball = pack_directory(dir.path()).unwrap();
+        assert_eq!(
+            entries(&tarball),
+            vec![
+                "package/debug.log",
+                "package/index.js",
+                "package/lib/tool.js",
+                "package/package.json",
+                "package/test/index.test.js",
+            ]
+        );
+    }
+
+    #[test]
+    fn packs_the_files_field_and_the_files_npm_always_packs() {
+        let dir = TempDir::new("local_package").unwrap();
+        write_files(
+            dir.path(),
+            &[
+                (
+                    "package.json",
+                    r#"{
+                        "name": "tool",
+                        "version": "1.0.0",
+                        "main": "./index.js",
+                        "bin": {"tool": "./scripts/tool.js"},
+                        "files": ["./lib", "types/*.d.ts", "!lib/*.test.js"]
+                    }"#,
+                ),
+                (".npmignore", "index.js\n"),
+                ("README.md", ""),
+                ("LICENSE", ""),
+                ("CONTRIBUTING.md", ""),
+                ("index.js", ""),
+                ("scripts/tool.js", ""),
+                ("scripts/release.js", ""),
+                ("lib/tool.js", ""),
+                ("lib/tool.test.js", ""),
+                ("lib/.DS_Store", ""),
+                ("types/tool.d.ts", ""),
+                ("types/tool.ts", ""),
+            ],
+        );
+
+        let tarball = pack_directory(dir.path()).unwrap();
+        assert_eq!(
+            entries(&tarball),
+            vec![
+                "package/LICENSE",
+                "package/README.md",
+                "package/index.js",
+                "package/lib/tool.js",
+                "package/package.json",
+                "package/scripts/tool.js",
+                "package/types/tool.d.ts",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_directories_and_tarballs() {
+        let dir = TempDir::new("local_package").unwrap();
+        let package_dir = dir.path().join("tool");
+        create_package(&package_dir);
+
+        let from_dir: NodePackageVersion = package_dir.display().to_string().parse().unwrap();
+        let from_dir = LocalPackage::read(&from_dir).unwrap().unwrap();
+        assert_eq!(from_dir.manifest.name, "@acme/tool");
+        assert_eq!(from_dir.file_name(), "acme-tool-1.2.0.tgz");
+
+        let tarball_path = dir.path().join("tool-1.2.0.tgz");
+        std::fs::write(&tarball_path, &from_dir.tarball).unwrap();
+        let from_tarball: NodePackageVersion = tarball_path.display().to_string().parse().unwrap();
+        let from_tarball = LocalPackage::read(&from_tarball).unwrap().unwrap();
+        assert_eq!(from_tarball.manifest.version, "1.2.0");
+        assert_eq!(from_tarball.integrity, from_dir.integrity);
+
+        let from_registry: NodePackageVersion = "@acme/tool@1.2.0".parse().unwrap();
+        assert!(LocalPackage::read(&from_registry).unwrap().is_none());
+    }
+
+    #[test]
+    fn fails_without_package_json() {
+        let dir = TempDir::new("local_package").unwrap();
+        let requested: NodePackageVersion = dir.path().display().to_string().parse().unwrap();
+        assert!(matches!(
+            LocalPackage::read(&requested),
+            Err(Errors::MissingManifest { .. })
+        ));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::file_manifest::{self, hash_file, FileManifest};
+use crate::install_package::package_version_in;
+use crate::package_manager::PackageManagerKind;
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Errors {
+    #[error("Can't read {path:?}")]
+    #[diagnostic()]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("{path:?} has no lockfile")]
+    #[diagnostic(help(
+        "Installations made before gpkg kept lockfiles can't be reinstalled exactly. \
+         Install the package again with `gpkg uninstall` and `gpkg install` to get one"
+    ))]
+    NoLockfile { path: PathBuf },
+
+    #[error("{file} in {path:?} changed since the package was installed")]
+    #[diagnostic(help("Run `gpkg verify` to see what else changed"))]
+    Modified { path: PathBuf, file: String },
+
+    #[error("{lockfile:?} is not a lockfile of {package_manager}")]
+    #[diagnostic(help(
+        "The lock names a file {} doesn't write. \
+         Check where the lock came from, and sync without `--frozen` to write a new one",
+        package_manager
+    ))]
+    UnknownLockfile {
+        lockfile: String,
+        package_manager: PackageManagerKind,
+    },
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    FileManifest(#[from] file_manifest::Errors),
+}
+
+/// What it takes to install exactly the same dependency tree again:
+/// the `package.json` gpkg wrote for an installation and the lockfile its package manager resolved
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LockedInstallation {
+    pub package_manager: PackageManagerKind,
+    /// The installed version of the package
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub package_json: String,
+    /// The file name of the lockfile, like `package-lock.json`
+    pub lockfile: String,
+    pub lockfile_contents: String,
+}
+
+impl LockedInstallation {
+    /// Reads the `package.json` and the lockfile kept in the installation of `package_name`,
+    /// making sure they weren't changed since it was installed
+    pub fn read(
+        installation_path: &Path,
+        package_name: &str,
+        package_manager: PackageManagerKind,
+    ) -> Result<Self, Errors> {
+        let manifest = match FileManifest::read(installation_path) {
+            Ok(manifest) => Some(manifest),
+            Err(file_manifest::Errors::MissingManifest { .. }) => None,
+            Err(error) => return Err(error.into()),
+        };
+        let lockfile = manifest
+            .as_ref()
+            .and_then(|manifest| manifest.lockfile.clone())
+            .or_else(|| {
+                package_manager
+                    .lockfile_names()
+                    .iter()
+                    .find(|name| installation_path.join(name).exists())
+                    .map(|name| name.to_string())
+            })
+            .ok_or_else(|| Errors::NoLockfile {
+                path: installation_path.to_path_buf(),
+            })?;
+
+        if let Some(manifest) = &manifest {
+            for file in ["package.json", lockfile.as_str()] {
+                let recorded = manifest.files.get(file);
+                let path = installation_path.join(file);
+                if recorded.is_some() && path.exists() && recorded != Some(&hash_file(&path)?) {
+                    return Err(Errors::Modified {
+                        path: installation_path.to_path_buf(),
+                        file: file.to_string(),
+                    });
+                }
+            }
+        }
+
+        let read = |file: &str| {
+            let path = installation_path.join(file);
+            std::fs::read_to_string(&path).map_err(|source| Errors::Io { path, source })
+        };
+        Ok(Self {
+            package_manager,
+            version: package_version_in(installation_path, package_name),
+            package_json: read("package.json")?,
+            lockfile_contents: read(&lockfile)?,
+            lockfile,
+        })
+    }
+
+    /// Writes the `package.json` and the lockfile into `dir`, ready for a frozen install.
+    /// Locks come from files that may be committed anywhere, so the lockfile has to be
+    /// one the package manager writes, and never a path leading elsewhere.
+    pub fn write_into(&self, dir: &Path) -> Result<(), Errors> {
+        let is_known = self
+            .package_manager
+            .lockfile_names()
+            .contains(&self.lockfile.as_str());
+        if !is_known {
+            return Err(Errors::UnknownLockfile {
+                lockfile: self.lockfile.clone(),
+                package_manager: self.package_manager,
+            });
+        }
+        let write = |file: &str, contents: &str| {
+            let path = dir.join(file);
+            std::fs::write(&path, contents).map_err(|source| Errors::Io { path, source })
+        };
+        write("package.json", &self.package_json)?;
+        write(&self.lockfile, &self.lockfile_contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manifest::record_manifest;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    #[test]
+    fn refuses_modified_lockfiles() {
+        let installation = TempDir::new("installation").unwrap();
+        let path = installation.path();
+        let package_dir = path.join("node_modules").join("tool");
+        std::fs::create_dir_all(&package_dir).unwrap();
+        std::fs::write(
+            package_dir.join("package.json"),
+            r#"{"name": "tool", "version": "1.2.0"}"#,
+        )
+        .unwrap();
+        std::fs::write(path.join("package.json"), r#"{"name": "x"}"#).unwrap();
+        std::fs::write(path.join("pnpm-lock.yaml"), "lockfileVersion: 5.4").unwrap();
+        record_manifest(path, PackageManagerKind::Pnpm).unwrap();
+
+        let locked = LockedInstallation::read(path, "tool", PackageManagerKind::Pnpm).unwrap();
+        assert_eq!(locked.lockfile, "pnpm-lock.yaml");
+        assert_eq!(locked.version.as_deref(), Some("1.2.0"));
+        let copy = TempDir::new("copy").unwrap();
+        locked.write_into(copy.path()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(copy.path().join("pnpm-lock.yaml")).unwrap(),
+            "lockfileVersion: 5.4"
+        );
+
+        let traversal = LockedInstallation {
+            lockfile: "../../.bashrc".to_string(),
+            ..locked.clone()
+        };
+        assert!(matches!(
+            traversal.write_into(&copy.path().join("nested")),
+            Err(Errors::UnknownLockfile { lockfile, .. }) if lockfile == "../../.bashrc"
+        ));
+        let other_package_manager = LockedInstallation {
+            lockfile: "package-lock.json".to_string(),
+            ..locked.clone()
+        };
+        assert!(other_package_manager.write_into(copy.path()).is_err());
+        assert!(!copy.path().join("package-lock.json").exists());
+
+        std::fs::write(path.join("pnpm-lock.yaml"), "lockfileVersion: 6.0").unwrap();
+        assert!(matches!(
+            LockedInstallation::read(path, "tool", PackageManagerKind::Pnpm),
+            Err(Errors::Modified { file, .. }) if file == "pnpm-lock.yaml"
+        ));
+        std::fs::remove_file(path.join("pnpm-lock.yaml")).unwrap();
+        file_manifest::remove_manifest(path).unwrap();
+        assert!(matches!(
+            LockedInstallation::read(path, "tool", PackageManagerKind::Pnpm),
+            Err(Errors::NoLockfile { .. })
+        ));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::index::update_index;
+use crate::install_package::{installed_version, InstalledPackage};
+use crate::storage::{binary_paths, script_node_path, Metadata};
+use log::*;
+use std::path::Path;
+
+/// Rewrites the metadata of every shim in `bin_dir` that uses an older metadata version.
+///
+/// Only the metadata line is replaced, so the scripts keep working as they did.
+/// The installed version is read from the installation (or the working copy of a linked package),
+/// and the node binary from the `PATH` the script sets when it was never recorded.
+/// Other fields that were never recorded are left empty.
+/// Returns the names of the migrated binaries.
+pub fn migrate<InstallationDir: AsRef<Path>, BinDir: AsRef<Path>>(
+    installation_dir: InstallationDir,
+    bin_dir: BinDir,
+) -> std::io::Result<Vec<String>> {
+    let mut migrated = vec![];
+    for path in binary_paths(&bin_dir)? {
+        let mut metadata = match Metadata::read(&path) {
+            Ok(metadata) if !metadata.is_latest() => metadata.latest(),
+            Ok(_) => continue,
+            Err(_) => {
+                debug!("Skipping {:?}, which is not a gpkg binary", path);
+                continue;
+            }
+        };
+
+        metadata.package_version = match metadata.linked_dir() {
+            Some(dir) => InstalledPackage::read(&dir)
+                .ok()
+                .and_then(|package| package.version().map(str::to_string)),
+            None => installed_version(&installation_dir, &metadata.package_name),
+        };
+        if metadata.node_path.is_none() {
+            metadata.node_path = script_node_path(&path);
+        }
+        debug!(
+            "Migrating {} of {}@{:?}",
+            metadata.binary_name, metadata.package_name, metadata.package_version
+        );
+        let binary_name = metadata.binary_name.clone();
+        Metadata::V2(metadata).rewrite(&path)?;
+        migrated.push(binary_name);
+    }
+    migrated.sort();
+    if !migrated.is_empty() {
+        update_index(installation_dir, bin_dir, &migrated)?;
+    }
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::Binary;
+    use crate::install_package::installation_path;
+    use crate::storage::MetadataV1;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    fn create_v1_script(bin_dir: &Path, installation_dir: &Path) {
+        let metadata = Metadata::V1(MetadataV1 {
+            binary_name: "qnm".to_string(),
+            package_name: "qnm".to_string(),
+            node_version: "v14.0.0".to_string(),
+            node_path: None,
+            package_manager: Default::default(),
+            source: None,
+            integrity: None,
+            linked: false,
+        });
+        let target_path = installation_path(installation_dir, "qnm").join("node_modules/.bin/qnm");
+        Binary::new(metadata, bin_dir.join("qnm"), target_path, "/usr/bin/node")
+            .create_script()
+            .unwrap();
+    }
+
+    #[test]
+    fn migrates_v1_scripts() {
+        let installation_dir = TempDir::new("installations").unwrap();
+        let bin_dir = TempDir::new("bin").unwrap();
+        let package_dir =
+            installation_path(installation_dir.path(), "qnm").join("node_modules/qnm");
+        std::fs::create_dir_all(&package_dir).unwrap();
+        std::fs::write(
+            package_dir.join("package.json"),
+            r#"{"name": "qnm", "version": "2.8.1", "bin": "bin/qnm"}"#,
+        )
+        .unwrap();
+        create_v1_script(bin_dir.path(), installation_dir.path());
+        std::fs::write(bin_dir.path().join("foreign"), "#!/bin/sh").unwrap();
+        let script_before = std::fs::read_to_string(bin_dir.path().join("qnm")).unwrap();
+
+        assert_eq!(
+            migrate(installation_dir.path(), bin_dir.path()).unwrap(),
+            vec!["qnm"]
+        );
+
+        let script = std::fs::read_to_string(bin_dir.path().join("qnm")).unwrap();
+        let metadata = Metadata::read(bin_dir.path().join("qnm")).unwrap();
+        assert!(metadata.is_latest());
+        let metadata = metadata.latest();
+        assert_eq!(metadata.package_version.as_deref(), Some("2.8.1"));
+        assert_eq!(metadata.node_version, "v14.0.0");
+        assert_eq!(metadata.node_path, Some("/usr/bin/node".into()));
+        assert_eq!(
+            script
+                .lines()
+                .filter(|line| !line.contains("# metadata:"))
+                .collect::<Vec<_>>(),
+            script_before
+                .lines()
+                .filter(|line| !line.contains("# metadata:"))
+                .collect::<Vec<_>>()
+        );
+
+        // migrating again is a no-op
+        assert!(migrate(installation_dir.path(), bin_dir.path())
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,1210 @@
+use crate::node::NodeBinary;
+use crate::node_package_version::{NodePackageVersion, PackageSource};
+use crate::package_json::{LockedPackage, PackageLock, PackageManifest, PackageRoot};
+use crate::package_manager::{Errors, PackageManager};
+use crate::registry::{self, Dist, PackumentVersion};
+use crate::registry_config::RegistryConfig;
+use crate::tarball_cache::TarballCache;
+use crate::version_range::VersionRange;
+use log::*;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Installs packages by talking to the registry directly, without spawning a package manager.
+///
+/// Dependencies are hoisted to the top-level `node_modules` when possible,
+/// and nested under the package that requires them when their version conflicts.
+/// Every package is fetched from the registry configured for its scope,
+/// going through the tarball cache when there is one.
+pub struct Native {
+    registries: RegistryConfig,
+    cache: Option<TarballCache>,
+}
+
+impl Native {
+    pub fn new(registries: RegistryConfig, cache: Option<TarballCache>) -> Self {
+        Self { registries, cache }
+    }
+
+    /// Installs the package locked at `key` of the lockfile in `lockfile_path` into `target`,
+    /// from its locked tarball, checked against its locked integrity
+    fn install_locked(
+        &self,
+        cwd: &Path,
+        key: &str,
+        locked: &LockedPackage,
+        target: &Path,
+        lockfile_path: &Path,
+    ) -> Result<InstalledDependency, Errors> {
+        let frozen_error = |reason: String| Errors::FrozenLockfile {
+            path: lockfile_path.to_path_buf(),
+            reason,
+        };
+        let name = match &locked.name {
+            Some(name) => name.as_str(),
+            None => key.rsplit("node_modules/").next().unwrap_or(key),
+        };
+        let (version, resolved, integrity) =
+            match (&locked.version, &locked.resolved, &locked.integrity) {
+                (Some(version), Some(resolved), Some(integrity)) => (version, resolved, integrity),
+                _ => {
+                    return Err(frozen_error(format!(
+                        "{} has no locked version, tarball or integrity",
+                        key
+                    )))
+                }
+            };
+        let tarball = match local_tarball(resolved) {
+            Some(path) => {
+                let tarball = read(&local_tarball_path(cwd, path)?)?;
+                if &registry::integrity(&tarball) != integrity {
+                    return Err(frozen_error(format!(
+                        "{} doesn't match the integrity of {}",
+                        path, key
+                    )));
+                }
+                tarball
+            }
+            None => {
+                let registry = self.registries.registry(name);
+                let version = PackumentVersion {
+                    name: name.to_string(),
+                    version: version.clone(),
+                    dependencies: HashMap::new(),
+                    os: locked.os.clone(),
+                    cpu: locked.cpu.clone(),
+                    dist: Dist {
+                        tarball: resolved.clone(),
+                        integrity: Some(integrity.clone()),
+                        shasum: None,
+                    },
+                };
+                match &self.cache {
+                    Some(cache) => cache.tarball(&registry, &version)?,
+                    None => registry.tarball(&version)?,
+                }
+            }
+        };
+
+        extract_tarball(&tarball, target)?;
+        let manifest = read_manifest(target)?;
+        if &manifest.version != version {
+            return Err(frozen_error(format!(
+                "{} is locked to {}, but its tarball has version {}",
+                key, version, manifest.version
+            )));
+        }
+        link_binaries(target, &manifest)?;
+        Ok(InstalledDependency {
+            path: target.to_path_buf(),
+            manifest,
+            resolved: Some(resolved.clone()),
+            integrity: Some(integrity.clone()),
+            optional: locked.optional,
+        })
+    }
+}
+
+impl PackageManager for Native {
+    fn install(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        let package_json_path = cwd.join("package.json");
+        let package_json = read(&package_json_path)?;
+        let root: PackageRoot =
+            serde_json::from_slice(&package_json).map_err(|source| Errors::InvalidManifest {
+                path: package_json_path,
+                source,
+            })?;
+
+        let mut tree = Tree::new(cwd, self.cache.as_ref());
+        tree.install_all(&self.registries, &root.dependencies)?;
+        write_lockfile(cwd, &root, &tree.installed)?;
+        // dependencies are built before the packages that depend on them
+        for package in tree.installed.iter().rev() {
+            run_install_scripts(cwd, package, node)?;
+        }
+        Ok(())
+    }
+
+    /// Installs every package of the `package-lock.json` at its locked path,
+    /// from its locked tarball, checked against its locked integrity
+    fn install_frozen(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        let package_json_path = cwd.join("package.json");
+        let root: PackageRoot =
+            serde_json::from_slice(&read(&package_json_path)?).map_err(|source| {
+                Errors::InvalidManifest {
+                    path: package_json_path,
+                    source,
+                }
+            })?;
+        let lockfile_path = cwd.join("package-lock.json");
+        let lockfile: PackageLock =
+            serde_json::from_slice(&read(&lockfile_path)?).map_err(|source| {
+                Errors::InvalidManifest {
+                    path: lockfile_path.clone(),
+                    source,
+                }
+            })?;
+        let frozen_error = |reason: String| Errors::FrozenLockfile {
+            path: lockfile_path.clone(),
+            reason,
+        };
+
+        let dependencies: BTreeMap<_, _> = root.dependencies.into_iter().collect();
+        let locked_dependencies = lockfile.packages.get("").map(|root| &root.dependencies);
+        if locked_dependencies != Some(&dependencies) {
+            return Err(frozen_error(
+                "its dependencies differ from the ones in package.json".to_string(),
+            ));
+        }
+
+        let mut installed = vec![];
+        // parents sort before the packages nested in their `node_modules`
+        for (key, locked) in lockfile.packages.iter().filter(|(key, _)| !key.is_empty()) {
+            if locked.optional && !supports_platform(&locked.os, &locked.cpu) {
+                debug!(
+                    "Skipping optional {}, which doesn't support this platform",
+                    key
+                );
+                continue;
+            }
+            if !is_node_modules_path(key) {
+                return Err(frozen_error(format!(
+                    "{:?} is not a path inside node_modules",
+                    key
+                )));
+            }
+            let target = cwd.join(key);
+            let package = match self.install_locked(cwd, key, locked, &target, &lockfile_path) {
+                Ok(package) => package,
+                Err(error) if locked.optional => {
+                    warn!("Skipping optional dependency {}: {}", key, error);
+                    if target.exists() {
+                        std::fs::remove_dir_all(&target).map_err(|source| Errors::Io {
+                            path: target.clone(),
+                            source,
+                        })?;
+                    }
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            let is_direct = key
+                .strip_prefix("node_modules/")
+                .is_some_and(|name| dependencies.contains_key(name));
+            let depth = key.matches("node_modules/").count();
+            installed.push((is_direct, depth, package));
+        }
+
+        // dependencies are built before the packages that depend on them: nested packages first,
+        // then the hoisted ones and the ones required by package.json last
+        installed.sort_by_key(|(is_direct, depth, _)| (*is_direct, std::cmp::Reverse(*depth)));
+        for (_, _, package) in &installed {
+            run_install_scripts(cwd, package, node)?;
+        }
+        Ok(())
+    }
+
+    fn rebuild(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        for package in installed_packages(&cwd.join("node_modules"))? {
+            run_install_scripts(cwd, &package, node)?;
+        }
+        Ok(())
+    }
+}
+
+/// A package that was extracted into a `node_modules` directory
+struct InstalledDependency {
+    path: PathBuf,
+    manifest: PackageManifest,
+    /// Where the tarball came from, unknown for packages found on disk
+    resolved: Option<String>,
+    integrity: Option<String>,
+    /// Whether the package is only required as an optional dependency
+    optional: bool,
+}
+
+/// The `node_modules` tree being installed
+struct Tree<'a> {
+    /// The directory of the `package.json` file, which local tarballs are relative to
+    cwd: PathBuf,
+    root: PathBuf,
+    installed: Vec<InstalledDependency>,
+    /// The version installed in every package directory
+    versions: HashMap<PathBuf, semver::Version>,
+    cache: Option<&'a TarballCache>,
+}
+
+impl<'a> Tree<'a> {
+    fn new(cwd: &Path, cache: Option<&'a TarballCache>) -> Self {
+        Self {
+            cwd: cwd.to_path_buf(),
+            root: cwd.join("node_modules"),
+            installed: vec![],
+            versions: HashMap::new(),
+            cache,
+        }
+    }
+
+    /// Installs the given dependencies and all of their transitive dependencies.
+    ///
+    /// The tree is installed breadth first, so shallower dependencies get the top-level
+    /// spots and a conflicting version is nested under the package that requires it.
+    /// Optional dependencies, and everything they depend on, are skipped when they can't be
+    /// installed, like platform specific binaries on other platforms.
+    fn install_all(
+        &mut self,
+        registries: &RegistryConfig,
+        dependencies: &HashMap<String, String>,
+    ) -> Result<(), Errors> {
+        let mut queue: VecDeque<(String, String, Vec<PathBuf>, bool)> = dependencies
+            .iter()
+            .map(|(name, spec)| (name.clone(), spec.clone(), vec![], false))
+            .collect();
+
+        while let Some((name, spec, ancestors, optional)) = queue.pop_front() {
+            let result = self
+                .fetch(registries, &name, &spec, &ancestors)
+                .and_then(|fetched| match fetched {
+                    Some((target, tarball, resolved)) => {
+                        let dependencies = self.unpack(&tarball, &target, &resolved, optional);
+                        if dependencies.is_err() && target.exists() {
+                            std::fs::remove_dir_all(&target).map_err(|source| Errors::Io {
+                                path: target.clone(),
+                                source,
+                            })?;
+                        }
+                        Ok(Some((target, dependencies?)))
+                    }
+                    None => Ok(None),
+                });
+            let (target, dependencies) = match result {
+                Ok(Some(installed)) => installed,
+                Ok(None) => continue,
+                Err(error) if optional => {
+                    match error {
+                        Errors::UnsupportedPlatform { .. } => debug!("{}", error),
+                        _ => warn!("Skipping optional dependency {}@{}: {}", name, spec, error),
+                    }
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            let mut ancestors = ancestors;
+            ancestors.push(target.join("node_modules"));
+            for (dependency, spec, is_optional) in dependencies {
+                queue.push_back((dependency, spec, ancestors.clone(), optional || is_optional));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Where `name@spec` should be installed, when it is required by the package whose
+    /// `node_modules` directory is the last of `ancestors`.
+    /// Returns `None` when a satisfying version is already reachable from there.
+    fn placement(&self, name: &str, spec: &str, ancestors: &[PathBuf]) -> Option<PathBuf> {
+        let lookup_dirs = ancestors.iter().rev().chain(std::iter::once(&self.root));
+        let nearest = lookup_dirs
+            .map(|dir| dir.join(name))
+            .find(|path| self.versions.contains_key(path));
+        match nearest {
+            Some(path) if satisfies(spec, &self.versions[&path]) => {
+                debug!("{}@{} is already satisfied by {:?}", name, spec, path);
+                None
+            }
+            Some(_) => Some(ancestors.last().unwrap_or(&self.root).join(name)),
+            None => Some(self.root.join(name)),
+        }
+    }
+
+    /// Downloads the tarball of the dependency `name@spec`, or reads it when it's a local one.
+    /// Returns where to install it, the tarball and where it was resolved from,
+    /// or `None` when a satisfying version is already installed.
+    fn fetch(
+        &self,
+        registries: &RegistryConfig,
+        name: &str,
+        spec: &str,
+        ancestors: &[PathBuf],
+    ) -> Result<Option<(PathBuf, Vec<u8>, String)>, Errors> {
+        if !is_package_name(name) {
+            return Err(Errors::InvalidDependencyName {
+                name: name.to_string(),
+            });
+        }
+        if let Some(path) = local_tarball(spec) {
+            let target = ancestors.last().unwrap_or(&self.root).join(name);
+            return Ok(Some((
+                target,
+                read(&local_tarball_path(&self.cwd, path)?)?,
+                spec.to_string(),
+            )));
+        }
+
+        let (package, spec) = registry_package(name, spec)?;
+        let registry = registries.registry(&package);
+        let fetch_packument = || match self.cache {
+            Some(cache) => cache.packument(&registry, &package),
+            None => registry.packument(&package),
+        };
+        // dist-tags are resolved first, as only versions and ranges can be satisfied by
+        // a version that is already installed, and dependency cycles through tags would never end
+        let (spec, packument) = if VersionRange::parse(&spec).is_some() {
+            (spec, None)
+        } else {
+            let packument = fetch_packument()?;
+            let version = packument.resolve(&spec)?.version.clone();
+            debug!("Resolved the tag {}@{} to {}", package, spec, version);
+            (version, Some(packument))
+        };
+        let target = match self.placement(name, &spec, ancestors) {
+            Some(target) => target,
+            None => return Ok(None),
+        };
+        let packument = match packument {
+            Some(packument) => packument,
+            None => fetch_packument()?,
+        };
+        let version = packument.resolve(&spec)?;
+        debug!("Resolved {}@{} to {}", package, spec, version.version);
+        if !supports_platform(&version.os, &version.cpu) {
+            return Err(Errors::UnsupportedPlatform {
+                name: package,
+                version: version.version.clone(),
+            });
+        }
+        let tarball = match self.cache {
+            Some(cache) => cache.tarball(&registry, version)?,
+            None => registry.tarball(version)?,
+        };
+        Ok(Some((target, tarball, version.dist.tarball.clone())))
+    }
+
+    /// Extracts a package tarball, resolved from `resolved`, into `target`,
+    /// returning its dependencies, and whether each of them is optional
+    fn unpack(
+        &mut self,
+        tarball: &[u8],
+        target: &Path,
+        resolved: &str,
+        optional: bool,
+    ) -> Result<Vec<(String, String, bool)>, Errors> {
+        extract_tarball(tarball, target)?;
+
+        let manifest = read_manifest(target)?;
+        link_binaries(target, &manifest)?;
+        if let Ok(version) = semver::Version::parse(&manifest.version) {
+            self.versions.insert(target.to_path_buf(), version);
+        }
+        let dependencies = manifest
+            .dependencies
+            .iter()
+            .filter(|(name, _)| !manifest.optional_dependencies.contains_key(*name))
+            .map(|(name, spec)| (name.clone(), spec.clone(), false))
+            .chain(
+                manifest
+                    .optional_dependencies
+                    .iter()
+                    .map(|(name, spec)| (name.clone(), spec.clone(), true)),
+            )
+            .collect();
+        self.installed.push(InstalledDependency {
+            path: target.to_path_buf(),
+            manifest,
+            resolved: Some(resolved.to_string()),
+            integrity: Some(registry::integrity(tarball)),
+            optional,
+        });
+
+        Ok(dependencies)
+    }
+}
+
+/// The path of a `file:` dependency on a local tarball, relative to the `package.json` file
+fn local_tarball(spec: &str) -> Option<&str> {
+    let path = spec.strip_prefix("file:")?;
+    let is_tarball = path.ends_with(".tgz") || path.ends_with(".tar.gz") || path.ends_with(".tar");
+    if is_tarball {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Where the local tarball at `path`, relative to `cwd`, is read from.
+/// Tarballs outside `cwd` are refused, as the paths come from lockfiles and dependencies.
+fn local_tarball_path(cwd: &Path, path: &str) -> Result<PathBuf, Errors> {
+    let is_inside = Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if is_inside {
+        Ok(cwd.join(path))
+    } else {
+        Err(Errors::TarballOutside {
+            path: path.to_string(),
+            cwd: cwd.to_path_buf(),
+        })
+    }
+}
+
+/// Whether `name` is a package name, like `tool` or `@scope/tool`,
+/// which can only be installed inside the `node_modules` directory it's joined to
+fn is_package_name(name: &str) -> bool {
+    let is_part =
+        |part: &str| !part.is_empty() && !part.starts_with('.') && !part.contains(['\\', ':']);
+    match name.split('/').collect::<Vec<_>>().as_slice() {
+        [name] => is_part(name),
+        [scope, name] => scope.strip_prefix('@').is_some_and(is_part) && is_part(name),
+        _ => false,
+    }
+}
+
+/// Whether a key of the `packages` of a lockfile is a path inside `node_modules`,
+/// like `node_modules/a/node_modules/@scope/b`
+fn is_node_modules_path(key: &str) -> bool {
+    key.starts_with("node_modules/")
+        && Path::new(key)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// The registry package and version range to install for the dependency `name@spec`,
+/// following `npm:` aliases
+fn registry_package(name: &str, spec: &str) -> Result<(String, String), Errors> {
+    let unsupported = || Errors::UnsupportedSpec {
+        name: name.to_string(),
+        spec: spec.to_string(),
+    };
+    let requested: NodePackageVersion = format!("{}@{}", name, spec)
+        .parse()
+        .map_err(|_| unsupported())?;
+    match requested.source() {
+        PackageSource::Registry(spec) => Ok((name.to_string(), spec.to_string())),
+        PackageSource::Alias { package, spec } => Ok((package.clone(), spec.to_string())),
+        _ => Err(unsupported()),
+    }
+}
+
+/// Whether the current platform is one a package supports, by its `os` and `cpu` lists.
+/// Like in npm, entries starting with `!` exclude a platform, and empty lists allow any.
+fn supports_platform(os: &[String], cpu: &[String]) -> bool {
+    let current_os = match std::env::consts::OS {
+        "macos" => "darwin",
+        "windows" => "win32",
+        os => os,
+    };
+    let current_cpu = match std::env::consts::ARCH {
+        "x86_64" => "x64",
+        "x86" => "ia32",
+        "aarch64" => "arm64",
+        "powerpc64" => "ppc64",
+        arch => arch,
+    };
+    is_allowed(os, current_os) && is_allowed(cpu, current_cpu)
+}
+
+fn is_allowed(platforms: &[String], current: &str) -> bool {
+    let (excluded, included): (Vec<_>, Vec<_>) = platforms
+        .iter()
+        .map(String::as_str)
+        .partition(|platform| platform.starts_with('!'));
+    let is_excluded = excluded.iter().any(|platform| platform[1..] == *current);
+    !is_excluded && (included.is_empty() || included.contains(&current))
+}
+
+fn satisfies(spec: &str, version: &semver::Version) -> bool {
+    VersionRange::parse(spec).is_some_and(|range| range.matches(version))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, Errors> {
+    std::fs::read(path).map_err(|source| Errors::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn read_manifest(package_dir: &Path) -> Result<PackageManifest, Errors> {
+    let path = package_dir.join("package.json");
+    serde_json::from_slice(&read(&path)?).map_err(|source| Errors::InvalidManifest { path, source })
+}
+
+/// Extracts a gzipped npm tarball into `target`, stripping its top level directory
+pub(crate) fn extract_tarball(tarball: &[u8], target: &Path) -> Result<(), Errors> {
+    let to_error = |source| Errors::Io {
+        path: target.to_path_buf(),
+        source,
+    };
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(tarball));
+    for entry in archive.entries().map_err(to_error)? {
+        let mut entry = entry.map_err(to_error)?;
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            continue;
+        }
+        let entry_path = entry.path().map_err(to_error)?.into_owned();
+        let relative: PathBuf = entry_path.components().skip(1).collect();
+        let is_safe = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if relative.as_os_str().is_empty() || !is_safe {
+            continue;
+        }
+        let path = target.join(relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(to_error)?;
+        }
+        entry.unpack(&path).map_err(to_error)?;
+    }
+    Ok(())
+}
+
+/// Links the binaries of the package in `package_dir` into the `.bin` directory next to it
+fn link_binaries(package_dir: &Path, manifest: &PackageManifest) -> Result<(), Errors> {
+    let bin = match &manifest.bin {
+        Some(bin) => bin.binaries(&manifest.name),
+        None => return Ok(()),
+    };
+    let node_modules = package_dir
+        .ancestors()
+        .find(|dir| dir.file_name().is_some_and(|name| name == "node_modules"))
+        .expect("Package is not in a node_modules directory");
+    let bin_dir = node_modules.join(".bin");
+    let to_error = |source| Errors::Io {
+        path: bin_dir.clone(),
+        source,
+    };
+    std::fs::create_dir_all(&bin_dir).map_err(to_error)?;
+
+    for (binary_name, binary_path) in bin {
+        if !is_safe_binary(&binary_name, &binary_path) {
+            return Err(Errors::UnsafeBinary {
+                package: manifest.name.clone(),
+                name: binary_name,
+                path: binary_path,
+            });
+        }
+        let target = package_dir.join(&binary_path);
+        let link_path = bin_dir.join(&binary_name);
+        let relative_target = Path::new("..")
+            .join(
+                package_dir
+                    .strip_prefix(node_modules)
+                    .unwrap_or(package_dir),
+            )
+            .join(&binary_path);
+        debug!("Linking {:?} to {:?}", link_path, relative_target);
+        if link_path.exists() {
+            std::fs::remove_file(&link_path).map_err(to_error)?;
+        }
+        link(&relative_target, &target, &link_path).map_err(to_error)?;
+    }
+    Ok(())
+}
+
+/// Whether a binary is named like a file, and points into its package, as npm requires
+fn is_safe_binary(name: &str, path: &str) -> bool {
+    let is_safe_name =
+        !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', ':']);
+    let is_safe_path = Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    is_safe_name && is_safe_path
+}
+
+#[cfg(unix)]
+fn link(relative_target: &Path, target: &Path, link_path: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(relative_target, link_path)?;
+    crate::binary::set_permissions(target)
+}
+
+#[cfg(windows)]
+fn link(_relative_target: &Path, target: &Path, link_path: &Path) -> std::io::Result<()> {
+    let script = format!("@node \"{}\" %*\r\n", target.display());
+    std::fs::write(link_path.with_extension("cmd"), script)
+}
+
+/// All packages in a `node_modules` tree, including nested ones
+fn installed_packages(node_modules: &Path) -> Result<Vec<InstalledDependency>, Errors> {
+    let mut packages = vec![];
+    let entries = match node_modules.read_dir() {
+        Ok(entries) => entries.filter_map(Result::ok),
+        Err(_) => return Ok(packages),
+    };
+    for entry in entries {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') {
+            continue;
+        }
+        if name.starts_with('@') {
+            packages.extend(installed_packages(&path)?);
+            continue;
+        }
+        if let Ok(manifest) = read_manifest(&path) {
+            packages.extend(installed_packages(&path.join("node_modules"))?);
+            packages.push(InstalledDependency {
+                path,
+                manifest,
+                resolved: None,
+                integrity: None,
+                optional: false,
+            });
+        }
+    }
+    Ok(packages)
+}
+
+/// Writes the `package-lock.json` of the packages installed in `cwd`,
+/// in the same format npm does
+fn write_lockfile(
+    cwd: &Path,
+    root: &PackageRoot,
+    installed: &[InstalledDependency],
+) -> Result<(), Errors> {
+    let mut packages = BTreeMap::new();
+    packages.insert(
+        String::new(),
+        LockedPackage {
+            name: Some(root.name.clone()),
+            dependencies: root.dependencies.clone().into_iter().collect(),
+            ..LockedPackage::default()
+        },
+    );
+    for package in installed {
+        let relative = package.path.strip_prefix(cwd).unwrap_or(&package.path);
+        let key = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let is_renamed = !key.ends_with(&format!("node_modules/{}", package.manifest.name));
+        packages.insert(
+            key,
+            LockedPackage {
+                name: Some(package.manifest.name.clone()).filter(|_| is_renamed),
+                version: Some(package.manifest.version.clone()),
+                resolved: package.resolved.clone(),
+                integrity: package.integrity.clone(),
+                dependencies: package.manifest.dependencies.clone().into_iter().collect(),
+                optional_dependencies: package
+                    .manifest
+                    .optional_dependencies
+                    .clone()
+                    .into_iter()
+                    .collect(),
+                optional: package.optional,
+                os: package.manifest.os.clone(),
+                cpu: package.manifest.cpu.clone(),
+            },
+        );
+    }
+    let lockfile = PackageLock {
+        name: root.name.clone(),
+        lockfile_version: 3,
+        requires: true,
+        packages,
+    };
+    let path = cwd.join("package-lock.json");
+    let contents = serde_json::to_string_pretty(&lockfile).expect("Can't serialize the lockfile");
+    std::fs::write(&path, contents).map_err(|source| Errors::Io { path, source })
+}
+
+/// The `preinstall`, `install` and `postinstall` scripts of a package, in the order they run.
+/// Like in npm, a package with a `binding.gyp` file and no install scripts is built with `node-gyp`.
+fn install_scripts(package: &InstalledDependency) -> Vec<(&'static str, String)> {
+    let scripts = &package.manifest.scripts;
+    let builds_with_gyp = !scripts.contains_key("install")
+        && !scripts.contains_key("preinstall")
+        && package.manifest.gypfile != Some(false)
+        && package.path.join("binding.gyp").is_file();
+    ["preinstall", "install", "postinstall"]
+        .iter()
+        .copied()
+        .filter_map(|script_name| {
+            let script = match scripts.get(script_name) {
+                Some(script) => script.clone(),
+                None if script_name == "install" && builds_with_gyp => {
+                    "node-gyp rebuild".to_string()
+                }
+                None => return None,
+            };
+            Some((script_name, script))
+        })
+        .collect()
+}
+
+/// The directory of the `node-gyp` wrapper of the npm bundled with `node`,
+/// which npm puts in the `PATH` of install scripts
+fn node_gyp_bin(node: &NodeBinary) -> Option<PathBuf> {
+    let npm_dir = if cfg!(windows) {
+        node.dir().join("node_modules").join("npm")
+    } else {
+        node.dir().parent()?.join("lib/node_modules/npm")
+    };
+    let dir = npm_dir.join("node_modules/@npmcli/run-script/lib/node-gyp-bin");
+    dir.is_dir().then_some(dir)
+}
+
+/// Runs the install scripts of a package
+fn run_install_scripts(
+    root: &Path,
+    package: &InstalledDependency,
+    node: &NodeBinary,
+) -> Result<(), Errors> {
+    for (script_name, script) in install_scripts(package) {
+        let script = &script;
+        debug!(
+            "Running {} script of {}: {}",
+            script_name, package.manifest.name, script
+        );
+        let current_path = std::env::var_os("PATH").unwrap_or_default();
+        let paths = node_gyp_bin(node).into_iter().chain([
+            node.dir().to_path_buf(),
+            package.path.join("node_modules").join(".bin"),
+            root.join("node_modules").join(".bin"),
+        ]);
+        let path = std::env::join_paths(paths.chain(std::env::split_paths(&current_path)))
+            .map_err(|source| Errors::InvalidPath {
+                node: node.path.clone(),
+                source,
+            })?;
+        let mut command = if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.arg("/C");
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.arg("-c");
+            command
+        };
+        let status = command
+            .arg(script)
+            .current_dir(&package.path)
+            .env("PATH", path)
+            .env("npm_package_name", &package.manifest.name)
+            .env("npm_package_version", &package.manifest.version)
+            .env("npm_lifecycle_event", script_name)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+            .map_err(|source| Errors::Spawn {
+                program: script.to_string(),
+                source,
+            })?;
+        if !status.success() {
+            return Err(Errors::Failed {
+                command: format!(
+                    "{} ({} script of {})",
+                    script, script_name, package.manifest.name
+                ),
+                status,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry;
+    use crate::test_registry::{create_tarball, TestPackage, TestRegistry};
+    use pretty_assertions::assert_eq;
+
+    fn installed_version(package_dir: &Path) -> String {
+        read_manifest(package_dir).unwrap().version
+    }
+
+    fn install(registry: &TestRegistry, dependencies: &[(&str, &str)]) -> tempdir::TempDir {
+        let cwd = tempdir::TempDir::new("native").unwrap();
+        let package_json = serde_json::json!({
+            "name": "test_global_installation",
+            "dependencies": dependencies.iter().cloned().collect::<HashMap<_, _>>(),
+            "engines": { "node": "v14.0.0" },
+        });
+        std::fs::write(cwd.path().join("package.json"), package_json.to_string()).unwrap();
+        let node = NodeBinary {
+            path: PathBuf::from("/usr/bin/node"),
+            version: "v14.0.0".to_string(),
+        };
+        Native::new(registry.config(), None)
+            .install(cwd.path(), &node)
+            .expect("Can't install");
+        cwd
+    }
+
+    #[test]
+    fn nests_conflicting_versions() {
+        let registry = TestRegistry::new();
+        registry
+            .publish(TestPackage::new("left-pad", "1.0.0"))
+            .publish(TestPackage::new("left-pad", "1.3.0"))
+            .publish(TestPackage::new("left-pad", "2.0.0"))
+            .publish(TestPackage {
+                dependencies: &[("left-pad", "^1.1.0")],
+                ..TestPackage::new("greeter", "1.0.0")
+            });
+
+        let cwd = install(&registry, &[("greeter", "^1"), ("left-pad", "2")]);
+        let node_modules = cwd.path().join("node_modules");
+
+        assert_eq!(installed_version(&node_modules.join("left-pad")), "2.0.0");
+        assert_eq!(
+            installed_version(&node_modules.join("greeter/node_modules/left-pad")),
+            "1.3.0"
+        );
+
+        let lockfile: PackageLock =
+            serde_json::from_slice(&std::fs::read(cwd.path().join("package-lock.json")).unwrap())
+                .unwrap();
+        let locked: Vec<_> = lockfile
+            .packages
+            .iter()
+            .map(|(path, package)| (path.as_str(), package.version.as_deref()))
+            .collect();
+        assert_eq!(
+            locked,
+            vec![
+                ("", None),
+                ("node_modules/greeter", Some("1.0.0")),
+                ("node_modules/greeter/node_modules/left-pad", Some("1.3.0")),
+                ("node_modules/left-pad", Some("2.0.0")),
+            ]
+        );
+        let left_pad = &lockfile.packages["node_modules/left-pad"];
+        assert!(left_pad.resolved.as_deref().unwrap().ends_with(".tgz"));
+        assert!(left_pad
+            .integrity
+            .as_deref()
+            .unwrap()
+            .starts_with("sha512-"));
+    }
+
+    #[test]
+    fn hoists_and_links_binaries() {
+        let registry = TestRegistry::new();
+        registry
+            .publish(TestPackage::new("left-pad", "1.0.0"))
+            .publish(TestPackage {
+                dependencies: &[("left-pad", "^1")],
+                bin: &[("greet", "console.log('hello')")],
+                ..TestPackage::new("@acme/greeter", "1.0.0")
+            });
+
+        let cwd = install(&registry, &[("@acme/greeter", "latest")]);
+        let node_modules = cwd.path().join("node_modules");
+
+        assert_eq!(installed_version(&node_modules.join("left-pad")), "1.0.0");
+        assert!(!node_modules.join("@acme/greeter/node_modules").exists());
+        let link = node_modules.join(".bin").join("greet");
+        assert_eq!(
+            std::fs::read_to_string(link).unwrap(),
+            "console.log('hello')"
+        );
+    }
+
+    #[test]
+    fn fails_on_missing_versions() {
+        let registry = TestRegistry::new();
+        registry.publish(TestPackage::new("left-pad", "1.0.0"));
+
+        let cwd = tempdir::TempDir::new("native").unwrap();
+        std::fs::write(
+            cwd.path().join("package.json"),
+            r#"{"name": "x", "dependencies": {"left-pad": "^2"}, "engines": {"node": "v14.0.0"}}"#,
+        )
+        .unwrap();
+        let node = NodeBinary {
+            path: PathBuf::from("/usr/bin/node"),
+            version: "v14.0.0".to_string(),
+        };
+        let result = Native::new(registry.config(), None).install(cwd.path(), &node);
+
+        assert!(matches!(
+            result,
+            Err(Errors::Registry(registry::Errors::NoMatchingVersion { .. }))
+        ));
+    }
+
+    #[test]
+    fn installs_local_tarballs() {
+        let registry = TestRegistry::new();
+        registry.publish(TestPackage::new("left-pad", "1.0.0"));
+        let tarball = create_tarball(&[(
+            "package/package.json".to_string(),
+            br#"{"name": "tool", "version": "0.1.0", "dependencies": {"left-pad": "1"}}"#.to_vec(),
+        )]);
+
+        let cwd = tempdir::TempDir::new("native").unwrap();
+        std::fs::write(cwd.path().join("tool-0.1.0.tgz"), tarball).unwrap();
+        std::fs::write(
+            cwd.path().join("package.json"),
+            r#"{"name": "x", "dependencies": {"tool": "file:./tool-0.1.0.tgz"}, "engines": {"node": "v14.0.0"}}"#,
+        )
+        .unwrap();
+        let node = NodeBinary {
+            path: PathBuf::from("/usr/bin/node"),
+            version: "v14.0.0".to_string(),
+        };
+        Native::new(registry.config(), None)
+            .install(cwd.path(), &node)
+            .expect("Can't install");
+
+        let node_modules = cwd.path().join("node_modules");
+        assert_eq!(installed_version(&node_modules.join("tool")), "0.1.0");
+        assert_eq!(installed_version(&node_modules.join("left-pad")), "1.0.0");
+    }
+
+    #[test]
+    fn refuses_dependencies_named_like_paths() {
+        let registry = TestRegistry::new();
+        let node = NodeBinary {
+            path: PathBuf::from("/usr/bin/node"),
+            version: "v14.0.0".to_string(),
+        };
+        for (name, spec) in [
+            ("../../escaped", "file:./tool-0.1.0.tgz"),
+            ("@scope/../../escaped", "1"),
+            ("..", "1"),
+        ] {
+            let cwd = tempdir::TempDir::new("native").unwrap();
+            std::fs::write(
+                cwd.path().join("tool-0.1.0.tgz"),
+                create_tarball(&[(
+                    "package/package.json".to_string(),
+                    br#"{"name": "tool", "version": "0.1.0"}"#.to_vec(),
+                )]),
+            )
+            .unwrap();
+            let manifest = serde_json::json!({
+                "name": "x",
+                "dependencies": { name: spec },
+                "engines": { "node": "v14.0.0" },
+            });
+            std::fs::write(cwd.path().join("package.json"), manifest.to_string()).unwrap();
+            let error = Native::new(registry.config(), None)
+                .install(cwd.path(), &node)
+                .unwrap_err();
+            assert!(
+                matches!(error, Errors::InvalidDependencyName { .. }),
+                "{}: {:?}",
+                name,
+                error
+            );
+            assert!(!cwd.path().parent().unwrap().join("escaped").exists());
+        }
+    }
+
+    #[test]
+    fn refuses_locks_that_write_outside_node_modules() {
+        let registry = TestRegistry::new();
+        let tarball = create_tarball(&[(
+            "package/package.json".to_string(),
+            br#"{"name": "tool", "version": "0.1.0"}"#.to_vec(),
+        )]);
+        let cwd = tempdir::TempDir::new("native").unwrap();
+        std::fs::write(cwd.path().join("tool-0.1.0.tgz"), &tarball).unwrap();
+        std::fs::write(cwd.path().parent().unwrap().join("outside.tgz"), &tarball).unwrap();
+        std::fs::write(
+            cwd.path().join("package.json"),
+            r#"{"name": "x", "dependencies": {"tool": "file:./tool-0.1.0.tgz"}, "engines": {"node": "v14.0.0"}}"#,
+        )
+        .unwrap();
+        let node = NodeBinary {
+            path: PathBuf::from("/usr/bin/node"),
+            version: "v14.0.0".to_string(),
+        };
+        let native = Native::new(registry.config(), None);
+        native.install(cwd.path(), &node).expect("Can't install");
+        let lockfile_path = cwd.path().join("package-lock.json");
+        let lockfile: PackageLock =
+            serde_json::from_slice(&std::fs::read(&lockfile_path).unwrap()).unwrap();
+
+        let mut traversal = lockfile.clone();
+        let locked = traversal.packages.remove("node_modules/tool").unwrap();
+        traversal
+            .packages
+            .insert("node_modules/../../escaped".to_string(), locked);
+        std::fs::write(&lockfile_path, serde_json::to_vec(&traversal).unwrap()).unwrap();
+        std::fs::remove_dir_all(cwd.path().join("node_modules")).unwrap();
+        let error = native.install_frozen(cwd.path(), &node).unwrap_err();
+        assert!(
+            matches!(&error, Errors::FrozenLockfile { reason, .. } if reason.contains("inside node_modules")),
+            "{:?}",
+            error
+        );
+        assert!(!cwd.path().parent().unwrap().join("escaped").exists());
+
+        let mut outside = lockfile;
+        outside
+            .packages
+            .get_mut("node_modules/tool")
+            .unwrap()
+            .resolved = Some("file:../outside.tgz".to_string());
+        std::fs::write(&lockfile_path, serde_json::to_vec(&outside).unwrap()).unwrap();
+        let error = native.install_frozen(cwd.path(), &node).unwrap_err();
+        assert!(
+            matches!(error, Errors::TarballOutside { .. }),
+            "{:?}",
+            error
+        );
+        assert!(!cwd.path().join("node_modules/tool").exists());
+    }
+
+    #[test]
+    fn refuses_binaries_outside_the_package() {
+        let registry = TestRegistry::new();
+        let node = NodeBinary {
+            path: PathBuf::from("/usr/bin/node"),
+            version: "v14.0.0".to_string(),
+        };
+        for bin in [r#"{"../../x": "cli.js"}"#, r#"{"x": "../../../cli.js"}"#] {
+            let manifest = format!(r#"{{"name": "tool", "version": "0.1.0", "bin": {}}}"#, bin);
+            let tarball = create_tarball(&[
+                ("package/package.json".to_string(), manifest.into_bytes()),
+                ("package/cli.js".to_string(), b"steal()".to_vec()),
+            ]);
+            let cwd = tempdir::TempDir::new("native").unwrap();
+            std::fs::write(cwd.path().join("tool-0.1.0.tgz"), tarball).unwrap();
+            std::fs::write(
+                cwd.path().join("package.json"),
+                r#"{"name": "x", "dependencies": {"tool": "file:./tool-0.1.0.tgz"}, "engines": {"node": "v14.0.0"}}"#,
+            )
+            .unwrap();
+
+            let result = Native::new(registry.config(), None).install(cwd.path(), &node);
+
+            assert!(
+                matches!(result, Err(Errors::UnsafeBinary { .. })),
+                "{} was linked",
+                bin
+            );
+            assert!(!cwd.path().join("x").exists());
+        }
+    }
+
+    #[test]
+    fn skips_optional_dependencies_that_cant_be_installed() {
+        let current_os = if cfg!(target_os = "macos") {
+            "darwin"
+        } else if cfg!(windows) {
+            "win32"
+        } else {
+            std::env::consts::OS
+        };
+        let other_os = format!("!{}", current_os);
+        let registry = TestRegistry::new();
+        registry
+            .publish(TestPackage {
+                os: &[current_os],
+                ..TestPackage::new("tool-here", "1.0.0")
+            })
+            .publish(TestPackage {
+                os: &[&other_os],
+                ..TestPackage::new("tool-elsewhere", "1.0.0")
+            })
+            .publish(TestPackage {
+                optional_dependencies: &[
+                    ("tool-here", "1"),
+                    ("tool-elsewhere", "1"),
+                    ("tool-unpublished", "1"),
+                ],
+                ..TestPackage::new("tool", "1.0.0")
+            });
+
+        let cwd = install(&registry, &[("tool", "1")]);
+        let node_modules = cwd.path().join("node_modules");
+        assert_eq!(installed_version(&node_modules.join("tool-here")), "1.0.0");
+        assert!(!node_modules.join("tool-elsewhere").exists());
+        assert!(!node_modules.join("tool-unpublished").exists());
+
+        let lockfile: PackageLock =
+            serde_json::from_slice(&std::fs::read(cwd.path().join("package-lock.json")).unwrap())
+                .unwrap();
+        let locked = &lockfile.packages["node_modules/tool-here"];
+        assert!(locked.optional);
+        assert_eq!(locked.os, vec![current_os]);
+        assert!(!lockfile.packages["node_modules/tool"].optional);
+
+        // a lock from another platform leaves out what this one doesn't support
+        let mut lockfile = lockfile;
+        lockfile
+            .packages
+            .get_mut("node_modules/tool-here")
+            .unwrap()
+            .os = vec![other_os];
+        std::fs::write(
+            cwd.path().join("package-lock.json"),
+            serde_json::to_vec(&lockfile).unwrap(),
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&node_modules).unwrap();
+        let node = NodeBinary {
+            path: PathBuf::from("/usr/bin/node"),
+            version: "v14.0.0".to_string(),
+        };
+        Native::new(registry.config(), None)
+            .install_frozen(cwd.path(), &node)
+            .expect("Can't install frozen");
+        assert!(node_modules.join("tool").exists());
+        assert!(!node_modules.join("tool-here").exists());
+    }
+
+    #[test]
+    fn builds_gyp_packages_without_install_scripts() {
+        let package_dir = tempdir::TempDir::new("package").unwrap();
+        let package = |manifest: &str| InstalledDependency {
+            path: package_dir.path().to_path_buf(),
+            manifest: serde_json::from_str(manifest).unwrap(),
+            resolved: None,
+            integrity: None,
+            optional: false,
+        };
+        let names = |package: &InstalledDependency| {
+            install_scripts(package)
+                .into_iter()
+                .map(|(name, script)| format!("{}: {}", name, script))
+                .collect::<Vec<_>>()
+        };
+        let manifest =
+            r#"{"name": "addon", "version": "1.0.0", "scripts": {"postinstall": "echo"}}"#;
+        assert_eq!(names(&package(manifest)), vec!["postinstall: echo"]);
+
+        std::fs::write(package_dir.path().join("binding.gyp"), "{}").unwrap();
+        assert_eq!(
+            names(&package(manifest)),
+            vec!["install: node-gyp rebuild", "postinstall: echo"]
+        );
+        let manifest =
+            r#"{"name": "addon", "version": "1.0.0", "scripts": {"install": "prebuild-install"}}"#;
+        assert_eq!(names(&package(manifest)), vec!["install: prebuild-install"]);
+        let manifest = r#"{"name": "addon", "version": "1.0.0", "gypfile": false}"#;
+        assert!(names(&package(manifest)).is_empty());
+    }
+
+    #[test]
+    fn resolves_tags_in_dependency_cycles() {
+        let registry = TestRegistry::new();
+        registry
+            .publish(TestPackage {
+                dependencies: &[("b", "next")],
+                ..TestPackage::new("a", "1.0.0")
+            })
+            .publish(TestPackage {
+                dependencies: &[("a", "beta")],
+                ..TestPackage::new("b", "2.0.0")
+            })
+            .publish(TestPackage::new("b", "1.0.0"))
+            .tag("a", "beta", "1.0.0")
+            .tag("b", "next", "2.0.0");
+
+        let cwd = install(&registry, &[("a", "beta")]);
+        let node_modules = cwd.path().join("node_modules");
+
+        assert_eq!(installed_version(&node_modules.join("a")), "1.0.0");
+        assert_eq!(installed_version(&node_modules.join("b")), "2.0.0");
+        assert!(!node_modules.join("b/node_modules").exists());
+    }
+}
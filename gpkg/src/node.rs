@@ -0,0 +1,292 @@
+use log::*;
+use miette::Diagnostic;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use thiserror::Error;
+
+/// A Node.js binary that packages are pinned to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeBinary {
+    pub path: PathBuf,
+    pub version: String,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Errors {
+    #[error("Can't find a `node` binary in your PATH")]
+    #[diagnostic(help("Install Node.js or provide one explicitly using `--node`"))]
+    NoCurrentNode,
+
+    #[error("Can't find a Node.js version matching {requested:?}")]
+    #[diagnostic(help(
+        "Install it using fnm, nvm or asdf, or pass a path to a node binary instead.\nSearched in:\n{}",
+        display_paths(searched)
+    ))]
+    VersionNotFound {
+        requested: String,
+        searched: Vec<PathBuf>,
+    },
+
+    #[error("Can't run node binary at {path:?}")]
+    #[diagnostic(help("Make sure the path points to an executable node binary"))]
+    InvalidBinary {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl NodeBinary {
+    /// The `node` binary that is currently in the user's `PATH`
+    pub fn current() -> Result<Self, Errors> {
+        let location = which_node().ok_or(Errors::NoCurrentNode)?;
+        Self::from_path(location)
+    }
+
+    /// A node binary in a specific path. Runs it to find its version.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Errors> {
+        let path = std::fs::canonicalize(&path).map_err(|source| Errors::InvalidBinary {
+            path: path.as_ref().to_path_buf(),
+            source,
+        })?;
+        let version = node_version(&path).map_err(|source| Errors::InvalidBinary {
+            path: path.clone(),
+            source,
+        })?;
+        Ok(Self { path, version })
+    }
+
+    /// Resolves a `--node` argument: either a path to a node binary,
+    /// or a version that is installed using fnm, nvm or asdf.
+    pub fn resolve(requested: &str) -> Result<Self, Errors> {
+        if looks_like_path(requested) {
+            Self::from_path(requested)
+        } else {
+            Self::find_in(requested, &version_manager_dirs())
+        }
+    }
+
+    /// Finds the latest installed version matching `requested` in the given version manager directories
+    pub fn find_in(requested: &str, dirs: &[VersionsDir]) -> Result<Self, Errors> {
+        let mut found: Option<(Vec<u64>, Self)> = None;
+
+        for dir in dirs {
+            let entries = match dir.path.read_dir() {
+                Ok(entries) => entries.filter_map(Result::ok),
+                Err(_) => continue,
+            };
+            for entry in entries {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !version_matches(requested, &name) {
+                    continue;
+                }
+                let parts = version_parts(&name);
+                let path = entry.path().join(&dir.binary_path);
+                if !path.exists() {
+                    continue;
+                }
+                if found.as_ref().is_none_or(|(latest, _)| &parts > latest) {
+                    let version = format!("v{}", name.trim_start_matches('v'));
+                    found = Some((parts, Self { path, version }));
+                }
+            }
+        }
+
+        let (_, node) = found.ok_or_else(|| Errors::VersionNotFound {
+            requested: requested.to_string(),
+            searched: dirs.iter().map(|dir| dir.path.clone()).collect(),
+        })?;
+        debug!("Resolved node {:?} to {:?}", requested, node.path);
+        Ok(node)
+    }
+
+    /// The directory containing the node binary
+    pub fn dir(&self) -> &Path {
+        self.path.parent().expect("Got node with no parent")
+    }
+}
+
+/// A directory containing a subdirectory for every installed Node.js version,
+/// like fnm's `node-versions` or nvm's `versions/node`
+#[derive(Debug)]
+pub struct VersionsDir {
+    pub path: PathBuf,
+    /// The path of the node binary, relative to a version directory
+    pub binary_path: PathBuf,
+}
+
+impl VersionsDir {
+    pub fn new(path: impl Into<PathBuf>, binary_path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            binary_path: binary_path.into(),
+        }
+    }
+}
+
+const NODE_BINARY: &str = if cfg!(windows) {
+    "node.exe"
+} else {
+    "bin/node"
+};
+
+/// The version directories of fnm, nvm and asdf, respecting their environment variables
+fn version_manager_dirs() -> Vec<VersionsDir> {
+    let home = dirs::home_dir().unwrap_or_default();
+    let env_or =
+        |name: &str, default: PathBuf| std::env::var_os(name).map(PathBuf::from).unwrap_or(default);
+
+    let mut fnm_dirs = vec![];
+    if let Some(fnm_dir) = std::env::var_os("FNM_DIR") {
+        fnm_dirs.push(PathBuf::from(fnm_dir));
+    } else {
+        fnm_dirs.push(home.join(".fnm"));
+        if let Some(data_dir) = dirs::data_dir() {
+            fnm_dirs.push(data_dir.join("fnm"));
+        }
+    }
+    let fnm_binary = Path::new("installation").join(NODE_BINARY);
+
+    let mut dirs: Vec<_> = fnm_dirs
+        .into_iter()
+        .map(|dir| VersionsDir::new(dir.join("node-versions"), &fnm_binary))
+        .collect();
+    dirs.push(VersionsDir::new(
+        env_or("NVM_DIR", home.join(".nvm"))
+            .join("versions")
+            .join("node"),
+        NODE_BINARY,
+    ));
+    dirs.push(VersionsDir::new(
+        env_or("ASDF_DATA_DIR", home.join(".asdf"))
+            .join("installs")
+            .join("nodejs"),
+        NODE_BINARY,
+    ));
+    dirs
+}
+
+/// Whether `version` matches a partial version like `v18` or `18.1`
+pub fn version_matches(requested: &str, version: &str) -> bool {
+    let requested_parts = version_parts(requested);
+    let parts = version_parts(version);
+    !requested_parts.is_empty()
+        && parts.len() >= requested_parts.len()
+        && parts[..requested_parts.len()] == requested_parts[..]
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| format!("  {}", path.display()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn looks_like_path(requested: &str) -> bool {
+    requested.contains('/') || requested.contains(std::path::MAIN_SEPARATOR)
+}
+
+/// Numeric parts of a version string: `v18.1.0` => `[18, 1, 0]`
+fn version_parts(version: &str) -> Vec<u64> {
+    version
+        .trim()
+        .trim_start_matches('v')
+        .split('.')
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+fn node_version(path: impl AsRef<Path>) -> std::io::Result<String> {
+    let cmd = Command::new(path.as_ref())
+        .arg("--version")
+        .stdout(Stdio::piped())
+        .output()?;
+    if !cmd.status.success() {
+        return Err(std::io::Error::other(format!(
+            "`node --version` exited with {}",
+            cmd.status
+        )));
+    }
+    let version = std::str::from_utf8(&cmd.stdout)
+        .ok()
+        .ok_or(std::io::ErrorKind::UnexpectedEof)?
+        .trim()
+        .to_string();
+    Ok(version)
+}
+
+// Still not sure whether to add `fnm exec {version} {node args}`
+// command in fnm, or to keep this hard Node binary string here
+fn which_node() -> Option<PathBuf> {
+    let stdout = Command::new(if cfg!(windows) { "where" } else { "which" })
+        .arg("node")
+        .output()
+        .ok()?
+        .stdout;
+    let location = std::str::from_utf8(&stdout)
+        .ok()?
+        .split('\n')
+        .next()?
+        .trim();
+    debug!("`which node` returned location {:?}", &location);
+    if location.is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(location))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn create_versions(root: &Path, versions: &[&str]) -> VersionsDir {
+        for version in versions {
+            let bin = root.join(version).join("bin");
+            std::fs::create_dir_all(&bin).unwrap();
+            std::fs::write(bin.join("node"), "").unwrap();
+        }
+        VersionsDir::new(root, "bin/node")
+    }
+
+    #[test]
+    fn finds_latest_matching_version() {
+        let root = tempdir::TempDir::new("node_versions").unwrap();
+        let dir = create_versions(root.path(), &["v16.3.0", "v18.1.0", "v18.12.1", "v20.0.0"]);
+
+        let node = NodeBinary::find_in("18", &[dir]).unwrap();
+
+        assert_eq!(node.version, "v18.12.1");
+        assert_eq!(node.path, root.path().join("v18.12.1").join("bin/node"));
+    }
+
+    #[test]
+    fn matches_whole_version_parts() {
+        let root = tempdir::TempDir::new("node_versions").unwrap();
+        let dir = create_versions(root.path(), &["18.1.0", "18.10.0"]);
+
+        let node = NodeBinary::find_in("v18.1", &[dir]).unwrap();
+
+        assert_eq!(node.version, "v18.1.0");
+    }
+
+    #[test]
+    fn partial_versions_match() {
+        assert!(version_matches("v14", "v14.17.0"));
+        assert!(version_matches("14.17", "v14.17.0"));
+        assert!(!version_matches("v14.1", "v14.17.0"));
+        assert!(!version_matches("v16", "v14.17.0"));
+    }
+
+    #[test]
+    fn fails_when_no_version_matches() {
+        let root = tempdir::TempDir::new("node_versions").unwrap();
+        let dir = create_versions(root.path(), &["v16.3.0"]);
+
+        let result = NodeBinary::find_in("18", &[dir]);
+
+        assert!(matches!(result, Err(Errors::VersionNotFound { .. })));
+    }
+}
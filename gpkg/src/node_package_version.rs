@@ -1,43 +1,448 @@
+use crate::version_range::VersionRange;
+use miette::{Diagnostic, NamedSource, SourceSpan};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use thiserror::Error;
 
-#[derive(Debug, PartialEq, Eq)]
+/// A requested package, in any of the forms npm accepts:
+///
+/// * `name`, `name@1.2.3`, `name@^1.2`, `name@>=1 <2`, `name@next`
+/// * `alias@npm:name@1.2.3`
+/// * `github:user/repo#ref`, `user/repo`, `git+https://host/repo.git#ref`
+/// * `./path/to/directory`, `file:../tool`
+/// * `./tool-1.2.0.tgz`, `https://host/tool-1.2.0.tgz`
+///
+/// Every source can be prefixed with `name@` to set the name it is installed as.
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct NodePackageVersion {
     name: String,
-    version: Option<String>,
+    source: PackageSource,
+    /// Whether `name` was guessed from the source instead of being given
+    inferred_name: bool,
+}
+
+/// Where a requested package comes from
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PackageSource {
+    /// A package from the registry
+    Registry(RegistrySpec),
+    /// A registry package, installed under another name
+    Alias { package: String, spec: RegistrySpec },
+    /// A git repository. `url` is kept in the form it was given in,
+    /// like `github:user/repo` or `git+https://host/repo.git`
+    Git {
+        url: String,
+        committish: Option<String>,
+    },
+    /// A package in a local directory
+    Directory(PathBuf),
+    /// A tarball, either a URL or a path to a local file
+    Tarball(String),
+}
+
+/// What version of a registry package to install
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RegistrySpec {
+    Tag(String),
+    Version(String),
+    Range(String),
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Can't parse package {input:?}: {reason}")]
+#[diagnostic(help(
+    "The format is `name[@version]`, `alias@npm:name[@version]`, a git repository, a directory or a tarball"
+))]
+pub struct InvalidPackageSpec {
+    input: String,
+    reason: String,
+    src: NamedSource,
+    #[snippet(src, message = "package")]
+    snip: SourceSpan,
+    #[highlight(snip, label = "here")]
+    highlight: SourceSpan,
+}
+
+impl InvalidPackageSpec {
+    fn new(input: &str, reason: impl Into<String>, highlight: &str) -> Self {
+        let offset = substring_offset(input, highlight);
+        Self {
+            input: input.to_string(),
+            reason: reason.into(),
+            src: NamedSource::new("package", input.to_string()),
+            snip: (0, input.len()).into(),
+            highlight: (offset, highlight.len().max(1)).into(),
+        }
+    }
 }
 
 impl NodePackageVersion {
+    /// The name the package is installed as
     pub fn name(&self) -> &str {
         &self.name
     }
 
-    pub fn version(&self) -> &str {
-        self.version.as_ref().map(|x| &x[..]).unwrap_or("latest")
+    pub fn source(&self) -> &PackageSource {
+        &self.source
+    }
+
+    /// Whether the name was guessed from a git, directory or tarball source,
+    /// and the real one has to be read from the package itself
+    pub fn has_inferred_name(&self) -> bool {
+        self.inferred_name
+    }
+
+    /// The same package, installed as `name`
+    pub fn with_name(self, name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            inferred_name: false,
+            ..self
+        }
+    }
+
+    /// The specifier to use for this package in the `dependencies` of a `package.json` file
+    pub fn version(&self) -> String {
+        match &self.source {
+            PackageSource::Registry(spec) => spec.to_string(),
+            PackageSource::Alias { package, spec } => format!("npm:{}@{}", package, spec),
+            PackageSource::Git { url, committish } => match committish {
+                Some(committish) => format!("{}#{}", url, committish),
+                None => url.clone(),
+            },
+            PackageSource::Directory(path) => format!("file:{}", path.display()),
+            PackageSource::Tarball(tarball) if is_url(tarball) => tarball.clone(),
+            PackageSource::Tarball(tarball) => format!("file:{}", tarball),
+        }
+    }
+
+    /// The range of versions that was requested from the registry,
+    /// when a version or a range was given instead of a tag
+    pub fn version_range(&self) -> Option<VersionRange> {
+        match &self.source {
+            PackageSource::Registry(RegistrySpec::Version(spec) | RegistrySpec::Range(spec))
+            | PackageSource::Alias {
+                spec: RegistrySpec::Version(spec) | RegistrySpec::Range(spec),
+                ..
+            } => VersionRange::parse(spec),
+            _ => None,
+        }
+    }
+
+    /// The name of the package in the registry, when it's fetched from one
+    pub fn registry_name(&self) -> Option<&str> {
+        match &self.source {
+            PackageSource::Registry(_) => Some(&self.name),
+            PackageSource::Alias { package, .. } => Some(package),
+            _ => None,
+        }
+    }
+
+    /// Whether the package is fetched from the registry, as opposed to git or local files
+    pub fn is_from_registry(&self) -> bool {
+        matches!(
+            self.source,
+            PackageSource::Registry(_) | PackageSource::Alias { .. }
+        )
+    }
+}
+
+impl std::fmt::Display for NodePackageVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.name, self.version())
+    }
+}
+
+impl std::fmt::Display for RegistrySpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tag(spec) | Self::Version(spec) | Self::Range(spec) => f.write_str(spec),
+        }
     }
 }
 
 impl FromStr for NodePackageVersion {
-    type Err = String;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.trim().split('@');
-        match (parts.next().map(str::trim), parts.next().map(str::trim)) {
-            (Some(""), Some(name)) => Ok(Self {
-                name: format!("@{}", name),
-                version: None,
-            }),
-            (Some(name), None) | (Some(name), Some("")) => Ok(Self {
-                name: name.into(),
-                version: None,
-            }),
-            (Some(name), Some(version)) => Ok(Self {
-                name: name.into(),
-                version: Some(version.into()),
-            }),
-            _ => Err(format!(
-                "Can't parse version {}. The format is {:?}",
-                &s, "name[@version]"
-            )),
+    type Err = InvalidPackageSpec;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let s = input.trim();
+        if s.is_empty() {
+            return Err(InvalidPackageSpec::new(input, "no package given", s));
+        }
+
+        if let Some(source) = parse_source(input, s)? {
+            let name = infer_name(&source)
+                .ok_or_else(|| InvalidPackageSpec::new(input, "can't infer a package name", s))?;
+            return Ok(Self {
+                name,
+                source,
+                inferred_name: true,
+            });
+        }
+
+        let (name, spec) = split_name(s);
+        let name = name.trim();
+        let spec = spec.map(str::trim).unwrap_or("");
+        validate_name(input, name)?;
+
+        let source = if let Some(aliased) = spec.strip_prefix("npm:") {
+            let (package, spec) = split_name(aliased.trim());
+            let package = package.trim();
+            validate_name(input, package)?;
+            PackageSource::Alias {
+                package: package.to_string(),
+                spec: parse_registry_spec(input, spec.map(str::trim).unwrap_or(""))?,
+            }
+        } else if let Some(source) = parse_source(input, spec)? {
+            source
+        } else {
+            PackageSource::Registry(parse_registry_spec(input, spec)?)
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            source,
+            inferred_name: false,
+        })
+    }
+}
+
+/// Splits `name@spec` into its parts, taking care of `@scope/name@spec`
+fn split_name(s: &str) -> (&str, Option<&str>) {
+    let search_from = if s.starts_with('@') {
+        s.find('/').unwrap_or(1)
+    } else {
+        0
+    };
+    match s[search_from..].find('@') {
+        Some(index) => {
+            let index = search_from + index;
+            (&s[..index], Some(&s[index + 1..]))
+        }
+        None => (s, None),
+    }
+}
+
+/// Parses the sources that aren't a registry package: git, directories and tarballs
+fn parse_source(input: &str, s: &str) -> Result<Option<PackageSource>, InvalidPackageSpec> {
+    if is_git(s) {
+        let (url, committish) = match s.split_once('#') {
+            Some((url, committish)) => (url, Some(committish.to_string())),
+            None => (s, None),
+        };
+        if url.is_empty() || committish.as_deref() == Some("") {
+            return Err(InvalidPackageSpec::new(input, "invalid git repository", s));
+        }
+        return Ok(Some(PackageSource::Git {
+            url: url.to_string(),
+            committish,
+        }));
+    }
+
+    if is_url(s) {
+        return Ok(Some(PackageSource::Tarball(s.to_string())));
+    }
+
+    let path = match s.strip_prefix("file:") {
+        Some(path) => path,
+        None if is_path(s) => s,
+        None => return Ok(None),
+    };
+    if path.is_empty() {
+        return Err(InvalidPackageSpec::new(input, "missing path", s));
+    }
+    let path = absolute_path(path);
+    if is_tarball_path(&path) {
+        Ok(Some(PackageSource::Tarball(path.display().to_string())))
+    } else {
+        Ok(Some(PackageSource::Directory(path)))
+    }
+}
+
+fn parse_registry_spec(input: &str, spec: &str) -> Result<RegistrySpec, InvalidPackageSpec> {
+    if spec.is_empty() {
+        return Ok(RegistrySpec::Tag("latest".to_string()));
+    }
+
+    let exact = spec.trim_start_matches('=').trim_start_matches('v');
+    if semver::Version::parse(exact).is_ok() {
+        return Ok(RegistrySpec::Version(exact.to_string()));
+    }
+
+    let is_tag = spec.starts_with(|c: char| c.is_ascii_alphabetic())
+        && !spec.starts_with(['x', 'X'])
+        && !(spec.starts_with('v') && spec[1..].starts_with(|c: char| c.is_ascii_digit()))
+        && spec
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._".contains(c));
+    if is_tag {
+        return Ok(RegistrySpec::Tag(spec.to_string()));
+    }
+
+    if VersionRange::parse(spec).is_some() {
+        return Ok(RegistrySpec::Range(spec.to_string()));
+    }
+
+    Err(InvalidPackageSpec::new(
+        input,
+        "not a valid version, range or tag",
+        spec,
+    ))
+}
+
+/// Validates a package name according to npm's rules
+fn validate_name(input: &str, name: &str) -> Result<(), InvalidPackageSpec> {
+    let error = |reason: &str| Err(InvalidPackageSpec::new(input, reason, name));
+
+    if name.is_empty() {
+        return error("missing package name");
+    }
+    if name.len() > 214 {
+        return error("package names can't be longer than 214 characters");
+    }
+
+    let unscoped = match name.strip_prefix('@') {
+        Some(scoped) => match scoped.split_once('/') {
+            Some((scope, package)) if !scope.is_empty() => {
+                validate_name_part(scope).map_or(Ok(()), error)?;
+                package
+            }
+            _ => return error("scoped packages look like `@scope/name`"),
+        },
+        None => name,
+    };
+    if unscoped.starts_with('.') || unscoped.starts_with('_') {
+        return error("package names can't start with `.` or `_`");
+    }
+    validate_name_part(unscoped).map_or(Ok(()), error)
+}
+
+/// Returns the reason a part of a package name is invalid, if it is
+fn validate_name_part(part: &str) -> Option<&'static str> {
+    if part.is_empty() {
+        Some("missing package name")
+    } else if part.chars().any(|c| c.is_ascii_uppercase()) {
+        Some("package names must be lowercase")
+    } else if !part
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c))
+    {
+        Some("package names can only contain URL-safe characters")
+    } else {
+        None
+    }
+}
+
+/// The name to install a package as, when it wasn't given explicitly
+fn infer_name(source: &PackageSource) -> Option<String> {
+    let name = match source {
+        PackageSource::Registry(_) | PackageSource::Alias { .. } => return None,
+        PackageSource::Git { url, .. } => url
+            .trim_end_matches('/')
+            .rsplit(['/', ':'])
+            .next()?
+            .trim_end_matches(".git")
+            .to_string(),
+        PackageSource::Directory(path) => path.file_name()?.to_string_lossy().to_string(),
+        PackageSource::Tarball(tarball) => {
+            let file_name = tarball.rsplit(['/', '\\']).next()?;
+            let stem = file_name
+                .trim_end_matches(".tgz")
+                .trim_end_matches(".tar.gz")
+                .trim_end_matches(".tar");
+            // strip a trailing version, like in `tool-1.2.0`
+            match stem.rfind('-') {
+                Some(index) if semver::Version::parse(&stem[index + 1..]).is_ok() => {
+                    stem[..index].to_string()
+                }
+                _ => stem.to_string(),
+            }
+        }
+    };
+    let name = name.to_lowercase();
+    if validate_name_part(&name).is_none() {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+fn is_git(s: &str) -> bool {
+    const PREFIXES: &[&str] = &[
+        "github:",
+        "gitlab:",
+        "bitbucket:",
+        "gist:",
+        "git+",
+        "git://",
+    ];
+    if PREFIXES.iter().any(|prefix| s.starts_with(prefix)) {
+        return true;
+    }
+    // `user/repo` is a shorthand for a GitHub repository
+    let repo = s.split('#').next().unwrap_or(s);
+    !s.starts_with('@')
+        && !is_path(s)
+        && !repo.contains(':')
+        && !repo.contains('@')
+        && repo.matches('/').count() == 1
+        && !repo.starts_with('/')
+        && !repo.ends_with('/')
+}
+
+fn is_url(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+fn is_path(s: &str) -> bool {
+    s == "."
+        || s == ".."
+        || s.starts_with("./")
+        || s.starts_with("../")
+        || s.starts_with('/')
+        || s.starts_with("~/")
+        || s.starts_with(".\\")
+        || s.starts_with("..\\")
+        || (s.len() > 2 && s.as_bytes()[1] == b':' && s[2..].starts_with(['\\', '/']))
+        || (is_tarball_path(Path::new(s)) && !s.contains('@'))
+}
+
+fn is_tarball_path(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    path.ends_with(".tgz") || path.ends_with(".tar.gz") || path.ends_with(".tar")
+}
+
+fn absolute_path(path: &str) -> PathBuf {
+    let path = match path.strip_prefix("~/") {
+        Some(relative) => dirs::home_dir().unwrap_or_default().join(relative),
+        None => PathBuf::from(path),
+    };
+    if path.is_absolute() {
+        path
+    } else {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let mut absolute = cwd;
+        for component in path.components() {
+            match component {
+                std::path::Component::CurDir => {}
+                std::path::Component::ParentDir => {
+                    absolute.pop();
+                }
+                component => absolute.push(component),
+            }
         }
+        absolute
+    }
+}
+
+/// The offset of `part` inside `whole`, when `part` is a subslice of it
+fn substring_offset(whole: &str, part: &str) -> usize {
+    let whole_start = whole.as_ptr() as usize;
+    let part_start = part.as_ptr() as usize;
+    if part_start >= whole_start && part_start <= whole_start + whole.len() {
+        part_start - whole_start
+    } else {
+        whole.find(part).unwrap_or(0)
     }
 }
 
@@ -46,39 +451,130 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    fn registry(name: &str, spec: RegistrySpec) -> NodePackageVersion {
+        NodePackageVersion {
+            name: name.into(),
+            source: PackageSource::Registry(spec),
+            inferred_name: false,
+        }
+    }
+
     #[test]
     fn no_version() {
         let version = NodePackageVersion::from_str("qnm").unwrap();
+        assert_eq!(version, registry("qnm", RegistrySpec::Tag("latest".into())));
+        assert_eq!(version.version(), "latest");
+    }
+
+    #[test]
+    fn version_with_tag() {
+        let version = NodePackageVersion::from_str("qnm@next").unwrap();
+        assert_eq!(version, registry("qnm", RegistrySpec::Tag("next".into())));
+    }
+
+    #[test]
+    fn with_spaces_in_version() {
+        let version = NodePackageVersion::from_str(" qnm @ 1.0.1 ").unwrap();
         assert_eq!(
             version,
-            NodePackageVersion {
-                name: "qnm".into(),
-                version: None
-            }
+            registry("qnm", RegistrySpec::Version("1.0.1".into()))
         );
     }
 
     #[test]
-    fn version_with_tag() {
-        let version = NodePackageVersion::from_str("qnm@next").unwrap();
+    fn scoped_with_version() {
+        let version = NodePackageVersion::from_str("@scope/pkg@1.2.3").unwrap();
         assert_eq!(
             version,
-            NodePackageVersion {
-                name: "qnm".into(),
-                version: Some("next".into())
-            }
+            registry("@scope/pkg", RegistrySpec::Version("1.2.3".into()))
+        );
+
+        let version = NodePackageVersion::from_str("@scope/pkg").unwrap();
+        assert_eq!(
+            version,
+            registry("@scope/pkg", RegistrySpec::Tag("latest".into()))
         );
     }
 
     #[test]
-    fn with_spaces_in_version() {
-        let version = NodePackageVersion::from_str(" qnm @ 1.0.1 ").unwrap();
+    fn ranges() {
+        let version = NodePackageVersion::from_str("qnm@>=1 <2").unwrap();
+        assert_eq!(
+            version,
+            registry("qnm", RegistrySpec::Range(">=1 <2".into()))
+        );
+
+        let version = NodePackageVersion::from_str("qnm@^1.2").unwrap();
+        assert_eq!(version, registry("qnm", RegistrySpec::Range("^1.2".into())));
+    }
+
+    #[test]
+    fn alias() {
+        let version = NodePackageVersion::from_str("ts4@npm:typescript@4").unwrap();
         assert_eq!(
             version,
             NodePackageVersion {
-                name: "qnm".into(),
-                version: Some("1.0.1".into())
+                name: "ts4".into(),
+                source: PackageSource::Alias {
+                    package: "typescript".into(),
+                    spec: RegistrySpec::Range("4".into()),
+                },
+                inferred_name: false,
             }
         );
+        assert_eq!(version.version(), "npm:typescript@4");
+    }
+
+    #[test]
+    fn git() {
+        let version = NodePackageVersion::from_str("github:user/my-tool#v1.0.0").unwrap();
+        assert_eq!(version.name(), "my-tool");
+        assert_eq!(
+            version.source(),
+            &PackageSource::Git {
+                url: "github:user/my-tool".into(),
+                committish: Some("v1.0.0".into()),
+            }
+        );
+
+        let version = NodePackageVersion::from_str("user/repo").unwrap();
+        assert_eq!(version.name(), "repo");
+        assert_eq!(version.version(), "user/repo");
+
+        let version =
+            NodePackageVersion::from_str("tool@git+https://example.com/tool.git").unwrap();
+        assert_eq!(version.name(), "tool");
+    }
+
+    #[test]
+    fn local_paths() {
+        let version = NodePackageVersion::from_str("/tmp/tools/my-tool").unwrap();
+        assert_eq!(version.name(), "my-tool");
+        assert!(version.has_inferred_name());
+        assert_eq!(version.version(), "file:/tmp/tools/my-tool");
+
+        let version = NodePackageVersion::from_str("file:/tmp/my-tool-1.2.0.tgz").unwrap();
+        assert_eq!(version.name(), "my-tool");
+        assert_eq!(
+            version.source(),
+            &PackageSource::Tarball("/tmp/my-tool-1.2.0.tgz".into())
+        );
+    }
+
+    #[test]
+    fn tarball_url() {
+        let version =
+            NodePackageVersion::from_str("https://example.com/my-tool-1.2.0.tgz").unwrap();
+        assert_eq!(version.name(), "my-tool");
+        assert_eq!(version.version(), "https://example.com/my-tool-1.2.0.tgz");
+    }
+
+    #[test]
+    fn invalid_names() {
+        assert!(NodePackageVersion::from_str("Qnm").is_err());
+        assert!(NodePackageVersion::from_str("_qnm").is_err());
+        assert!(NodePackageVersion::from_str("@scope").is_err());
+        assert!(NodePackageVersion::from_str("qnm!@1").is_err());
+        assert!(NodePackageVersion::from_str("qnm@1.0.0 <<>> 2").is_err());
     }
 }
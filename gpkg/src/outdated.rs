@@ -0,0 +1,195 @@
+use crate::index::Index;
+use crate::install_package::{package_installations, package_version_in};
+use crate::node_package_version::{NodePackageVersion, PackageSource};
+use crate::registry::{self, Registry};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// An installation of a package that came from the registry
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegistryInstallation {
+    /// The name the package is installed as
+    pub name: String,
+    /// The name of the package in the registry, which differs for aliases
+    pub registry_name: String,
+    /// What was requested after `name@`, unknown for versions without binaries
+    pub requested: Option<String>,
+    /// The registry gpkg downloaded the package from, when it talked to it directly
+    pub registry: Option<String>,
+    pub current: Option<String>,
+    pub path: PathBuf,
+}
+
+/// How an installation compares to the versions in the registry
+#[derive(Debug, Serialize, PartialEq)]
+pub struct OutdatedPackage {
+    pub name: String,
+    /// The installed version
+    pub current: Option<String>,
+    /// The highest version matching what was requested when the package was installed
+    pub wanted: Option<String>,
+    /// The version of the `latest` dist-tag
+    pub latest: Option<String>,
+    pub requested: Option<String>,
+    pub path: PathBuf,
+}
+
+impl OutdatedPackage {
+    /// Whether a newer version is wanted, or available under the `latest` tag
+    pub fn is_outdated(&self) -> bool {
+        let is_behind = |version: &Option<String>| version.is_some() && version != &self.current;
+        is_behind(&self.wanted) || is_behind(&self.latest)
+    }
+}
+
+/// Every installation of a package from the registry, including versions installed side by side.
+/// Linked packages and packages from git or local files are skipped.
+pub fn registry_installations(
+    installation_dir: impl AsRef<Path>,
+    bin_dir: impl AsRef<Path>,
+) -> std::io::Result<Vec<RegistryInstallation>> {
+    let binaries = Index::load(&installation_dir, bin_dir)?.into_binaries();
+    let package_names: BTreeSet<_> = binaries
+        .iter()
+        .filter(|metadata| !metadata.linked && metadata.source.is_none())
+        .map(|metadata| metadata.package_name.as_str())
+        .collect();
+
+    let mut installations = vec![];
+    for package_name in package_names {
+        for installation in package_installations(&installation_dir, package_name)? {
+            let metadata = binaries.iter().find(|metadata| {
+                metadata.package_name == package_name
+                    && metadata.installation_path(&installation_dir) == installation.path
+            });
+            let requested = metadata.and_then(|metadata| metadata.requested.clone());
+            let registry_name = match requested_source(package_name, requested.as_deref()) {
+                Some(PackageSource::Registry(_)) | None => package_name.to_string(),
+                Some(PackageSource::Alias { package, .. }) => package,
+                Some(_) => continue,
+            };
+            installations.push(RegistryInstallation {
+                name: package_name.to_string(),
+                registry_name,
+                requested,
+                registry: metadata.and_then(|metadata| metadata.registry.clone()),
+                current: package_version_in(&installation.path, package_name),
+                path: installation.path,
+            });
+        }
+    }
+    Ok(installations)
+}
+
+fn requested_source(package_name: &str, requested: Option<&str>) -> Option<PackageSource> {
+    let requested: NodePackageVersion = format!("{}@{}", package_name, requested?).parse().ok()?;
+    Some(requested.source().clone())
+}
+
+/// Compares an installation with the versions of the package in `registry`
+pub fn check_outdated(
+    installation: &RegistryInstallation,
+    registry: &Registry,
+) -> Result<OutdatedPackage, registry::Errors> {
+    let packument = registry.packument(&installation.registry_name)?;
+    let wanted = match requested_source(&installation.name, installation.requested.as_deref()) {
+        Some(PackageSource::Registry(spec) | PackageSource::Alias { spec, .. }) => packument
+            .resolve(&spec.to_string())
+            .ok()
+            .map(|version| version.version.clone()),
+        _ => None,
+    };
+    Ok(OutdatedPackage {
+        name: installation.name.clone(),
+        current: installation.current.clone(),
+        wanted,
+        latest: packument.dist_tags.get("latest").cloned(),
+        requested: installation.requested.clone(),
+        path: installation.path.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::update_index;
+    use crate::install_package::{create_binary, installation_path, InstallationInfo};
+    use crate::node::NodeBinary;
+    use crate::package_manager::PackageManagerKind;
+    use crate::test_registry::{TestPackage, TestRegistry};
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    fn install(installation_dir: &Path, bin_dir: &Path, name: &str, requested: &str) {
+        let path = installation_path(installation_dir, name);
+        let package_dir = path.join("node_modules").join(name);
+        std::fs::create_dir_all(&package_dir).unwrap();
+        std::fs::write(
+            package_dir.join("package.json"),
+            format!(
+                r#"{{"name": "{}", "version": "1.0.0", "bin": "cli.js"}}"#,
+                name
+            ),
+        )
+        .unwrap();
+        let node = NodeBinary {
+            path: PathBuf::from("/usr/bin/node"),
+            version: "v14.0.0".to_string(),
+        };
+        let installation = InstallationInfo {
+            package_name: name,
+            node: &node,
+            package_manager: PackageManagerKind::Npm,
+            package_version: Some("1.0.0".to_string()),
+            installation: None,
+            aliases: Default::default(),
+            requested: Some(requested.to_string()),
+            source: None,
+            registry: None,
+            integrity: None,
+        };
+        create_binary(name, &installation, &path, bin_dir).unwrap();
+        update_index(installation_dir, bin_dir, [name]).unwrap();
+    }
+
+    #[test]
+    fn compares_installations_with_the_registry() {
+        let registry = TestRegistry::new();
+        for version in ["1.0.0", "1.1.0", "2.0.0"] {
+            registry.publish(TestPackage::new("tool", version));
+        }
+        registry.publish(TestPackage::new("fresh", "1.0.0"));
+        let installation_dir = TempDir::new("installations").unwrap();
+        let bin_dir = TempDir::new("bin").unwrap();
+        install(installation_dir.path(), bin_dir.path(), "tool", "^1");
+        install(installation_dir.path(), bin_dir.path(), "fresh", "latest");
+        install(installation_dir.path(), bin_dir.path(), "unpublished", "1");
+
+        let installations =
+            registry_installations(installation_dir.path(), bin_dir.path()).unwrap();
+        assert_eq!(installations.len(), 3);
+        let outdated: Vec<_> = installations
+            .iter()
+            .map(|installation| check_outdated(installation, &registry.registry()))
+            .collect();
+
+        let fresh = outdated[0].as_ref().unwrap();
+        assert_eq!(fresh.name, "fresh");
+        assert!(!fresh.is_outdated());
+        let tool = outdated[1].as_ref().unwrap();
+        assert_eq!(
+            (
+                tool.current.as_deref(),
+                tool.wanted.as_deref(),
+                tool.latest.as_deref()
+            ),
+            (Some("1.0.0"), Some("1.1.0"), Some("2.0.0"))
+        );
+        assert!(tool.is_outdated());
+        assert!(matches!(
+            outdated[2],
+            Err(registry::Errors::PackageNotFound { .. })
+        ));
+    }
+}
@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PackageRoot {
@@ -12,3 +12,110 @@ pub struct PackageRoot {
 pub struct PackageEngines {
     pub node: String,
 }
+
+/// The `bin` field of a `package.json` file
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum PackageBinary {
+    Single(String),
+    Multiple(HashMap<String, String>),
+}
+
+impl PackageBinary {
+    /// The binaries of a package named `package_name`, by binary name.
+    /// A single binary is named after the package, without its scope.
+    pub fn binaries(&self, package_name: &str) -> HashMap<String, String> {
+        match self {
+            Self::Single(path) => {
+                let name = package_name.rsplit('/').next().unwrap_or(package_name);
+                let mut bin = HashMap::new();
+                bin.insert(name.to_string(), path.clone());
+                bin
+            }
+            Self::Multiple(bin) => bin.clone(),
+        }
+    }
+}
+
+/// The parts of an installed package's `package.json` that gpkg cares about
+#[derive(Debug, Deserialize)]
+pub struct PackageManifest {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub bin: Option<PackageBinary>,
+    #[serde(default)]
+    pub main: Option<String>,
+    /// The files packed into the package's tarball, as `.gitignore`-like patterns
+    #[serde(default)]
+    pub files: Option<Vec<String>>,
+    #[serde(default)]
+    pub dependencies: HashMap<String, String>,
+    /// Dependencies the package works without, like binaries for other platforms
+    #[serde(default, rename = "optionalDependencies")]
+    pub optional_dependencies: HashMap<String, String>,
+    #[serde(default)]
+    pub os: Vec<String>,
+    #[serde(default)]
+    pub cpu: Vec<String>,
+    #[serde(default)]
+    pub scripts: HashMap<String, String>,
+    /// Whether a `binding.gyp` file is built with `node-gyp` when there's no install script
+    #[serde(default)]
+    pub gypfile: Option<bool>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub homepage: Option<String>,
+    /// An SPDX expression, or a `{"type": ...}` object in older packages
+    #[serde(default)]
+    license: Option<serde_json::Value>,
+}
+
+impl PackageManifest {
+    pub fn license(&self) -> Option<String> {
+        match self.license.as_ref()? {
+            serde_json::Value::String(license) => Some(license.clone()),
+            license => license.get("type")?.as_str().map(str::to_string),
+        }
+    }
+}
+
+/// A `package-lock.json` file, in the `lockfileVersion` 3 format of npm
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageLock {
+    pub name: String,
+    pub lockfile_version: u32,
+    pub requires: bool,
+    /// The locked packages, keyed by their path like `node_modules/a/node_modules/b`.
+    /// The root package is keyed by an empty path.
+    pub packages: BTreeMap<String, LockedPackage>,
+}
+
+/// A package in a [`PackageLock`]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LockedPackage {
+    /// The name of the package, when it differs from its directory, like for aliases
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub optional_dependencies: BTreeMap<String, String>,
+    /// Whether the package is only required as an optional dependency,
+    /// so it can be left out on platforms it doesn't support
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub optional: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub os: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cpu: Vec<String>,
+}
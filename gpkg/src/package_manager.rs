@@ -0,0 +1,535 @@
+use crate::native_installer::Native;
+use crate::node::NodeBinary;
+use crate::registry;
+use crate::registry_config::RegistryConfig;
+use crate::tarball_cache::TarballCache;
+use log::*;
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use thiserror::Error;
+
+/// A tool that installs the dependencies of a `package.json` file
+pub trait PackageManager {
+    /// Installs the dependencies of the `package.json` in `cwd`
+    fn install(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors>;
+
+    /// Installs exactly the dependency tree locked in the lockfile next to the `package.json` in `cwd`,
+    /// failing when the lockfile doesn't match the `package.json`
+    fn install_frozen(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors>;
+
+    /// Recompiles the native addons of the packages installed in `cwd` against `node`
+    fn rebuild(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors>;
+
+    /// The directory where the binaries of the packages installed in `cwd` are linked
+    fn bin_dir(&self, cwd: &Path) -> PathBuf {
+        cwd.join("node_modules").join(".bin")
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Errors {
+    #[error("Can't run {program:?}")]
+    #[diagnostic(help("Make sure {} is installed and available in your PATH", program))]
+    Spawn {
+        program: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("`{command}` failed with {status}")]
+    #[diagnostic(help("Check the output above for more information"))]
+    Failed {
+        command: String,
+        status: std::process::ExitStatus,
+    },
+
+    #[error("Can't access {path:?}")]
+    #[diagnostic()]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Can't parse {path:?}")]
+    #[diagnostic()]
+    InvalidManifest {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Registry(#[from] registry::Errors),
+
+    #[error("Can't install {name}@{spec} using the native installer")]
+    #[diagnostic(help("Only registry packages are supported. Try another `--package-manager`"))]
+    UnsupportedSpec { name: String, spec: String },
+
+    #[error("Can't install from {path:?}: {reason}")]
+    #[diagnostic(help(
+        "The lockfile has to describe the dependencies in package.json exactly. \
+         Install the package without the lockfile to resolve its dependencies again"
+    ))]
+    FrozenLockfile { path: PathBuf, reason: String },
+
+    #[error("Can't install the dependency {name:?}, which would be placed outside node_modules")]
+    #[diagnostic(help(
+        "Package names are `name` or `@scope/name`, without `..` or other path components"
+    ))]
+    InvalidDependencyName { name: String },
+
+    #[error("Can't read the local tarball {path:?}, which is outside of {cwd:?}")]
+    #[diagnostic(help(
+        "Local `file:` tarballs have to be inside the installation, where gpkg copies them"
+    ))]
+    TarballOutside { path: String, cwd: PathBuf },
+
+    #[error("{name}@{version} doesn't support this platform")]
+    #[diagnostic(help(
+        "The package lists the operating systems and CPUs it runs on in its `os` and `cpu` fields"
+    ))]
+    UnsupportedPlatform { name: String, version: String },
+
+    #[error("{package} has an unsafe binary {name:?}, pointing to {path:?}")]
+    #[diagnostic(help(
+        "Binary names can't contain path separators or `..`, \
+         and binaries have to be inside their package"
+    ))]
+    UnsafeBinary {
+        package: String,
+        name: String,
+        path: String,
+    },
+
+    #[error("Can't set up PATH for {node:?}")]
+    #[diagnostic()]
+    InvalidPath {
+        node: PathBuf,
+        #[source]
+        source: std::env::JoinPathsError,
+    },
+}
+
+/// The package managers gpkg knows how to use
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageManagerKind {
+    #[default]
+    Npm,
+    Pnpm,
+    Yarn,
+    Bun,
+    /// gpkg's own installer, which talks to the registry directly
+    Native,
+}
+
+impl PackageManagerKind {
+    pub fn variants() -> &'static [&'static str] {
+        &["npm", "pnpm", "yarn", "bun", "native"]
+    }
+
+    /// The lockfiles the package manager may write next to the `package.json`, newest format first
+    pub fn lockfile_names(self) -> &'static [&'static str] {
+        match self {
+            Self::Npm | Self::Native => &["package-lock.json"],
+            Self::Pnpm => &["pnpm-lock.yaml"],
+            Self::Yarn => &["yarn.lock"],
+            Self::Bun => &["bun.lock", "bun.lockb"],
+        }
+    }
+
+    pub fn package_manager(self) -> Box<dyn PackageManager> {
+        self.installer(&RegistryConfig::default(), None)
+    }
+
+    /// The package manager, installing from `registries`.
+    /// Spawned package managers read them from the `.npmrc` file written next to the `package.json`,
+    /// and only the native installer goes through gpkg's tarball `cache`.
+    pub fn installer(
+        self,
+        registries: &RegistryConfig,
+        cache: Option<&TarballCache>,
+    ) -> Box<dyn PackageManager> {
+        match self {
+            Self::Npm => Box::new(Npm),
+            Self::Pnpm => Box::new(Pnpm),
+            Self::Yarn => Box::new(Yarn::new(registries.clone())),
+            Self::Bun => Box::new(Bun),
+            Self::Native => Box::new(Native::new(registries.clone(), cache.cloned())),
+        }
+    }
+}
+
+impl std::str::FromStr for PackageManagerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "npm" => Ok(Self::Npm),
+            "pnpm" => Ok(Self::Pnpm),
+            "yarn" => Ok(Self::Yarn),
+            "bun" => Ok(Self::Bun),
+            "native" => Ok(Self::Native),
+            name => Err(format!("I don't know a package manager called {:?}", name)),
+        }
+    }
+}
+
+impl std::fmt::Display for PackageManagerKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Npm => "npm",
+            Self::Pnpm => "pnpm",
+            Self::Yarn => "yarn",
+            Self::Bun => "bun",
+            Self::Native => "native",
+        };
+        f.write_str(name)
+    }
+}
+
+pub struct Npm;
+
+impl PackageManager for Npm {
+    fn install(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        run(cwd, node, "npm", &["install"])
+    }
+
+    fn install_frozen(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        run(cwd, node, "npm", &["ci"])
+    }
+
+    fn rebuild(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        run(cwd, node, "npm", &["rebuild"])
+    }
+}
+
+pub struct Pnpm;
+
+impl PackageManager for Pnpm {
+    fn install(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        run(cwd, node, "pnpm", &["install"])
+    }
+
+    fn install_frozen(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        run(cwd, node, "pnpm", &["install", "--frozen-lockfile"])
+    }
+
+    fn rebuild(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        run(cwd, node, "pnpm", &["rebuild"])
+    }
+}
+
+/// Yarn, either classic (1.x) or Berry (2+).
+/// Berry ignores `.npmrc` and installs into Plug'n'Play by default,
+/// so it gets a `.yarnrc.yml` with the configured registries and a `node_modules` linker
+pub struct Yarn {
+    registries: RegistryConfig,
+}
+
+impl Yarn {
+    pub fn new(registries: RegistryConfig) -> Self {
+        Self { registries }
+    }
+
+    /// Whether the yarn running in `cwd` is Berry, writing its `.yarnrc.yml` when it is
+    fn prepare_berry(&self, cwd: &Path, node: &NodeBinary) -> Result<bool, Errors> {
+        let version = output(cwd, node, "yarn", &["--version"])?;
+        if !is_berry(&version) {
+            return Ok(false);
+        }
+        debug!("Using yarn {} with the node-modules linker", version.trim());
+        let path = cwd.join(".yarnrc.yml");
+        std::fs::write(&path, yarnrc(&self.registries))
+            .map_err(|source| Errors::Io { path, source })?;
+        Ok(true)
+    }
+}
+
+impl PackageManager for Yarn {
+    fn install(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        self.prepare_berry(cwd, node)?;
+        run(cwd, node, "yarn", &["install"])
+    }
+
+    fn install_frozen(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        if self.prepare_berry(cwd, node)? {
+            run(cwd, node, "yarn", &["install", "--immutable"])
+        } else {
+            run(cwd, node, "yarn", &["install", "--frozen-lockfile"])
+        }
+    }
+
+    /// Yarn classic has no `rebuild` command, so we force it to relink and rebuild everything
+    fn rebuild(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        if self.prepare_berry(cwd, node)? {
+            run(cwd, node, "yarn", &["rebuild"])
+        } else {
+            run(cwd, node, "yarn", &["install", "--force"])
+        }
+    }
+}
+
+/// Whether `version`, as printed by `yarn --version`, is a Berry release
+fn is_berry(version: &str) -> bool {
+    let major = version.trim().split('.').next().unwrap_or_default();
+    major.parse::<u64>().is_ok_and(|major| major >= 2)
+}
+
+/// The `.yarnrc.yml` making Yarn Berry install into `node_modules` from the configured `registries`.
+/// Like in `.npmrc`, tokens are written as `${VAR}` references.
+fn yarnrc(registries: &RegistryConfig) -> String {
+    let quote = |value: &str| serde_json::to_string(value).unwrap();
+    let mut lines = vec!["nodeLinker: node-modules".to_string()];
+    if let Some(registry) = &registries.registry {
+        lines.push(format!("npmRegistryServer: {}", quote(registry)));
+    }
+    if !registries.scopes.is_empty() {
+        lines.push("npmScopes:".to_string());
+        for (scope, registry) in &registries.scopes {
+            lines.push(format!("  {}:", quote(scope.trim_start_matches('@'))));
+            lines.push(format!("    npmRegistryServer: {}", quote(registry)));
+        }
+    }
+    if !registries.registries.is_empty() {
+        lines.push("npmRegistries:".to_string());
+        for (registry, auth) in &registries.registries {
+            lines.push(format!("  {}:", quote(registry.trim_end_matches('/'))));
+            let token = format!("${{{}}}", auth.token_env);
+            lines.push(format!("    npmAuthToken: {}", quote(&token)));
+        }
+    }
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+pub struct Bun;
+
+impl PackageManager for Bun {
+    fn install(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        run(cwd, node, "bun", &["install"])
+    }
+
+    fn install_frozen(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        run(cwd, node, "bun", &["install", "--frozen-lockfile"])
+    }
+
+    /// Bun has no `rebuild` command, so we force it to reinstall everything
+    fn rebuild(&self, cwd: &Path, node: &NodeBinary) -> Result<(), Errors> {
+        run(cwd, node, "bun", &["install", "--force"])
+    }
+}
+
+/// Runs `program` in `cwd`, with the directory of `node` first in the `PATH`,
+/// so the package manager and the lifecycle scripts it runs use the requested node
+fn run(cwd: &Path, node: &NodeBinary, program: &str, args: &[&str]) -> Result<(), Errors> {
+    let (mut command, command_line) = command(cwd, node, program, args)?;
+    debug!("Running `{}` in {:?}", command_line, cwd);
+
+    let status = command
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .map_err(|source| Errors::Spawn {
+            program: program.to_string(),
+            source,
+        })?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Errors::Failed {
+            command: command_line,
+            status,
+        })
+    }
+}
+
+/// Runs `program` like [`run`] does, returning what it printed
+fn output(cwd: &Path, node: &NodeBinary, program: &str, args: &[&str]) -> Result<String, Errors> {
+    let (mut command, command_line) = command(cwd, node, program, args)?;
+    debug!("Reading the output of `{}` in {:?}", command_line, cwd);
+
+    let output = command
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|source| Errors::Spawn {
+            program: program.to_string(),
+            source,
+        })?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(Errors::Failed {
+            command: command_line,
+            status: output.status,
+        })
+    }
+}
+
+/// The command running `program` with `args` in `cwd`, and its command line
+fn command(
+    cwd: &Path,
+    node: &NodeBinary,
+    program: &str,
+    args: &[&str],
+) -> Result<(Command, String), Errors> {
+    let path = path_with_node(node)?;
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(program);
+        command
+    } else {
+        Command::new(program)
+    };
+    command.args(args).current_dir(cwd).env("PATH", path);
+    let command_line = std::iter::once(program)
+        .chain(args.iter().copied())
+        .collect::<Vec<_>>()
+        .join(" ");
+    Ok((command, command_line))
+}
+
+/// The current `PATH`, with the directory of `node` in front of it
+fn path_with_node(node: &NodeBinary) -> Result<std::ffi::OsString, Errors> {
+    let current_path = std::env::var_os("PATH").unwrap_or_default();
+    let paths =
+        std::iter::once(node.dir().to_path_buf()).chain(std::env::split_paths(&current_path));
+    std::env::join_paths(paths).map_err(|source| Errors::InvalidPath {
+        node: node.path.clone(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry_config::RegistryAuth;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    #[test]
+    fn parses_and_prints_package_managers() {
+        for name in PackageManagerKind::variants() {
+            let kind: PackageManagerKind = name.parse().unwrap();
+            assert_eq!(&kind.to_string(), name);
+        }
+        assert!("cargo".parse::<PackageManagerKind>().is_err());
+        assert_eq!(PackageManagerKind::default(), PackageManagerKind::Npm);
+        assert_eq!(
+            PackageManagerKind::Native.lockfile_names(),
+            &["package-lock.json"]
+        );
+    }
+
+    #[test]
+    fn detects_yarn_berry() {
+        assert!(!is_berry("1.22.19\n"));
+        assert!(is_berry("3.6.4\n"));
+        assert!(is_berry("4.0.0-rc.42"));
+        assert!(!is_berry(""));
+    }
+
+    #[test]
+    fn writes_yarnrc_with_registries() {
+        let mut registries = RegistryConfig::with_registry("https://registry.example.com");
+        registries
+            .scopes
+            .insert("@acme".to_string(), "https://npm.acme.dev/".to_string());
+        registries.registries.insert(
+            "https://npm.acme.dev/".to_string(),
+            RegistryAuth {
+                token_env: "ACME_NPM_TOKEN".to_string(),
+            },
+        );
+        assert_eq!(
+            yarnrc(&registries),
+            "nodeLinker: node-modules\n\
+             npmRegistryServer: \"https://registry.example.com\"\n\
+             npmScopes:\n  \"acme\":\n    npmRegistryServer: \"https://npm.acme.dev/\"\n\
+             npmRegistries:\n  \"https://npm.acme.dev\":\n    npmAuthToken: \"${ACME_NPM_TOKEN}\"\n"
+        );
+        assert_eq!(
+            yarnrc(&RegistryConfig::default()),
+            "nodeLinker: node-modules\n"
+        );
+    }
+
+    #[test]
+    fn reports_missing_programs() {
+        let cwd = TempDir::new("cwd").unwrap();
+        let node = NodeBinary {
+            path: cwd.path().join("node"),
+            version: "16.0.0".to_string(),
+        };
+        let error = run(cwd.path(), &node, "gpkg-missing-program", &[]).unwrap_err();
+        assert!(matches!(error, Errors::Spawn { .. }), "{:?}", error);
+    }
+
+    /// A node directory with a fake `yarn` of the given version, recording its arguments in `args`
+    #[cfg(unix)]
+    fn fake_yarn(version: &str) -> (TempDir, NodeBinary) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("node").unwrap();
+        let yarn = dir.path().join("yarn");
+        std::fs::write(
+            &yarn,
+            format!(
+                "#!/bin/sh\n\
+                 [ \"$1\" = --version ] && echo {} && exit 0\n\
+                 echo \"$@\" >> args\n\
+                 [ \"$1\" != fail ]\n",
+                version
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&yarn, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let node = NodeBinary {
+            path: dir.path().join("node"),
+            version: "16.0.0".to_string(),
+        };
+        (dir, node)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_yarn_classic_and_berry() {
+        let yarn = Yarn::new(RegistryConfig::default());
+
+        let (_classic_dir, classic) = fake_yarn("1.22.19");
+        let cwd = TempDir::new("cwd").unwrap();
+        yarn.install_frozen(cwd.path(), &classic).unwrap();
+        yarn.rebuild(cwd.path(), &classic).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(cwd.path().join("args")).unwrap(),
+            "install --frozen-lockfile\ninstall --force\n"
+        );
+        assert!(!cwd.path().join(".yarnrc.yml").exists());
+
+        let (_berry_dir, berry) = fake_yarn("4.1.0");
+        let cwd = TempDir::new("cwd").unwrap();
+        yarn.install(cwd.path(), &berry).unwrap();
+        yarn.install_frozen(cwd.path(), &berry).unwrap();
+        yarn.rebuild(cwd.path(), &berry).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(cwd.path().join("args")).unwrap(),
+            "install\ninstall --immutable\nrebuild\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(cwd.path().join(".yarnrc.yml")).unwrap(),
+            "nodeLinker: node-modules\n"
+        );
+
+        let error = run(cwd.path(), &berry, "yarn", &["fail"]).unwrap_err();
+        assert!(
+            matches!(&error, Errors::Failed { command, .. } if command == "yarn fail"),
+            "{:?}",
+            error
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use crate::collision::CollisionPolicy;
+use crate::file_manifest::record_manifest;
+use crate::index::{update_index, Index};
+use crate::install_package::{create_binary, installation_path, Errors, InstallationInfo};
+use crate::link_package::link_package;
+use crate::node::{version_matches, NodeBinary};
+use crate::storage::LatestMetadata;
+use log::*;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// The packages with a binary pinned to a node version matching `node_version`, like `v18`
+pub fn packages_pinned_to(binaries: &[LatestMetadata], node_version: &str) -> BTreeSet<String> {
+    binaries
+        .iter()
+        .filter(|metadata| version_matches(node_version, &metadata.node_version))
+        .map(|metadata| metadata.package_name.clone())
+        .collect()
+}
+
+/// Pins all the binaries of an installed package to another node binary.
+///
+/// Rebuilds the installation first, using the package manager that installed it,
+/// so native addons are compiled against the new node, and records its file manifest again. Linked packages are relinked.
+/// Returns the names of the binaries that were rebound.
+pub fn rebind_package<InstallationDir: AsRef<Path>, BinDir: AsRef<Path>>(
+    package_name: &str,
+    node: &NodeBinary,
+    installation_dir: InstallationDir,
+    bin_dir: BinDir,
+) -> Result<Vec<String>, Errors> {
+    let binaries: Vec<_> = Index::load(&installation_dir, &bin_dir)?
+        .into_binaries()
+        .into_iter()
+        .filter(|metadata| metadata.package_name == package_name)
+        .collect();
+    if let Some(package_dir) = binaries.first().and_then(|metadata| metadata.linked_dir()) {
+        debug!("Relinking {:?} to node {}", package_dir, node.version);
+        let linked = link_package(
+            &package_dir,
+            node,
+            installation_dir,
+            bin_dir,
+            &CollisionPolicy::Keep,
+        )?;
+        return Ok(linked.binaries);
+    }
+
+    // every installation the binaries run, which includes versions installed side by side
+    let mut target_paths: Vec<_> = binaries
+        .iter()
+        .map(|metadata| metadata.installation_path(&installation_dir))
+        .collect();
+    target_paths.sort();
+    target_paths.dedup();
+    if target_paths.is_empty() {
+        target_paths.push(installation_path(&installation_dir, package_name));
+    }
+    if !target_paths.iter().all(|target_path| target_path.exists()) {
+        return Err(Errors::PackageNotInstalled(package_name.to_string()));
+    }
+
+    let package_manager = binaries
+        .first()
+        .map(|metadata| metadata.package_manager)
+        .unwrap_or_default();
+
+    for target_path in &target_paths {
+        debug!(
+            "Rebuilding {:?} using {} and node {}",
+            target_path, package_manager, node.version
+        );
+        package_manager
+            .package_manager()
+            .rebuild(target_path, node)?;
+        record_manifest(target_path, package_manager)?;
+    }
+
+    let mut rebound = vec![];
+    for metadata in binaries {
+        debug!(
+            "Rebinding {} from node {} to {}",
+            metadata.binary_name, metadata.node_version, node.version
+        );
+        let installation = InstallationInfo::from_metadata(&metadata, node);
+        let target_path = metadata.installation_path(&installation_dir);
+        create_binary(&metadata.binary_name, &installation, target_path, &bin_dir)?;
+        rebound.push(metadata.binary_name);
+    }
+    update_index(installation_dir, bin_dir, &rebound)?;
+
+    Ok(rebound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install_package::{install_package, InstallOptions};
+    use crate::node_package_version::NodePackageVersion;
+    use crate::package_manager::PackageManagerKind;
+    use crate::storage::Metadata;
+    use crate::test_registry::{TestPackage, TestRegistry};
+    use pretty_assertions::assert_eq;
+    use std::str::FromStr;
+
+    #[test]
+    fn rebinds_binaries_to_another_node() {
+        let registry = TestRegistry::new();
+        let bin = [("tool", "#!/usr/bin/env node\nconsole.log('tool')")];
+        registry
+            .publish(TestPackage {
+                bin: &bin,
+                ..TestPackage::new("tool", "1.0.0")
+            })
+            .publish(TestPackage {
+                bin: &[("other", "#!/usr/bin/env node")],
+                ..TestPackage::new("other", "1.0.0")
+            });
+        let installation_dir = tempdir::TempDir::new("installations").unwrap();
+        let bin_dir = tempdir::TempDir::new("bin").unwrap();
+        let node = NodeBinary::current().expect("Can't find node");
+        for package in ["tool", "other"] {
+            install_package(
+                &NodePackageVersion::from_str(package).unwrap(),
+                &node,
+                installation_dir.path(),
+                bin_dir.path(),
+                &InstallOptions {
+                    package_manager: PackageManagerKind::Native,
+                    registries: registry.config(),
+                    ..InstallOptions::default()
+                },
+            )
+            .expect("Can't install");
+        }
+
+        let other_node_dir = tempdir::TempDir::new("node").unwrap();
+        let other_node = NodeBinary {
+            path: other_node_dir.path().join("node"),
+            version: "v99.1.0".to_string(),
+        };
+        let rebound = rebind_package("tool", &other_node, installation_dir.path(), bin_dir.path())
+            .expect("Can't rebind");
+        assert_eq!(rebound, vec!["tool"]);
+
+        let script = std::fs::read_to_string(bin_dir.path().join("tool")).unwrap();
+        assert!(script.contains(&format!("export PATH={:?}:$PATH", other_node_dir.path())));
+        let metadata = Metadata::read(bin_dir.path().join("tool"))
+            .unwrap()
+            .latest();
+        assert_eq!(metadata.node_path.as_ref(), Some(&other_node.path));
+        assert_eq!(metadata.node_version, "v99.1.0");
+        let other = Metadata::read(bin_dir.path().join("other"))
+            .unwrap()
+            .latest();
+        assert_eq!(other.node_version, node.version);
+
+        // the index is updated, and `--from` picks the packages by their new node
+        let binaries = Index::read(installation_dir.path())
+            .expect("The index is missing")
+            .into_binaries();
+        assert_eq!(
+            packages_pinned_to(&binaries, "v99"),
+            std::iter::once("tool".to_string()).collect()
+        );
+        assert_eq!(
+            packages_pinned_to(&binaries, &node.version),
+            std::iter::once("other".to_string()).collect()
+        );
+        assert!(packages_pinned_to(&binaries, "v99.2").is_empty());
+
+        assert!(matches!(
+            rebind_package(
+                "missing",
+                &other_node,
+                installation_dir.path(),
+                bin_dir.path()
+            ),
+            Err(Errors::PackageNotInstalled(_))
+        ));
+    }
+}
@@ -0,0 +1,379 @@
+use crate::version_range::VersionRange;
+use log::*;
+use miette::Diagnostic;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+use thiserror::Error;
+
+pub const DEFAULT_REGISTRY: &str = "https://registry.npmjs.org";
+
+/// An npm registry.
+///
+/// Can be either a remote registry (`https://registry.npmjs.org`) or a local directory
+/// standing in for one, containing a `<package name>.json` packument for every package.
+/// Tarball URLs in a local registry can be relative to its directory.
+/// Requests to a remote registry are authenticated with its token, when it has one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Registry {
+    Remote { url: String, token: Option<String> },
+    Local(PathBuf),
+}
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Errors {
+    #[error("Can't fetch {url}")]
+    #[diagnostic(help("Check your network connection and the registry URL"))]
+    Http {
+        url: String,
+        #[source]
+        source: Box<ureq::Error>,
+    },
+
+    #[error("Can't read {path:?}")]
+    #[diagnostic()]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Package {name:?} was not found in the registry")]
+    #[diagnostic(help("Check the spelling of the package name"))]
+    PackageNotFound { name: String },
+
+    #[error("{name} is not in the tarball cache")]
+    #[diagnostic(help(
+        "Install it once without `--offline`, using `--package-manager native`, to cache it"
+    ))]
+    NotCached { name: String },
+
+    #[error("Can't parse the registry response for {name:?}")]
+    #[diagnostic()]
+    InvalidPackument {
+        name: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("No version of {name:?} matches {spec:?}")]
+    #[diagnostic(help("Available versions: {}", available.join(", ")))]
+    NoMatchingVersion {
+        name: String,
+        spec: String,
+        available: Vec<String>,
+    },
+
+    #[error("{name}@{version} has the tarball {tarball:?}, which isn't an HTTP URL")]
+    #[diagnostic(help(
+        "Remote registries can only point to tarballs over HTTP(S), never to local files"
+    ))]
+    UnsupportedTarball {
+        name: String,
+        version: String,
+        tarball: String,
+    },
+
+    #[error("Integrity check failed for {name}@{version}")]
+    #[diagnostic(help(
+        "The tarball was modified or corrupted in transit. Expected {}, got {}",
+        expected,
+        actual
+    ))]
+    IntegrityMismatch {
+        name: String,
+        version: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+/// The registry document describing all versions of a package
+#[derive(Debug, Deserialize)]
+pub struct Packument {
+    pub name: String,
+    #[serde(rename = "dist-tags", default)]
+    pub dist_tags: HashMap<String, String>,
+    #[serde(default)]
+    pub versions: HashMap<String, PackumentVersion>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PackumentVersion {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub dependencies: HashMap<String, String>,
+    /// The operating systems the version runs on, like `linux` or `!win32`, empty for all of them
+    #[serde(default)]
+    pub os: Vec<String>,
+    /// The CPU architectures the version runs on, like `x64` or `!arm`, empty for all of them
+    #[serde(default)]
+    pub cpu: Vec<String>,
+    pub dist: Dist,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Dist {
+    pub tarball: String,
+    pub integrity: Option<String>,
+    pub shasum: Option<String>,
+}
+
+impl Packument {
+    /// Resolves a dist-tag, version or version range into a specific version
+    pub fn resolve(&self, spec: &str) -> Result<&PackumentVersion, Errors> {
+        let spec = spec.trim();
+        let tag = if spec.is_empty() { "latest" } else { spec };
+        if let Some(version) = self.dist_tags.get(tag) {
+            if let Some(found) = self.versions.get(version) {
+                return Ok(found);
+            }
+        }
+
+        let versions: Vec<_> = self
+            .versions
+            .keys()
+            .filter_map(|version| semver::Version::parse(version).ok())
+            .collect();
+        let best = VersionRange::parse(spec)
+            .and_then(|range| range.max_satisfying(&versions))
+            .and_then(|version| self.versions.get(&version.to_string()));
+
+        best.ok_or_else(|| {
+            let mut available = versions;
+            available.sort();
+            Errors::NoMatchingVersion {
+                name: self.name.clone(),
+                spec: spec.to_string(),
+                available: available.iter().map(ToString::to_string).collect(),
+            }
+        })
+    }
+}
+
+impl Registry {
+    /// A registry from a URL. `file://` URLs and paths are treated as local registries.
+    pub fn new(url: &str) -> Self {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            Self::Remote {
+                url: url.trim_end_matches('/').to_string(),
+                token: None,
+            }
+        } else {
+            Self::Local(PathBuf::from(url.trim_start_matches("file://")))
+        }
+    }
+
+    /// The same registry, authenticated with `token` when it's remote
+    pub fn with_token(self, token: Option<String>) -> Self {
+        match self {
+            Self::Remote { url, .. } => Self::Remote { url, token },
+            local => local,
+        }
+    }
+
+    /// The URL of a remote registry, or the path of a local one
+    pub fn url(&self) -> String {
+        match self {
+            Self::Remote { url, .. } => url.clone(),
+            Self::Local(path) => path.display().to_string(),
+        }
+    }
+
+    pub fn packument(&self, name: &str) -> Result<Packument, Errors> {
+        let body = match self {
+            Self::Remote { url, token } => {
+                let url = format!("{}/{}", url, name.replace('/', "%2f"));
+                match get(&url, token.as_deref()) {
+                    Err(error) if is_not_found(&error) => {
+                        return Err(Errors::PackageNotFound {
+                            name: name.to_string(),
+                        })
+                    }
+                    result => String::from_utf8_lossy(&result?).to_string(),
+                }
+            }
+            Self::Local(dir) => {
+                let path = dir.join(format!("{}.json", name));
+                if !path.exists() {
+                    return Err(Errors::PackageNotFound {
+                        name: name.to_string(),
+                    });
+                }
+                std::fs::read_to_string(&path).map_err(|source| Errors::Io { path, source })?
+            }
+        };
+
+        serde_json::from_str(&body).map_err(|source| Errors::InvalidPackument {
+            name: name.to_string(),
+            source,
+        })
+    }
+
+    /// Downloads the tarball of a package version and verifies its integrity
+    pub fn tarball(&self, version: &PackumentVersion) -> Result<Vec<u8>, Errors> {
+        let tarball = &version.dist.tarball;
+        debug!("Fetching tarball {}", tarball);
+        let bytes = if tarball.starts_with("http://") || tarball.starts_with("https://") {
+            get(tarball, self.token_for(tarball))?
+        } else if let Self::Local(dir) = self {
+            let path = dir.join(tarball.trim_start_matches("file://"));
+            std::fs::read(&path).map_err(|source| Errors::Io { path, source })?
+        } else {
+            return Err(Errors::UnsupportedTarball {
+                name: version.name.clone(),
+                version: version.version.clone(),
+                tarball: tarball.clone(),
+            });
+        };
+        verify_integrity(version, &bytes)?;
+        Ok(bytes)
+    }
+
+    /// The token to send along to `url`: only requests to the registry itself are authenticated,
+    /// so tarballs hosted elsewhere never see it
+    fn token_for(&self, url: &str) -> Option<&str> {
+        match self {
+            Self::Remote {
+                url: registry_url,
+                token: Some(token),
+            } if is_under(registry_url, url) => Some(token),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `url` has the scheme, host and port of `base`, and a path inside the path of `base`
+fn is_under(base: &str, url: &str) -> bool {
+    let (base, url) = match (url::Url::parse(base), url::Url::parse(url)) {
+        (Ok(base), Ok(url)) => (base, url),
+        _ => return false,
+    };
+    let base_path = base.path().trim_end_matches('/');
+    base.scheme() == url.scheme()
+        && base.host_str() == url.host_str()
+        && base.port_or_known_default() == url.port_or_known_default()
+        && url
+            .path()
+            .strip_prefix(base_path)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn get(url: &str, token: Option<&str>) -> Result<Vec<u8>, Errors> {
+    let to_error = |source| Errors::Http {
+        url: url.to_string(),
+        source: Box::new(source),
+    };
+    let mut request = ureq::get(url).set("Accept", "application/json");
+    if let Some(token) = token {
+        request = request.set("Authorization", &format!("Bearer {}", token));
+    }
+    let response = request.call().map_err(to_error)?;
+    let mut bytes = vec![];
+    response
+        .into_reader()
+        .read_to_end(&mut bytes)
+        .map_err(|source| to_error(ureq::Error::from(source)))?;
+    Ok(bytes)
+}
+
+fn is_not_found(error: &Errors) -> bool {
+    matches!(error, Errors::Http { source, .. } if matches!(**source, ureq::Error::Status(404, _)))
+}
+
+/// Computes the `sha512-<base64>` integrity string of `bytes`
+pub fn integrity(bytes: &[u8]) -> String {
+    use sha2::{Digest, Sha512};
+    format!(
+        "sha512-{}",
+        base64::encode(Sha512::digest(bytes).as_slice())
+    )
+}
+
+fn verify_integrity(version: &PackumentVersion, bytes: &[u8]) -> Result<(), Errors> {
+    let (expected, actual) = match (&version.dist.integrity, &version.dist.shasum) {
+        (Some(expected), _) if expected.starts_with("sha512-") => {
+            (expected.clone(), integrity(bytes))
+        }
+        (_, Some(expected)) => {
+            use sha1::{Digest, Sha1};
+            let actual = Sha1::digest(bytes)
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            (expected.clone(), actual)
+        }
+        _ => {
+            warn!(
+                "No integrity information for {}@{}",
+                version.name, version.version
+            );
+            return Ok(());
+        }
+    };
+
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(Errors::IntegrityMismatch {
+            name: version.name.clone(),
+            version: version.version.clone(),
+            expected,
+            actual,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_sends_the_token_to_the_registry() {
+        let registry = Registry::new("https://npm.acme.com/npm/").with_token(Some("secret".into()));
+        let token_for = |url| registry.token_for(url);
+        assert_eq!(
+            token_for("https://npm.acme.com/npm/tool/-/tool-1.0.0.tgz"),
+            Some("secret")
+        );
+        assert_eq!(
+            token_for("https://npm.acme.com:443/npm/tool/-/tool-1.0.0.tgz"),
+            Some("secret")
+        );
+        for url in [
+            "https://npm.acme.com.evil.net/npm/tool/-/tool-1.0.0.tgz",
+            "https://npm.acme.com/npm-evil/tool-1.0.0.tgz",
+            "https://npm.acme.com:8443/npm/tool-1.0.0.tgz",
+            "http://npm.acme.com/npm/tool-1.0.0.tgz",
+            "https://evil.net/?https://npm.acme.com/npm/",
+        ] {
+            assert_eq!(token_for(url), None, "{}", url);
+        }
+    }
+
+    #[test]
+    fn reads_local_tarballs_only_from_local_registries() {
+        let dir = tempdir::TempDir::new("registry").unwrap();
+        let tarball = dir.path().join("tool-1.0.0.tgz");
+        std::fs::write(&tarball, b"tarball").unwrap();
+        let version: PackumentVersion = serde_json::from_value(serde_json::json!({
+            "name": "tool",
+            "version": "1.0.0",
+            "dist": { "tarball": tarball.display().to_string() },
+        }))
+        .unwrap();
+
+        let local = Registry::new(&dir.path().display().to_string());
+        assert_eq!(local.tarball(&version).unwrap(), b"tarball");
+        let error = Registry::new("https://npm.acme.com/npm/")
+            .tarball(&version)
+            .unwrap_err();
+        assert!(
+            matches!(error, Errors::UnsupportedTarball { .. }),
+            "{:?}",
+            error
+        );
+    }
+}
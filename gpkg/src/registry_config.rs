@@ -0,0 +1,306 @@
+use crate::package_manager::PackageManagerKind;
+use crate::registry::{self, Registry, DEFAULT_REGISTRY};
+use log::*;
+use miette::Diagnostic;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Errors {
+    #[error("Can't read {path:?}")]
+    #[diagnostic()]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Can't parse {path:?}: {message}")]
+    #[diagnostic(help(
+        "Registries are configured with `registry = \"<url>\"`, `[scopes]` mapping scopes to URLs \
+         and `[registries.\"<url>\"]` tables with a `token_env`. \
+         The default package manager is set with `package_manager = \"<name>\"`"
+    ))]
+    InvalidConfig { path: PathBuf, message: String },
+}
+
+/// The registries packages are installed from, and the package manager installing them,
+/// kept in the `config.toml` file of the gpkg root:
+///
+/// ```toml
+/// package_manager = "pnpm"
+/// registry = "https://registry.npmjs.org"
+///
+/// [scopes]
+/// "@acme" = "https://npm.acme.dev"
+///
+/// [registries."https://npm.acme.dev"]
+/// token_env = "ACME_NPM_TOKEN"
+/// ```
+///
+/// Auth tokens are never stored: only the names of the environment variables holding them are,
+/// and they are read when the registry is used.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryConfig {
+    /// The package manager used when none is given with `--package-manager` or `GPKG_PACKAGE_MANAGER`
+    pub package_manager: Option<PackageManagerKind>,
+    /// The registry of unscoped packages and of scopes without a registry of their own
+    pub registry: Option<String>,
+    /// Registry URLs, keyed by scope (`@acme`)
+    #[serde(default)]
+    pub scopes: BTreeMap<String, String>,
+    /// The authentication of every registry, keyed by URL
+    #[serde(default)]
+    pub registries: BTreeMap<String, RegistryAuth>,
+    /// Tokens used instead of the environment variables of the same names, never read from the file
+    #[serde(skip)]
+    pub(crate) tokens: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryAuth {
+    /// The environment variable holding the auth token of the registry
+    pub token_env: String,
+}
+
+impl RegistryConfig {
+    /// Reads the configuration at `path`, which doesn't have to exist.
+    /// The default registry can be overridden with the `GPKG_REGISTRY` environment variable.
+    pub fn load(path: &Path) -> Result<Self, Errors> {
+        let mut config = if path.exists() {
+            let contents = std::fs::read_to_string(path).map_err(|source| Errors::Io {
+                path: path.to_path_buf(),
+                source,
+            })?;
+            toml::from_str(&contents).map_err(|error| Errors::InvalidConfig {
+                path: path.to_path_buf(),
+                message: error.to_string(),
+            })?
+        } else {
+            Self::default()
+        };
+        if let Ok(registry) = std::env::var("GPKG_REGISTRY") {
+            config.registry = Some(registry);
+        }
+        Ok(config)
+    }
+
+    /// A configuration installing every package from `url`
+    pub fn with_registry(url: impl Into<String>) -> Self {
+        Self {
+            registry: Some(url.into()),
+            ..Self::default()
+        }
+    }
+
+    /// The registry configured for `package_name`, by its scope or as the default one
+    pub fn configured_url(&self, package_name: &str) -> Option<&str> {
+        scope(package_name)
+            .and_then(|scope| self.scopes.get(scope))
+            .or(self.registry.as_ref())
+            .map(String::as_str)
+    }
+
+    /// The URL of the registry `package_name` is installed from
+    pub fn url(&self, package_name: &str) -> &str {
+        self.configured_url(package_name)
+            .unwrap_or(DEFAULT_REGISTRY)
+    }
+
+    /// The registry `package_name` is installed from, authenticated when it has a token
+    pub fn registry(&self, package_name: &str) -> Registry {
+        self.registry_at(self.url(package_name))
+    }
+
+    /// The registry at `url`, authenticated when it has a token
+    pub fn registry_at(&self, url: &str) -> Registry {
+        Registry::new(url).with_token(self.token(url))
+    }
+
+    /// The version `spec` of `package_name` resolves to in its registry,
+    /// where `spec` is a version, a range or a dist-tag
+    pub fn resolve(&self, package_name: &str, spec: &str) -> Result<String, registry::Errors> {
+        let packument = self.registry(package_name).packument(package_name)?;
+        Ok(packument.resolve(spec)?.version.clone())
+    }
+
+    /// The auth token of the registry at `url`, read from its environment variable
+    fn token(&self, url: &str) -> Option<String> {
+        let auth = self
+            .registries
+            .iter()
+            .find(|(registry, _)| same_url(registry, url))
+            .map(|(_, auth)| auth)?;
+        let token = match self.tokens.get(&auth.token_env) {
+            Some(token) => Some(token.clone()),
+            None => std::env::var(&auth.token_env).ok(),
+        };
+        if token.is_none() {
+            warn!(
+                "{} is not set, accessing {} without a token",
+                auth.token_env, url
+            );
+        }
+        token
+    }
+
+    /// The same configuration, with `package_name` installed from `url`
+    pub fn pinned(&self, package_name: &str, url: &str) -> Self {
+        let mut config = self.clone();
+        match scope(package_name) {
+            Some(scope) => {
+                config.scopes.insert(scope.to_string(), url.to_string());
+            }
+            None => config.registry = Some(url.to_string()),
+        }
+        config
+    }
+
+    /// The contents of an `.npmrc` file pointing package managers to the configured registries.
+    ///
+    /// Tokens are written as `${VAR}` references, which package managers expand themselves.
+    pub fn npmrc(&self) -> String {
+        let mut lines = vec![];
+        if let Some(registry) = &self.registry {
+            lines.push(format!("registry={}", with_trailing_slash(registry)));
+        }
+        for (scope, registry) in &self.scopes {
+            lines.push(format!(
+                "{}:registry={}",
+                scope,
+                with_trailing_slash(registry)
+            ));
+        }
+        for (registry, auth) in &self.registries {
+            let without_protocol = registry
+                .trim_start_matches("https:")
+                .trim_start_matches("http:");
+            lines.push(format!(
+                "{}:_authToken=${{{}}}",
+                with_trailing_slash(without_protocol),
+                auth.token_env
+            ));
+        }
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    /// Whether no registry is configured, so package managers can use their own defaults
+    pub fn is_empty(&self) -> bool {
+        self.registry.is_none() && self.scopes.is_empty() && self.registries.is_empty()
+    }
+
+    /// The package manager to install with: the `requested` one, the configured one, or npm
+    pub fn package_manager_or(&self, requested: Option<PackageManagerKind>) -> PackageManagerKind {
+        requested.or(self.package_manager).unwrap_or_default()
+    }
+}
+
+/// The scope of a scoped package name, like `@acme` for `@acme/tool`
+fn scope(package_name: &str) -> Option<&str> {
+    if package_name.starts_with('@') {
+        package_name.split('/').next()
+    } else {
+        None
+    }
+}
+
+fn same_url(left: &str, right: &str) -> bool {
+    left.trim_end_matches('/') == right.trim_end_matches('/')
+}
+
+fn with_trailing_slash(url: &str) -> String {
+    format!("{}/", url.trim_end_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn config() -> RegistryConfig {
+        toml::from_str(
+            r#"
+            package_manager = "pnpm"
+            registry = "https://registry.example.com"
+
+            [scopes]
+            "@acme" = "https://npm.acme.dev/"
+
+            [registries."https://npm.acme.dev"]
+            token_env = "GPKG_TEST_ACME_TOKEN"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn picks_registries_by_scope() {
+        let config = config();
+        assert_eq!(config.url("@acme/tool"), "https://npm.acme.dev/");
+        assert_eq!(config.url("@other/tool"), "https://registry.example.com");
+        assert_eq!(config.url("tool"), "https://registry.example.com");
+        assert_eq!(RegistryConfig::default().url("tool"), DEFAULT_REGISTRY);
+        assert_eq!(RegistryConfig::default().configured_url("tool"), None);
+
+        assert_eq!(
+            config.registry("@acme/tool"),
+            Registry::new("https://npm.acme.dev")
+        );
+        let mut config = config;
+        config
+            .tokens
+            .insert("GPKG_TEST_ACME_TOKEN".to_string(), "secret".to_string());
+        assert_eq!(
+            config.registry("@acme/tool"),
+            Registry::new("https://npm.acme.dev").with_token(Some("secret".to_string()))
+        );
+        assert_eq!(
+            config.registry("tool"),
+            Registry::new("https://registry.example.com")
+        );
+
+        let pinned = config.pinned("@acme/tool", "https://old.acme.dev");
+        assert_eq!(pinned.url("@acme/tool"), "https://old.acme.dev");
+        assert_eq!(pinned.url("tool"), "https://registry.example.com");
+    }
+
+    #[test]
+    fn picks_the_package_manager() {
+        let config = config();
+        assert_eq!(config.package_manager_or(None), PackageManagerKind::Pnpm);
+        assert_eq!(
+            config.package_manager_or(Some(PackageManagerKind::Yarn)),
+            PackageManagerKind::Yarn
+        );
+        assert_eq!(
+            RegistryConfig::default().package_manager_or(None),
+            PackageManagerKind::Npm
+        );
+
+        let only_package_manager = RegistryConfig {
+            package_manager: Some(PackageManagerKind::Bun),
+            ..RegistryConfig::default()
+        };
+        assert!(only_package_manager.is_empty());
+    }
+
+    #[test]
+    fn writes_npmrc_with_token_references() {
+        let mut config = config();
+        config
+            .tokens
+            .insert("GPKG_TEST_ACME_TOKEN".to_string(), "secret".to_string());
+        let npmrc = config.npmrc();
+        assert_eq!(
+            npmrc,
+            "registry=https://registry.example.com/\n\
+             @acme:registry=https://npm.acme.dev/\n\
+             //npm.acme.dev/:_authToken=${GPKG_TEST_ACME_TOKEN}\n"
+        );
+        assert!(RegistryConfig::default().npmrc().is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+use crate::directory_portal::DirectoryPortal;
+use crate::file_manifest::record_manifest;
+use crate::index::Index;
+use crate::install_package::{
+    package_installations, package_manager_for, package_version_in, prepare_locked_installation,
+    Errors, Installation,
+};
+use crate::lockfile::LockedInstallation;
+use crate::node::NodeBinary;
+use crate::package_json::PackageRoot;
+use crate::registry_config::RegistryConfig;
+use crate::tarball_cache::TarballCache;
+use crate::version_range::VersionRange;
+use log::*;
+use std::path::Path;
+
+/// Installs the installations of a package again, exactly as described by the lockfiles kept in them,
+/// or only the installed versions in `version_range`.
+///
+/// Every installation is installed into a new directory first, and only replaces the current one
+/// once that succeeded. The binaries are left alone, as they run the same versions as before.
+/// Returns the reinstalled installations.
+pub fn reinstall_package<InstallationDir: AsRef<Path>, BinDir: AsRef<Path>>(
+    package_name: &str,
+    version_range: Option<&VersionRange>,
+    installation_dir: InstallationDir,
+    bin_dir: BinDir,
+    registries: &RegistryConfig,
+    cache: Option<&TarballCache>,
+) -> Result<Vec<Installation>, Errors> {
+    let binaries: Vec<_> = Index::load(&installation_dir, &bin_dir)?
+        .into_binaries()
+        .into_iter()
+        .filter(|metadata| metadata.package_name == package_name)
+        .collect();
+    if binaries.iter().any(|metadata| metadata.linked) {
+        return Err(Errors::PackageLinked(package_name.to_string()));
+    }
+    let installations: Vec<_> = package_installations(&installation_dir, package_name)?
+        .into_iter()
+        .filter(|installation| version_range.is_none_or(|range| installation.matches(range)))
+        .collect();
+    if installations.is_empty() {
+        return Err(Errors::PackageNotInstalled(package_name.to_string()));
+    }
+
+    let mut reinstalled = vec![];
+    for installation in installations {
+        let metadata = binaries
+            .iter()
+            .find(|metadata| metadata.installation_path(&installation_dir) == installation.path);
+        let locked = LockedInstallation::read(
+            &installation.path,
+            package_name,
+            metadata
+                .map(|metadata| metadata.package_manager)
+                .unwrap_or_default(),
+        )?;
+        let node = match metadata.and_then(|metadata| metadata.node_path.as_ref()) {
+            Some(node_path) => NodeBinary::from_path(node_path)?,
+            None => NodeBinary::current()?,
+        };
+        let registries = match metadata.and_then(|metadata| metadata.registry.as_ref()) {
+            Some(registry) => registries.pinned(package_name, registry),
+            None => registries.clone(),
+        };
+        let package_manager = package_manager_for(locked.package_manager, cache);
+        debug!(
+            "Reinstalling {:?} from its {} using {} and node {}",
+            installation.path, locked.lockfile, package_manager, node.version
+        );
+
+        let portal = DirectoryPortal::new_in(&installation_dir, &installation.path);
+        copy_local_tarballs(&locked, &installation.path, &portal)?;
+        prepare_locked_installation(
+            package_name,
+            &locked,
+            &node,
+            package_manager,
+            &registries,
+            cache,
+            &portal,
+        )?;
+
+        let backup_dir = tempfile::Builder::new()
+            .prefix(".reinstall")
+            .tempdir_in(&installation_dir)?;
+        let backup_path = backup_dir.path().join("previous");
+        std::fs::rename(&installation.path, &backup_path)?;
+        if let Err(error) = portal.teleport() {
+            warn!(
+                "Reinstall failed, restoring the previous installation: {}",
+                error
+            );
+            std::fs::rename(&backup_path, &installation.path)?;
+            return Err(error.into());
+        }
+        record_manifest(&installation.path, package_manager)?;
+        reinstalled.push(Installation {
+            version: package_version_in(&installation.path, package_name),
+            ..installation
+        });
+    }
+
+    Ok(reinstalled)
+}
+
+/// Copies the local tarballs the `package.json` of `locked` depends on from the installation,
+/// where they were copied when it was installed.
+/// An invalid `package.json` is left for the package manager to report.
+fn copy_local_tarballs(
+    locked: &LockedInstallation,
+    installation_path: &Path,
+    portal: &Path,
+) -> Result<(), Errors> {
+    let root: PackageRoot = match serde_json::from_str(&locked.package_json) {
+        Ok(root) => root,
+        Err(_) => return Ok(()),
+    };
+    for spec in root.dependencies.values() {
+        if let Some(file_name) = spec.strip_prefix("file:./") {
+            std::fs::copy(installation_path.join(file_name), portal.join(file_name))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manifest::verify_installation;
+    use crate::install_package::{install_package, installation_path, InstallOptions};
+    use crate::node_package_version::NodePackageVersion;
+    use crate::package_manager::PackageManagerKind;
+    use crate::test_registry::{TestPackage, TestRegistry};
+    use pretty_assertions::assert_eq;
+    use std::str::FromStr;
+
+    #[test]
+    fn reinstalls_the_locked_tree() {
+        let registry = TestRegistry::new();
+        let bin = [("tool", "#!/usr/bin/env node\nconsole.log('tool')")];
+        registry
+            .publish(TestPackage::new("left-pad", "1.0.0"))
+            .publish(TestPackage {
+                bin: &bin,
+                dependencies: &[("left-pad", "^1")],
+                ..TestPackage::new("tool", "1.0.0")
+            });
+        let installation_dir = tempdir::TempDir::new("installations").unwrap();
+        let bin_dir = tempdir::TempDir::new("bin").unwrap();
+        let node = NodeBinary::current().expect("Can't find node");
+        install_package(
+            &NodePackageVersion::from_str("tool@1").unwrap(),
+            &node,
+            installation_dir.path(),
+            bin_dir.path(),
+            &InstallOptions {
+                package_manager: PackageManagerKind::Native,
+                registries: registry.config(),
+                ..InstallOptions::default()
+            },
+        )
+        .expect("Can't install");
+
+        // a newer dependency is published, and the installation is tampered with
+        registry.publish(TestPackage::new("left-pad", "1.1.0"));
+        let path = installation_path(installation_dir.path(), "tool");
+        let left_pad = path.join("node_modules").join("left-pad");
+        std::fs::write(left_pad.join("index.js"), "steal()").unwrap();
+        assert_eq!(verify_installation(&path).unwrap().len(), 1);
+
+        let reinstalled = reinstall_package(
+            "tool",
+            None,
+            installation_dir.path(),
+            bin_dir.path(),
+            &registry.config(),
+            None,
+        )
+        .expect("Can't reinstall");
+        assert_eq!(reinstalled.len(), 1);
+        assert_eq!(verify_installation(&path).unwrap(), vec![]);
+        let manifest = std::fs::read_to_string(left_pad.join("package.json")).unwrap();
+        assert!(manifest.contains("\"1.0.0\""));
+
+        // a modified lockfile is refused, and one that doesn't match the package.json can't be honoured
+        let lockfile = path.join("package-lock.json");
+        let contents = std::fs::read_to_string(&lockfile).unwrap();
+        std::fs::write(
+            &lockfile,
+            contents.replace("\"tool\": \"1\"", "\"tool\": \"^2\""),
+        )
+        .unwrap();
+        let reinstall = || {
+            reinstall_package(
+                "tool",
+                None,
+                installation_dir.path(),
+                bin_dir.path(),
+                &registry.config(),
+                None,
+            )
+        };
+        assert!(matches!(reinstall(), Err(Errors::Lockfile(_))));
+        crate::file_manifest::remove_manifest(&path).unwrap();
+        assert!(matches!(
+            reinstall(),
+            Err(Errors::PackageManager(
+                crate::package_manager::Errors::FrozenLockfile { .. }
+            ))
+        ));
+        assert!(left_pad.join("package.json").exists());
+    }
+}
@@ -0,0 +1,505 @@
+use crate::binary::runnable_path;
+use crate::collision::CollisionPolicy;
+use crate::index::Index;
+use crate::install_package::{self, install_package, InstallOptions};
+use crate::node::NodeBinary;
+use crate::node_package_version::{NodePackageVersion, PackageSource, RegistrySpec};
+use crate::package_manager::PackageManagerKind;
+use crate::registry_config::RegistryConfig;
+use crate::storage::timestamp;
+use crate::tarball_cache::TarballCache;
+use log::*;
+use miette::Diagnostic;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+/// The file in a cache entry that records when it was last used
+const LAST_USED_FILE: &str = ".last-used";
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Errors {
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Install(#[from] install_package::Errors),
+
+    #[error("Can't read the cached installation in {path:?}")]
+    #[diagnostic()]
+    ReadingCache {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Package {package_name:?} has no binary named {binary_name:?}")]
+    #[diagnostic(help("Its binaries are: {}", binaries))]
+    NoSuchBinary {
+        package_name: String,
+        binary_name: String,
+        binaries: String,
+    },
+
+    #[error("Package {package_name:?} has several binaries: {binaries}")]
+    #[diagnostic(help("Use `--bin <name>` to choose the one to run"))]
+    AmbiguousBinary {
+        package_name: String,
+        binaries: String,
+    },
+}
+
+/// A package installed into the run cache, with its own bin directory
+#[derive(Debug)]
+pub struct CachedPackage {
+    pub package_name: String,
+    /// The cache entry, which is laid out like the gpkg root directory
+    pub path: PathBuf,
+    /// Whether the installation was already in the cache
+    pub reused: bool,
+}
+
+impl CachedPackage {
+    pub fn installation_dir(&self) -> PathBuf {
+        self.path.join("installations")
+    }
+
+    pub fn bin_dir(&self) -> PathBuf {
+        self.path.join("bin")
+    }
+
+    /// The names of the binaries of the package, sorted
+    pub fn binaries(&self) -> Result<Vec<String>, Errors> {
+        let index = Index::load(self.installation_dir(), self.bin_dir()).map_err(|source| {
+            Errors::ReadingCache {
+                path: self.path.clone(),
+                source,
+            }
+        })?;
+        Ok(index
+            .into_binaries()
+            .into_iter()
+            .map(|metadata| metadata.binary_name)
+            .collect())
+    }
+
+    /// The file to execute to run `binary_name`, or the binary of the package when it's not given.
+    ///
+    /// A package with several binaries runs the one named after the package, like npx does.
+    pub fn binary_path(&self, binary_name: Option<&str>) -> Result<PathBuf, Errors> {
+        let binaries = self.binaries()?;
+        let unscoped_name = self.package_name.rsplit('/').next().unwrap_or_default();
+        let binary_name = match binary_name {
+            Some(binary_name) if binaries.iter().any(|name| name == binary_name) => binary_name,
+            Some(binary_name) => {
+                return Err(Errors::NoSuchBinary {
+                    package_name: self.package_name.clone(),
+                    binary_name: binary_name.to_string(),
+                    binaries: binaries.join(", "),
+                })
+            }
+            None if binaries.len() == 1 => &binaries[0],
+            None if binaries.iter().any(|name| name == unscoped_name) => unscoped_name,
+            None => {
+                return Err(Errors::AmbiguousBinary {
+                    package_name: self.package_name.clone(),
+                    binaries: binaries.join(", "),
+                })
+            }
+        };
+        Ok(runnable_path(self.bin_dir().join(binary_name)))
+    }
+}
+
+/// Installs `requested_package` into `cache_dir`, unless it is already there,
+/// so it can be run without touching the installed packages.
+///
+/// Entries are keyed by the requested package, the node version and the package manager,
+/// so the binaries always run on the requested node.
+/// Packages from local directories are installed again every time, to pick up their changes,
+/// and tags and ranges are installed again when they resolve to another version than the cached one.
+pub fn prepare_cached_package(
+    requested_package: &NodePackageVersion,
+    node: &NodeBinary,
+    cache_dir: impl AsRef<Path>,
+    package_manager: PackageManagerKind,
+    registries: &RegistryConfig,
+    tarball_cache: Option<&TarballCache>,
+) -> Result<CachedPackage, Errors> {
+    let path = cache_entry_path(&cache_dir, requested_package, node, package_manager);
+    let is_directory = matches!(requested_package.source(), PackageSource::Directory(_));
+    let reading_cache = |source| Errors::ReadingCache {
+        path: path.clone(),
+        source,
+    };
+    if is_directory && path.exists() {
+        debug!("Removing the cached installation of a local directory");
+        std::fs::remove_dir_all(&path).map_err(reading_cache)?;
+    }
+
+    let installation_dir = path.join("installations");
+    let bin_dir = path.join("bin");
+    // an entry without binaries is left over from an interrupted installation
+    let cached_version = if installation_dir.exists() {
+        Index::load(&installation_dir, &bin_dir)
+            .ok()
+            .and_then(|index| index.binaries().next().cloned())
+            .map(|metadata| metadata.package_version)
+    } else {
+        None
+    };
+    let reused = match cached_version {
+        Some(version) => is_up_to_date(
+            requested_package,
+            version.as_deref(),
+            registries,
+            tarball_cache,
+        ),
+        None => false,
+    };
+    if reused {
+        debug!("Reusing the cached installation in {:?}", path);
+    } else {
+        if path.exists() {
+            std::fs::remove_dir_all(&path).map_err(reading_cache)?;
+        }
+        std::fs::create_dir_all(&installation_dir).map_err(reading_cache)?;
+        std::fs::create_dir_all(&bin_dir).map_err(reading_cache)?;
+        install_package(
+            requested_package,
+            node,
+            &installation_dir,
+            &bin_dir,
+            &InstallOptions {
+                collision_policy: CollisionPolicy::Force,
+                package_manager,
+                registries: registries.clone(),
+                cache: tarball_cache.cloned(),
+                ..InstallOptions::default()
+            },
+        )?;
+    }
+    std::fs::write(path.join(LAST_USED_FILE), timestamp()).map_err(reading_cache)?;
+
+    // the name of a local package is only known once it's installed
+    let package_name = Index::load(&installation_dir, &bin_dir)
+        .map_err(reading_cache)?
+        .binaries()
+        .next()
+        .map(|metadata| metadata.package_name.clone())
+        .unwrap_or_else(|| requested_package.name().to_string());
+
+    Ok(CachedPackage {
+        package_name,
+        path,
+        reused,
+    })
+}
+
+/// Whether the cached `version` of `requested_package` is still the one it would install.
+///
+/// Exact versions, tarballs and git commits always install the same thing.
+/// Tags and ranges are resolved against the registry again, and git branches are installed again.
+/// When the registry can't be reached, the cached version is used.
+fn is_up_to_date(
+    requested_package: &NodePackageVersion,
+    version: Option<&str>,
+    registries: &RegistryConfig,
+    tarball_cache: Option<&TarballCache>,
+) -> bool {
+    let spec = match requested_package.source() {
+        PackageSource::Registry(spec) | PackageSource::Alias { spec, .. } => spec,
+        PackageSource::Git { committish, .. } => {
+            return committish.as_deref().is_some_and(is_commit_hash)
+        }
+        PackageSource::Tarball(_) => return true,
+        PackageSource::Directory(_) => return false,
+    };
+    let (range, version) = match (spec, version) {
+        (RegistrySpec::Version(_), _) => return true,
+        (RegistrySpec::Tag(range) | RegistrySpec::Range(range), Some(version)) => (range, version),
+        (_, None) => return false,
+    };
+    if tarball_cache.is_some_and(TarballCache::is_offline) {
+        return true;
+    }
+
+    let name = requested_package
+        .registry_name()
+        .unwrap_or_else(|| requested_package.name());
+    match registries.resolve(name, range) {
+        Ok(resolved) => {
+            debug!(
+                "{}@{} resolves to {}, {} is cached",
+                name, range, resolved, version
+            );
+            resolved == version
+        }
+        Err(error) => {
+            warn!(
+                "Can't check for newer versions of {}, using the cached {}: {}",
+                name, version, error
+            );
+            true
+        }
+    }
+}
+
+fn is_commit_hash(committish: &str) -> bool {
+    committish.len() == 40 && committish.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The cache entry of a requested package: a readable prefix,
+/// followed by a hash of everything the installation depends on
+pub fn cache_entry_path(
+    cache_dir: impl AsRef<Path>,
+    requested_package: &NodePackageVersion,
+    node: &NodeBinary,
+    package_manager: PackageManagerKind,
+) -> PathBuf {
+    let key = format!(
+        "{}\n{}\n{}",
+        requested_package, node.version, package_manager
+    );
+    let hash: String = Sha256::digest(key.as_bytes())
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let prefix: String = requested_package
+        .to_string()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' | '@' => c,
+            _ => '_',
+        })
+        .take(64)
+        .collect();
+    cache_dir.as_ref().join(format!("{}-{}", prefix, hash))
+}
+
+/// An installation in the run cache
+#[derive(Debug)]
+pub struct CacheEntry {
+    pub path: PathBuf,
+    /// When it was last run, or created when that was never recorded
+    pub last_used: Option<SystemTime>,
+}
+
+impl CacheEntry {
+    fn read(path: PathBuf) -> Self {
+        let last_used = std::fs::read_to_string(path.join(LAST_USED_FILE))
+            .ok()
+            .and_then(|last_used| humantime::parse_rfc3339(last_used.trim()).ok())
+            .or_else(|| path.metadata().and_then(|meta| meta.modified()).ok());
+        Self { path, last_used }
+    }
+
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    }
+}
+
+/// Every entry of the run cache, sorted by name
+pub fn cache_entries(cache_dir: impl AsRef<Path>) -> std::io::Result<Vec<CacheEntry>> {
+    if !cache_dir.as_ref().exists() {
+        return Ok(vec![]);
+    }
+    let mut paths = vec![];
+    for entry in cache_dir.as_ref().read_dir()? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    Ok(paths.into_iter().map(CacheEntry::read).collect())
+}
+
+/// Removes the entries of the run cache that weren't used in the last `older_than`,
+/// or all of them when it's not given. Returns the removed entries.
+pub fn clean_cache(
+    cache_dir: impl AsRef<Path>,
+    older_than: Option<Duration>,
+) -> std::io::Result<Vec<CacheEntry>> {
+    let now = SystemTime::now();
+    let mut removed = vec![];
+    for entry in cache_entries(cache_dir)? {
+        let is_stale = match (older_than, entry.last_used) {
+            (None, _) | (Some(_), None) => true,
+            (Some(older_than), Some(last_used)) => now
+                .duration_since(last_used)
+                .is_ok_and(|age| age > older_than),
+        };
+        if is_stale {
+            debug!("Removing cached installation {:?}", entry.path);
+            std::fs::remove_dir_all(&entry.path)?;
+            removed.push(entry);
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_registry::{TestPackage, TestRegistry};
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    fn node(version: &str) -> NodeBinary {
+        NodeBinary {
+            path: PathBuf::from("/usr/bin/node"),
+            version: version.to_string(),
+        }
+    }
+
+    #[test]
+    fn keys_entries_by_package_and_node() {
+        let package: NodePackageVersion = "@scope/tool@^1.2".parse().unwrap();
+        let path = cache_entry_path("/cache", &package, &node("v14.0.0"), Default::default());
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        assert!(file_name.starts_with("@scope_tool@_1.2-"));
+        assert_ne!(
+            path,
+            cache_entry_path("/cache", &package, &node("v16.0.0"), Default::default())
+        );
+        assert_eq!(
+            path,
+            cache_entry_path("/cache", &package, &node("v14.0.0"), Default::default())
+        );
+    }
+
+    #[test]
+    fn runs_local_packages() {
+        let source_dir = TempDir::new("source").unwrap();
+        let package_dir = source_dir.path().join("tool");
+        std::fs::create_dir_all(&package_dir).unwrap();
+        std::fs::write(
+            package_dir.join("package.json"),
+            r#"{"name": "@acme/tool", "version": "1.0.0", "bin": {"tool": "tool.js", "other": "tool.js"}}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            package_dir.join("tool.js"),
+            "#!/usr/bin/env node\nconsole.log(process.argv[2])",
+        )
+        .unwrap();
+        let cache_dir = TempDir::new("cache").unwrap();
+        let package: NodePackageVersion = package_dir.display().to_string().parse().unwrap();
+        let node = NodeBinary::current().expect("Can't find node");
+
+        let cached = prepare_cached_package(
+            &package,
+            &node,
+            cache_dir.path(),
+            PackageManagerKind::Native,
+            &RegistryConfig::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(cached.package_name, "@acme/tool");
+        assert_eq!(cached.binaries().unwrap(), vec!["other", "tool"]);
+        assert!(matches!(
+            cached.binary_path(Some("missing")),
+            Err(Errors::NoSuchBinary { .. })
+        ));
+        let stdout = std::process::Command::new(cached.binary_path(None).unwrap())
+            .arg("cached")
+            .output()
+            .unwrap()
+            .stdout;
+        assert_eq!(std::str::from_utf8(&stdout).unwrap().trim(), "cached");
+
+        // local directories are installed again, so they're never stale
+        let cached = prepare_cached_package(
+            &package,
+            &node,
+            cache_dir.path(),
+            PackageManagerKind::Native,
+            &RegistryConfig::default(),
+            None,
+        )
+        .unwrap();
+        assert!(!cached.reused);
+        assert_eq!(cache_entries(cache_dir.path()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn resolves_ranges_again_before_reusing_entries() {
+        let registry = TestRegistry::new();
+        let bin = [("tool", "#!/usr/bin/env node\nconsole.log('tool')")];
+        registry.publish(TestPackage {
+            bin: &bin,
+            ..TestPackage::new("tool", "1.0.0")
+        });
+        let cache_dir = TempDir::new("cache").unwrap();
+        let node = NodeBinary::current().expect("Can't find node");
+        let prepare = |package: &str| {
+            let cached = prepare_cached_package(
+                &package.parse().unwrap(),
+                &node,
+                cache_dir.path(),
+                PackageManagerKind::Native,
+                &registry.config(),
+                None,
+            )
+            .unwrap();
+            let index = Index::load(cached.installation_dir(), cached.bin_dir()).unwrap();
+            let version = index.binaries().next().unwrap().package_version.clone();
+            (cached.reused, version.unwrap())
+        };
+
+        assert_eq!(prepare("tool@^1"), (false, "1.0.0".to_string()));
+        assert_eq!(prepare("tool@^1"), (true, "1.0.0".to_string()));
+        assert_eq!(prepare("tool@1.0.0"), (false, "1.0.0".to_string()));
+
+        registry.publish(TestPackage {
+            bin: &bin,
+            ..TestPackage::new("tool", "1.1.0")
+        });
+        assert_eq!(prepare("tool@^1"), (false, "1.1.0".to_string()));
+        assert_eq!(prepare("tool@^1"), (true, "1.1.0".to_string()));
+        assert_eq!(prepare("tool@1.0.0"), (true, "1.0.0".to_string()));
+
+        let offline = |spec: &str, version: Option<&str>| {
+            is_up_to_date(
+                &spec.parse().unwrap(),
+                version,
+                &RegistryConfig::with_registry("/missing"),
+                None,
+            )
+        };
+        assert!(offline("tool@^1", Some("1.0.0")));
+        assert!(!offline("tool@^1", None));
+        assert!(offline(
+            &format!("github:user/tool#{}", "a".repeat(40)),
+            None
+        ));
+        assert!(!offline("github:user/tool#main", None));
+    }
+
+    #[test]
+    fn cleans_stale_entries() {
+        let cache_dir = TempDir::new("cache").unwrap();
+        let fresh = cache_dir.path().join("fresh");
+        let stale = cache_dir.path().join("stale");
+        std::fs::create_dir_all(&fresh).unwrap();
+        std::fs::create_dir_all(&stale).unwrap();
+        std::fs::write(fresh.join(LAST_USED_FILE), timestamp()).unwrap();
+        std::fs::write(stale.join(LAST_USED_FILE), "2020-01-01T00:00:00Z").unwrap();
+
+        let removed = clean_cache(cache_dir.path(), Some(Duration::from_secs(60 * 60))).unwrap();
+        assert_eq!(
+            removed.iter().map(CacheEntry::name).collect::<Vec<_>>(),
+            vec!["stale"]
+        );
+        assert!(fresh.exists());
+
+        clean_cache(cache_dir.path(), None).unwrap();
+        assert!(cache_entries(cache_dir.path()).unwrap().is_empty());
+    }
+}
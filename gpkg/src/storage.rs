@@ -1,36 +1,250 @@
+use crate::binary::{sidecar_path, ScriptForm, Sidecar};
+use crate::install_package::installation_path;
+use crate::package_manager::PackageManagerKind;
+use log::*;
 use serde::{Deserialize, Serialize};
-use std::convert::TryFrom;
-use std::path::Path;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// How the metadata line starts in shell and PowerShell scripts, and in batch files
+const METADATA_PREFIXES: [&str; 2] = ["# metadata: ", "@REM metadata: "];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MetadataV1 {
     pub binary_name: String,
     pub package_name: String,
     pub node_version: String,
+    /// The node binary the package is pinned to.
+    /// Missing in binaries that were installed before it was recorded.
+    #[serde(default)]
+    pub node_path: Option<PathBuf>,
+    /// The package manager that installed the package
+    #[serde(default)]
+    pub package_manager: PackageManagerKind,
+    /// Where the package was installed from when it's not the registry,
+    /// like `file:/path/to/tool` or `github:user/repo`
+    #[serde(default)]
+    pub source: Option<String>,
+    /// The `sha512-<base64>` hash of a package installed from a local directory or tarball
+    #[serde(default)]
+    pub integrity: Option<String>,
+    /// Whether the binary runs a working copy linked with `gpkg link`,
+    /// whose directory is in `source`
+    #[serde(default)]
+    pub linked: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MetadataV2 {
+    pub binary_name: String,
+    pub package_name: String,
+    /// The version of the package that was installed.
+    /// Unknown for binaries upgraded from V1 until they are migrated.
+    pub package_version: Option<String>,
+    /// The directory of the installation the binary runs, for versions installed
+    /// side by side with `--as` or `--bin-suffix`.
+    /// Empty for the main installation, which is named after the package.
+    pub installation: Option<String>,
+    /// The name of the binary in the package, when it's exposed under another name
+    pub package_binary: Option<String>,
+    /// What was requested after `name@`, like `^8`, `latest` or `github:user/repo`
+    pub requested: Option<String>,
+    /// Where the package was installed from when it's not the registry,
+    /// like `file:/path/to/tool` or `github:user/repo`
+    pub source: Option<String>,
+    /// The registry the package was downloaded from, when gpkg talked to it directly
+    pub registry: Option<String>,
+    /// The `sha512-<base64>` hash of a package installed from a local directory or tarball
+    pub integrity: Option<String>,
+    pub node_version: String,
+    /// The node binary the package is pinned to
+    pub node_path: Option<PathBuf>,
+    pub package_manager: PackageManagerKind,
+    /// Whether the binary runs a working copy linked with `gpkg link`,
+    /// whose directory is in `source`
+    #[serde(default)]
+    pub linked: bool,
+    /// When the package was first installed, in RFC 3339 format
+    pub installed_at: Option<String>,
+    /// When the binary was last written, in RFC 3339 format
+    pub updated_at: Option<String>,
+}
+
+impl MetadataV2 {
+    /// The working copy directory of a linked package
+    pub fn linked_dir(&self) -> Option<PathBuf> {
+        match &self.source {
+            Some(source) if self.linked => Some(PathBuf::from(source.trim_start_matches("file:"))),
+            _ => None,
+        }
+    }
+
+    /// The directory of the installation the binary runs
+    pub fn installation_path(&self, installation_dir: impl AsRef<Path>) -> PathBuf {
+        match &self.installation {
+            Some(installation) => installation_dir.as_ref().join(installation),
+            None => installation_path(installation_dir, &self.package_name),
+        }
+    }
+
+    /// The name of the binary in the package
+    pub fn package_binary(&self) -> &str {
+        self.package_binary.as_deref().unwrap_or(&self.binary_name)
+    }
+
+    /// The metadata of `binary_name` from version 1.0.0 of `package_name`, run with node v14.0.0
+    #[cfg(test)]
+    pub(crate) fn for_test(package_name: &str, binary_name: &str) -> Self {
+        Self {
+            binary_name: binary_name.to_string(),
+            package_name: package_name.to_string(),
+            package_version: Some("1.0.0".to_string()),
+            installation: None,
+            package_binary: None,
+            requested: None,
+            source: None,
+            registry: None,
+            integrity: None,
+            node_version: "v14.0.0".to_string(),
+            node_path: None,
+            package_manager: Default::default(),
+            linked: false,
+            installed_at: None,
+            updated_at: None,
+        }
+    }
+}
+
+impl From<MetadataV1> for MetadataV2 {
+    fn from(v1: MetadataV1) -> Self {
+        Self {
+            binary_name: v1.binary_name,
+            package_name: v1.package_name,
+            package_version: None,
+            installation: None,
+            package_binary: None,
+            requested: None,
+            source: v1.source,
+            registry: None,
+            integrity: v1.integrity,
+            node_version: v1.node_version,
+            node_path: v1.node_path,
+            package_manager: v1.package_manager,
+            linked: v1.linked,
+            installed_at: None,
+            updated_at: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "metadata_version", rename_all = "lowercase")]
 pub enum Metadata {
     V1(MetadataV1),
+    V2(MetadataV2),
 }
 
-pub type LatestMetadata = MetadataV1;
+pub type LatestMetadata = MetadataV2;
+
+/// The current time, in the RFC 3339 format used in metadata
+pub(crate) fn timestamp() -> String {
+    humantime::format_rfc3339_seconds(std::time::SystemTime::now()).to_string()
+}
 
 impl Metadata {
+    /// Upgrades the metadata to the latest version, keeping all of its fields
     pub fn latest(self) -> LatestMetadata {
         match self {
-            Self::V1(m) => m,
+            Self::V1(m) => m.into(),
+            Self::V2(m) => m,
         }
     }
 
+    pub fn is_latest(&self) -> bool {
+        matches!(self, Self::V2(_))
+    }
+
+    /// Replaces the metadata line of every script of an existing binary,
+    /// leaving the rest of them untouched.
+    /// The metadata of native shims is replaced in their sidecar file.
+    pub fn rewrite(&self, binary_path: impl AsRef<Path>) -> std::io::Result<()> {
+        if let Ok(mut sidecar) = Sidecar::read(&binary_path) {
+            sidecar.metadata = serde_json::to_value(self)?;
+            return sidecar.write(&binary_path);
+        }
+        let encoded = base64::encode(&serde_json::to_string(self).unwrap());
+        for form in ScriptForm::ALL {
+            let script_path = form.path(&binary_path);
+            if !script_path.is_file() {
+                continue;
+            }
+            let script = std::fs::read_to_string(&script_path)?;
+            let rewritten: Vec<String> = script
+                .split('\n')
+                .map(|line| {
+                    let prefix = METADATA_PREFIXES
+                        .iter()
+                        .find(|prefix| line.starts_with(*prefix));
+                    match prefix {
+                        Some(prefix) => {
+                            let line_ending = if line.ends_with('\r') { "\r" } else { "" };
+                            format!("{}{}{}", prefix, encoded, line_ending)
+                        }
+                        None => line.to_string(),
+                    }
+                })
+                .collect();
+            std::fs::write(script_path, rewritten.join("\n"))?;
+        }
+        Ok(())
+    }
+
+    /// Reads the metadata embedded in the first script of the binary at `path`,
+    /// or the sidecar file of the native shim at `path`
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, ReadError> {
+        let script_path = ScriptForm::find(&path).unwrap_or_else(|| path.as_ref().to_path_buf());
+        match Self::parse(&std::fs::read(script_path)?) {
+            Err(ReadError::NotGpkg) if sidecar_path(&path).is_file() => {
+                Ok(serde_json::from_value(Sidecar::read(&path)?.metadata)?)
+            }
+            result => result,
+        }
+    }
+
+    fn parse(script: &[u8]) -> Result<Self, ReadError> {
+        let encoded = script
+            .split(|byte| *byte == b'\n')
+            .find_map(|line| {
+                METADATA_PREFIXES
+                    .iter()
+                    .find_map(|prefix| line.strip_prefix(prefix.as_bytes()))
+            })
+            .ok_or(ReadError::NotGpkg)?;
+        let json = base64::decode(encoded.trim_ascii_end())?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    /// Reads every binary in `bin_dir`, sorted by name
+    pub fn scan<BinDir: AsRef<Path>>(bin_dir: BinDir) -> std::io::Result<Vec<BinEntry>> {
+        Ok(binary_paths(bin_dir)?
+            .into_iter()
+            .map(BinEntry::read)
+            .collect())
+    }
+
+    /// Reads the metadata of every gpkg binary in `bin_dir`, sorted by name.
+    /// Foreign files and corrupt scripts are skipped.
     pub fn read_all<BinDir: AsRef<Path>>(bin_dir: BinDir) -> std::io::Result<Vec<LatestMetadata>> {
         let mut binaries = vec![];
-        let metadata_entries = bin_dir.as_ref().read_dir()?.filter_map(Result::ok);
-
-        for entry in metadata_entries {
-            let metadata = Metadata::try_from(std::fs::File::open(entry.path())?)?.latest();
-            binaries.push(metadata);
+        for entry in Self::scan(bin_dir)? {
+            match entry {
+                BinEntry::Binary(metadata) => binaries.push(*metadata),
+                BinEntry::Foreign(_) => {}
+                BinEntry::Corrupt { path, reason } => {
+                    warn!("Skipping {:?}: {}", path, reason);
+                }
+            }
         }
 
         binaries.sort_by(|a, b| a.binary_name.cmp(&b.binary_name));
@@ -39,27 +253,136 @@ impl Metadata {
     }
 }
 
-impl std::convert::TryFrom<std::fs::File> for Metadata {
-    type Error = std::io::Error;
-
-    fn try_from(file: std::fs::File) -> Result<Self, Self::Error> {
-        use std::io::{BufRead, BufReader};
-        let reader = BufReader::new(file);
-
-        for line in reader.lines() {
-            let line = line?;
-            if line.starts_with("# metadata: ") {
-                let metadata: Metadata = serde_json::from_slice(
-                    &base64::decode(line.trim_start_matches("# metadata: "))
-                        .ok()
-                        .ok_or(std::io::ErrorKind::UnexpectedEof)?,
-                )
-                .ok()
-                .ok_or(std::io::ErrorKind::UnexpectedEof)?;
-                return Ok(metadata);
-            }
+/// The node binary the binary at `path` actually runs, read from the sidecar of a native shim
+/// or the `PATH` its script sets, for binaries whose metadata never recorded it
+pub fn script_node_path(path: impl AsRef<Path>) -> Option<PathBuf> {
+    if let Ok(sidecar) = Sidecar::read(&path) {
+        return Some(sidecar.node_path);
+    }
+    let script = std::fs::read_to_string(ScriptForm::find(&path)?).ok()?;
+    let node_dir = script.lines().find_map(|line| {
+        if let Some(quoted) = line
+            .strip_prefix("export PATH=")
+            .and_then(|rest| rest.strip_suffix(":$PATH"))
+        {
+            // the directory is written with `{:?}`, which escapes like a JSON string
+            serde_json::from_str::<String>(quoted).ok()
+        } else {
+            line.trim_end_matches('\r')
+                .strip_prefix("SET \"PATH=")
+                .and_then(|rest| rest.strip_suffix(";%PATH%\""))
+                .map(|dir| dir.replace("%%", "%"))
+        }
+    })?;
+    Some(Path::new(&node_dir).join(format!("node{}", std::env::consts::EXE_SUFFIX)))
+}
+
+/// Why the metadata of a script can't be read
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error("it has no gpkg metadata")]
+    NotGpkg,
+
+    #[error("can't read it: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("its metadata is not valid base64: {0}")]
+    Encoding(#[from] base64::DecodeError),
+
+    #[error("its metadata is not valid: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Whether a file in the bin directory is hidden, and not a binary
+pub(crate) fn is_hidden(file_name: &std::ffi::OsStr) -> bool {
+    file_name.to_string_lossy().starts_with('.')
+}
+
+/// The paths of the binaries in `bin_dir`, sorted.
+/// The `.cmd` and `.ps1` scripts of a binary are all the same binary,
+/// and hidden files, like the sidecar files of native shims, are skipped.
+pub(crate) fn binary_paths(bin_dir: impl AsRef<Path>) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths = BTreeSet::new();
+    for entry in bin_dir.as_ref().read_dir()? {
+        let entry = entry?;
+        if !is_hidden(&entry.file_name()) {
+            paths.insert(ScriptForm::binary_path(&entry.path()));
+        }
+    }
+    Ok(paths.into_iter().collect())
+}
+
+/// A file in the bin directory
+#[derive(Debug)]
+pub enum BinEntry {
+    /// A script written by gpkg
+    Binary(Box<LatestMetadata>),
+    /// A file that wasn't written by gpkg
+    Foreign(PathBuf),
+    /// A script written by gpkg whose metadata can't be read, like a truncated one
+    Corrupt { path: PathBuf, reason: String },
+}
+
+impl BinEntry {
+    /// Reads the binary at `path`, reporting its first script when it's not a valid binary
+    pub fn read(path: PathBuf) -> Self {
+        let script_path = match ScriptForm::find(&path) {
+            Some(script_path) => script_path,
+            None => return Self::Foreign(path),
+        };
+        match Metadata::read(&path) {
+            Ok(metadata) => Self::Binary(Box::new(metadata.latest())),
+            Err(ReadError::NotGpkg) => Self::Foreign(script_path),
+            Err(reason) => Self::Corrupt {
+                path: script_path,
+                reason: reason.to_string(),
+            },
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::Binary;
+    use tempdir::TempDir;
+
+    #[test]
+    fn scans_foreign_and_corrupt_files() {
+        let bin_dir = TempDir::new("bin").unwrap();
+        let metadata = MetadataV1 {
+            binary_name: "qnm".to_string(),
+            package_name: "qnm".to_string(),
+            node_version: "v14.0.0".to_string(),
+            node_path: None,
+            package_manager: Default::default(),
+            source: None,
+            integrity: None,
+            linked: false,
+        };
+        let script_path = bin_dir.path().join("qnm");
+        Binary::new(
+            Metadata::V1(metadata),
+            &script_path,
+            "/qnm",
+            "/usr/bin/node",
+        )
+        .create_script()
+        .unwrap();
+        let script = std::fs::read_to_string(&script_path).unwrap();
+        std::fs::write(bin_dir.path().join("truncated"), &script[..40]).unwrap();
+        std::fs::write(bin_dir.path().join("foreign"), [0xff, 0xfe, b'\n', 0]).unwrap();
+        std::fs::create_dir(bin_dir.path().join("directory")).unwrap();
+
+        let entries = Metadata::scan(bin_dir.path()).unwrap();
+        assert!(matches!(&entries[..], [
+            BinEntry::Foreign(_),
+            BinEntry::Foreign(_),
+            BinEntry::Binary(metadata),
+            BinEntry::Corrupt { path, .. },
+        ] if metadata.binary_name == "qnm" && path.ends_with("truncated")));
 
-        Err(std::io::ErrorKind::UnexpectedEof.into())
+        let binaries = Metadata::read_all(bin_dir.path()).unwrap();
+        assert_eq!(binaries.len(), 1);
     }
 }
@@ -0,0 +1,393 @@
+use crate::registry::{self, integrity, Dist, Packument, PackumentVersion, Registry};
+use crate::storage::timestamp;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// A content-addressed store of the package tarballs gpkg downloaded,
+/// keyed by their sha512 integrity.
+///
+/// Every tarball is kept as `<sha512 hex>.tgz`, next to a `<sha512 hex>.json` entry
+/// telling which package version it is, so versions can be resolved from the cache alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TarballCache {
+    dir: PathBuf,
+    offline: bool,
+}
+
+/// A tarball in the cache
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CachedTarball {
+    pub name: String,
+    pub version: String,
+    /// The `sha512-<base64>` integrity of the tarball
+    pub integrity: String,
+    pub last_used: Option<String>,
+    /// The size of the tarball, in bytes
+    #[serde(default)]
+    pub size: u64,
+}
+
+impl CachedTarball {
+    fn last_used(&self) -> Option<SystemTime> {
+        humantime::parse_rfc3339(self.last_used.as_deref()?).ok()
+    }
+}
+
+/// The outcome of [`TarballCache::verify`]
+#[derive(Debug, Default)]
+pub struct Verification {
+    /// The number of tarballs that are intact
+    pub verified: usize,
+    /// Tarballs that didn't match their integrity, or files that were not tarballs of the cache,
+    /// which were removed
+    pub removed: Vec<PathBuf>,
+}
+
+impl TarballCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            offline: false,
+        }
+    }
+
+    /// The same cache, which never reaches out to the registry:
+    /// versions are resolved from the cached tarballs
+    pub fn offline(self) -> Self {
+        Self {
+            offline: true,
+            ..self
+        }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// The versions of `name` available from `registry`, or from the cache when offline.
+    /// When the registry can't be reached, the cached versions are used as well.
+    pub fn packument(
+        &self,
+        registry: &Registry,
+        name: &str,
+    ) -> Result<Packument, registry::Errors> {
+        if self.offline {
+            return self.cached_packument(name);
+        }
+        match registry.packument(name) {
+            Err(error @ registry::Errors::Http { .. }) => {
+                self.cached_packument(name).map_err(|_| error).inspect(|_| {
+                    warn!(
+                        "Can't reach the registry, using the cached versions of {}",
+                        name
+                    )
+                })
+            }
+            result => result,
+        }
+    }
+
+    /// A packument of the versions of `name` in the cache
+    fn cached_packument(&self, name: &str) -> Result<Packument, registry::Errors> {
+        let mut tarballs: Vec<_> = self
+            .entries()
+            .map_err(|source| self.io_error(source))?
+            .into_iter()
+            .filter(|tarball| tarball.name == name)
+            .collect();
+        if tarballs.is_empty() {
+            return Err(registry::Errors::NotCached {
+                name: name.to_string(),
+            });
+        }
+        tarballs.sort_by_key(|tarball| semver::Version::parse(&tarball.version).ok());
+        let latest = tarballs.last().map(|tarball| tarball.version.clone());
+        let versions: HashMap<_, _> = tarballs
+            .into_iter()
+            .map(|tarball| {
+                let version = PackumentVersion {
+                    os: vec![],
+                    cpu: vec![],
+                    name: tarball.name,
+                    version: tarball.version.clone(),
+                    dependencies: HashMap::new(),
+                    dist: Dist {
+                        tarball: self.tarball_path(&tarball.integrity).display().to_string(),
+                        integrity: Some(tarball.integrity),
+                        shasum: None,
+                    },
+                };
+                (tarball.version, version)
+            })
+            .collect();
+        Ok(Packument {
+            name: name.to_string(),
+            dist_tags: latest
+                .map(|latest| ("latest".to_string(), latest))
+                .into_iter()
+                .collect(),
+            versions,
+        })
+    }
+
+    /// The tarball of `version`, from the cache when it's there,
+    /// otherwise downloaded from `registry` and stored in the cache
+    pub fn tarball(
+        &self,
+        registry: &Registry,
+        version: &PackumentVersion,
+    ) -> Result<Vec<u8>, registry::Errors> {
+        let expected = version
+            .dist
+            .integrity
+            .as_deref()
+            .filter(|integrity| integrity.starts_with("sha512-"));
+        if let Some(expected) = expected {
+            match std::fs::read(self.tarball_path(expected)) {
+                Ok(bytes) if integrity(&bytes) == expected => {
+                    debug!(
+                        "Using cached tarball of {}@{}",
+                        version.name, version.version
+                    );
+                    self.write_entry(&version.name, &version.version, expected, bytes.len())
+                        .map_err(|source| self.io_error(source))?;
+                    return Ok(bytes);
+                }
+                Ok(_) => warn!(
+                    "The cached tarball of {}@{} is corrupt, run `gpkg cache verify`",
+                    version.name, version.version
+                ),
+                Err(_) => {}
+            }
+        }
+        if self.offline {
+            return Err(registry::Errors::NotCached {
+                name: format!("{}@{}", version.name, version.version),
+            });
+        }
+
+        let bytes = registry.tarball(version)?;
+        self.store(&version.name, &version.version, &bytes)
+            .map_err(|source| self.io_error(source))?;
+        Ok(bytes)
+    }
+
+    /// Adds a tarball of `name@version` to the cache, returning its integrity
+    pub fn store(&self, name: &str, version: &str, bytes: &[u8]) -> std::io::Result<String> {
+        let integrity = integrity(bytes);
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.tarball_path(&integrity);
+        if !path.exists() {
+            debug!("Caching the tarball of {}@{} in {:?}", name, version, path);
+            // written aside and renamed, so an interrupted write never looks like a cached tarball
+            let mut temp = tempfile::NamedTempFile::new_in(&self.dir)?;
+            std::io::Write::write_all(&mut temp, bytes)?;
+            temp.persist(&path).map_err(|error| error.error)?;
+        }
+        self.write_entry(name, version, &integrity, bytes.len())?;
+        Ok(integrity)
+    }
+
+    /// Every tarball in the cache, sorted by name and version
+    pub fn entries(&self) -> std::io::Result<Vec<CachedTarball>> {
+        let mut tarballs = vec![];
+        for path in self.files_with_extension("json")? {
+            let entry = std::fs::read(&path)
+                .ok()
+                .and_then(|json| serde_json::from_slice::<CachedTarball>(&json).ok());
+            let size = std::fs::metadata(path.with_extension("tgz")).map(|meta| meta.len());
+            match (entry, size) {
+                (Some(entry), Ok(size)) => tarballs.push(CachedTarball { size, ..entry }),
+                _ => debug!("Skipping the broken cache entry {:?}", path),
+            }
+        }
+        tarballs.sort_by(|a, b| {
+            let version = |tarball: &CachedTarball| semver::Version::parse(&tarball.version).ok();
+            (&a.name, version(a)).cmp(&(&b.name, version(b)))
+        });
+        Ok(tarballs)
+    }
+
+    /// Checks every tarball against its integrity,
+    /// removing the corrupt ones and the files that don't belong to a tarball
+    pub fn verify(&self) -> std::io::Result<Verification> {
+        let mut verification = Verification::default();
+        for path in self.files_with_extension("tgz")? {
+            let entry_path = path.with_extension("json");
+            let is_intact = std::fs::read(&entry_path)
+                .ok()
+                .and_then(|json| serde_json::from_slice::<CachedTarball>(&json).ok())
+                .is_some_and(|entry| {
+                    self.tarball_path(&entry.integrity) == path
+                        && std::fs::read(&path)
+                            .is_ok_and(|bytes| integrity(&bytes) == entry.integrity)
+                });
+            if is_intact {
+                verification.verified += 1;
+            } else {
+                debug!("Removing corrupt tarball {:?}", path);
+                std::fs::remove_file(&path)?;
+                verification.removed.push(path);
+                if entry_path.exists() {
+                    std::fs::remove_file(&entry_path)?;
+                }
+            }
+        }
+        for path in self.files_with_extension("json")? {
+            if !path.with_extension("tgz").exists() {
+                debug!("Removing entry {:?} without a tarball", path);
+                std::fs::remove_file(&path)?;
+                verification.removed.push(path);
+            }
+        }
+        Ok(verification)
+    }
+
+    /// Removes the tarballs that weren't used in the last `older_than`,
+    /// or all of them when it's not given. Returns the removed tarballs.
+    pub fn clean(&self, older_than: Option<Duration>) -> std::io::Result<Vec<CachedTarball>> {
+        let now = SystemTime::now();
+        let mut removed = vec![];
+        for tarball in self.entries()? {
+            let is_stale = match (older_than, tarball.last_used()) {
+                (None, _) | (Some(_), None) => true,
+                (Some(older_than), Some(last_used)) => now
+                    .duration_since(last_used)
+                    .is_ok_and(|age| age > older_than),
+            };
+            if is_stale {
+                let path = self.tarball_path(&tarball.integrity);
+                debug!("Removing cached tarball {:?}", path);
+                std::fs::remove_file(path.with_extension("json"))?;
+                std::fs::remove_file(path)?;
+                removed.push(tarball);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Where the tarball with the `sha512-<base64>` `integrity` is kept
+    fn tarball_path(&self, integrity: &str) -> PathBuf {
+        let digest = base64::decode(integrity.trim_start_matches("sha512-")).unwrap_or_default();
+        let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.dir.join(format!("{}.tgz", hex))
+    }
+
+    fn write_entry(
+        &self,
+        name: &str,
+        version: &str,
+        integrity: &str,
+        size: usize,
+    ) -> std::io::Result<()> {
+        let entry = CachedTarball {
+            name: name.to_string(),
+            version: version.to_string(),
+            integrity: integrity.to_string(),
+            last_used: Some(timestamp()),
+            size: size as u64,
+        };
+        let json = serde_json::to_vec(&entry).expect("Can't serialize the cache entry");
+        std::fs::write(self.tarball_path(integrity).with_extension("json"), json)
+    }
+
+    fn files_with_extension(&self, extension: &str) -> std::io::Result<Vec<PathBuf>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut paths = vec![];
+        for entry in self.dir.read_dir()? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == extension) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn io_error(&self, source: std::io::Error) -> registry::Errors {
+        registry::Errors::Io {
+            path: self.dir.clone(),
+            source,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_registry::{TestPackage, TestRegistry};
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    #[test]
+    fn caches_tarballs_for_offline_use() {
+        let registry = TestRegistry::new();
+        registry
+            .publish(TestPackage::new("left-pad", "1.0.0"))
+            .publish(TestPackage::new("left-pad", "1.3.0"));
+        let cache_dir = TempDir::new("cache").unwrap();
+        let cache = TarballCache::new(cache_dir.path());
+
+        let packument = cache.packument(&registry.registry(), "left-pad").unwrap();
+        let version = packument.resolve("^1.0.0").unwrap().clone();
+        let bytes = cache.tarball(&registry.registry(), &version).unwrap();
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            (entries[0].name.as_str(), entries[0].version.as_str()),
+            ("left-pad", "1.3.0")
+        );
+        assert_eq!(entries[0].size, bytes.len() as u64);
+
+        // offline, versions are resolved from the cache and the registry is never reached
+        let offline = cache.clone().offline();
+        let gone = Registry::new("/nonexistent");
+        let packument = offline.packument(&gone, "left-pad").unwrap();
+        let version = packument.resolve("1").unwrap().clone();
+        assert_eq!(version.version, "1.3.0");
+        assert_eq!(offline.tarball(&gone, &version).unwrap(), bytes);
+        assert!(matches!(
+            packument.resolve("1.0.0"),
+            Err(registry::Errors::NoMatchingVersion { .. })
+        ));
+        assert!(matches!(
+            offline.packument(&gone, "right-pad"),
+            Err(registry::Errors::NotCached { .. })
+        ));
+    }
+
+    #[test]
+    fn verifies_and_cleans_tarballs() {
+        let cache_dir = TempDir::new("cache").unwrap();
+        let cache = TarballCache::new(cache_dir.path());
+        let intact = cache.store("left-pad", "1.0.0", b"intact").unwrap();
+        let corrupt = cache.store("right-pad", "1.0.0", b"corrupt").unwrap();
+        std::fs::write(cache.tarball_path(&corrupt), b"tampered").unwrap();
+        std::fs::write(cache_dir.path().join("stray.json"), "{}").unwrap();
+
+        let verification = cache.verify().unwrap();
+        assert_eq!(verification.verified, 1);
+        assert_eq!(
+            verification.removed,
+            vec![
+                cache.tarball_path(&corrupt),
+                cache_dir.path().join("stray.json")
+            ]
+        );
+        assert_eq!(cache.entries().unwrap().len(), 1);
+
+        assert!(cache
+            .clean(Some(Duration::from_secs(3600)))
+            .unwrap()
+            .is_empty());
+        let removed = cache.clean(None).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].integrity, intact);
+        assert!(cache.entries().unwrap().is_empty());
+    }
+}
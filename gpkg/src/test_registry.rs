@@ -0,0 +1,146 @@
+//! A local, on-disk stand-in for the npm registry, used in tests
+
+use crate::registry::{integrity, Registry};
+use crate::registry_config::RegistryConfig;
+use serde_json::{json, Value};
+use std::path::Path;
+use tempdir::TempDir;
+
+pub struct TestRegistry {
+    dir: TempDir,
+}
+
+/// A package version to publish into a [`TestRegistry`]
+pub struct TestPackage<'a> {
+    pub name: &'a str,
+    pub version: &'a str,
+    pub dependencies: &'a [(&'a str, &'a str)],
+    pub optional_dependencies: &'a [(&'a str, &'a str)],
+    /// The operating systems the package runs on, in npm's terms like `linux` or `!win32`
+    pub os: &'a [&'a str],
+    /// Binaries of the package, as `(name, script source)` pairs
+    pub bin: &'a [(&'a str, &'a str)],
+}
+
+impl<'a> TestPackage<'a> {
+    pub fn new(name: &'a str, version: &'a str) -> Self {
+        Self {
+            name,
+            version,
+            dependencies: &[],
+            optional_dependencies: &[],
+            os: &[],
+            bin: &[],
+        }
+    }
+}
+
+impl TestRegistry {
+    pub fn new() -> Self {
+        Self {
+            dir: TempDir::new("registry").expect("Can't create registry dir"),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn registry(&self) -> Registry {
+        Registry::Local(self.path().to_path_buf())
+    }
+
+    /// A configuration installing every package from this registry
+    pub fn config(&self) -> RegistryConfig {
+        RegistryConfig::with_registry(self.path().display().to_string())
+    }
+
+    /// Publishes a package version, making it the `latest` one
+    pub fn publish(&self, package: TestPackage) -> &Self {
+        let dependencies: serde_json::Map<String, Value> = package
+            .dependencies
+            .iter()
+            .map(|(name, spec)| (name.to_string(), json!(spec)))
+            .collect();
+        let optional_dependencies: serde_json::Map<String, Value> = package
+            .optional_dependencies
+            .iter()
+            .map(|(name, spec)| (name.to_string(), json!(spec)))
+            .collect();
+        let bin: serde_json::Map<String, Value> = package
+            .bin
+            .iter()
+            .map(|(name, _)| (name.to_string(), json!(format!("bin/{}.js", name))))
+            .collect();
+        let manifest = json!({
+            "name": package.name,
+            "version": package.version,
+            "dependencies": dependencies,
+            "optionalDependencies": optional_dependencies,
+            "os": package.os,
+            "bin": bin,
+        });
+
+        let mut files = vec![(
+            "package/package.json".to_string(),
+            serde_json::to_vec_pretty(&manifest).unwrap(),
+        )];
+        for (name, source) in package.bin {
+            files.push((
+                format!("package/bin/{}.js", name),
+                source.as_bytes().to_vec(),
+            ));
+        }
+        let tarball = create_tarball(&files);
+        let tarball_name = format!(
+            "{}-{}.tgz",
+            package.name.replace('/', "-").trim_start_matches('@'),
+            package.version
+        );
+        std::fs::write(self.path().join(&tarball_name), &tarball).unwrap();
+
+        let packument_path = self.path().join(format!("{}.json", package.name));
+        let mut packument: Value = std::fs::read(&packument_path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_else(|| json!({ "name": package.name, "dist-tags": {}, "versions": {} }));
+        let mut version = manifest;
+        version["dist"] = json!({ "tarball": tarball_name, "integrity": integrity(&tarball) });
+        packument["versions"][package.version] = version;
+        packument["dist-tags"]["latest"] = json!(package.version);
+
+        if let Some(parent) = packument_path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        std::fs::write(packument_path, serde_json::to_vec(&packument).unwrap()).unwrap();
+        self
+    }
+}
+
+impl TestRegistry {
+    /// Points the dist-tag `tag` of a published package to `version`
+    pub fn tag(&self, name: &str, tag: &str, version: &str) -> &Self {
+        let packument_path = self.path().join(format!("{}.json", name));
+        let mut packument: Value =
+            serde_json::from_slice(&std::fs::read(&packument_path).unwrap()).unwrap();
+        packument["dist-tags"][tag] = json!(version);
+        std::fs::write(packument_path, serde_json::to_vec(&packument).unwrap()).unwrap();
+        self
+    }
+}
+
+/// Creates a gzipped tarball with the given `(path, contents)` files
+pub fn create_tarball(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for (path, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, path, &contents[..])
+            .unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
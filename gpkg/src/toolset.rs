@@ -0,0 +1,854 @@
+use crate::index::Index;
+use crate::install_package::package_version_in;
+use crate::lockfile::LockedInstallation;
+use crate::node::version_matches;
+use crate::node_package_version::{
+    InvalidPackageSpec, NodePackageVersion, PackageSource, RegistrySpec,
+};
+use crate::package_manager::PackageManagerKind;
+use crate::registry_config::RegistryConfig;
+use crate::tarball_cache::TarballCache;
+use crate::version_range::VersionRange;
+use log::*;
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Errors {
+    #[error("Can't read {path:?}")]
+    #[diagnostic()]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Can't parse {path:?}: {message}")]
+    #[diagnostic(help(
+        "Tools are listed under `[tools]`, as `name = \"version\"` or `name = {{ version = \"...\", node = \"...\" }}`"
+    ))]
+    InvalidManifest { path: PathBuf, message: String },
+
+    #[error("Can't parse {path:?}: {message}")]
+    #[diagnostic(help(
+        "The lock is written by `gpkg sync`. Delete it and run `gpkg sync` to write it again"
+    ))]
+    InvalidLock { path: PathBuf, message: String },
+
+    #[error("{path:?} doesn't exist")]
+    #[diagnostic(help("Run `gpkg sync` without `--frozen` to write it"))]
+    MissingLock { path: PathBuf },
+
+    #[error("{name:?} is not locked in {path:?}")]
+    #[diagnostic(help("Run `gpkg sync` without `--frozen` to lock it"))]
+    NotLocked { name: String, path: PathBuf },
+
+    #[error("{name:?} is locked to {version} in {path:?}, which doesn't match the toolset")]
+    #[diagnostic(help(
+        "Run `gpkg sync` without `--frozen` to lock the version the toolset asks for"
+    ))]
+    LockOutdated {
+        name: String,
+        version: String,
+        path: PathBuf,
+    },
+
+    #[error("Invalid tool {name:?}")]
+    #[diagnostic()]
+    InvalidTool {
+        name: String,
+        #[source]
+        source: Box<InvalidPackageSpec>,
+    },
+}
+
+/// A declarative list of tools, kept in a `gpkg.toml` or `gpkg.json` file:
+///
+/// ```toml
+/// node = "18"
+///
+/// [tools]
+/// typescript = "^4.9"
+/// eslint = { version = "8", node = "16" }
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Toolset {
+    /// The Node.js version tools are pinned to, unless they set their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+    #[serde(default)]
+    pub tools: BTreeMap<String, Tool>,
+}
+
+/// A tool in a [`Toolset`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ToolEntry", into = "ToolEntry")]
+pub struct Tool {
+    /// Anything that can follow `name@` in `gpkg install`: a version, range,
+    /// tag, git repository or local path
+    pub version: String,
+    pub node: Option<String>,
+    pub package_manager: Option<PackageManagerKind>,
+}
+
+/// A tool is either a version string or a table
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ToolEntry {
+    Version(String),
+    Detailed(DetailedTool),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DetailedTool {
+    #[serde(default)]
+    version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    node: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    package_manager: Option<PackageManagerKind>,
+}
+
+impl From<ToolEntry> for Tool {
+    fn from(entry: ToolEntry) -> Self {
+        match entry {
+            ToolEntry::Version(version) => Self {
+                version,
+                node: None,
+                package_manager: None,
+            },
+            ToolEntry::Detailed(tool) => Self {
+                version: tool.version,
+                node: tool.node,
+                package_manager: tool.package_manager,
+            },
+        }
+    }
+}
+
+impl From<Tool> for ToolEntry {
+    fn from(tool: Tool) -> Self {
+        if tool.node.is_none() && tool.package_manager.is_none() {
+            Self::Version(tool.version)
+        } else {
+            Self::Detailed(DetailedTool {
+                version: tool.version,
+                node: tool.node,
+                package_manager: tool.package_manager,
+            })
+        }
+    }
+}
+
+/// A package installed by gpkg, as far as a [`Toolset`] is concerned
+#[derive(Debug, Clone)]
+pub struct InstalledTool {
+    pub name: String,
+    /// The installed version, when it can be read from the installation
+    pub version: Option<String>,
+    pub node_version: String,
+    pub node_path: Option<PathBuf>,
+    pub package_manager: PackageManagerKind,
+    /// Where the package was installed from, when it's not the registry
+    pub source: Option<String>,
+    pub linked: bool,
+    /// The lockfile of the installation, when it has an unmodified one
+    pub lock: Option<LockedInstallation>,
+}
+
+/// A tool requested by a [`Toolset`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolRequest {
+    pub package: NodePackageVersion,
+    pub node: Option<String>,
+    pub package_manager: Option<PackageManagerKind>,
+}
+
+/// What needs to happen to bring the installed tools in line with a [`Toolset`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncAction {
+    Install(ToolRequest),
+    /// The installed version doesn't match the requested one
+    Upgrade {
+        request: ToolRequest,
+        installed_version: Option<String>,
+    },
+    /// The tool is pinned to another Node.js version
+    Rebind {
+        name: String,
+        node: String,
+        installed_node: String,
+    },
+    /// The tool is not in the toolset, and `--prune` was given
+    Uninstall {
+        name: String,
+    },
+    /// The tool isn't installed, or its installed tree differs from the locked one
+    InstallLocked {
+        request: ToolRequest,
+        locked: Box<LockedInstallation>,
+        /// Whether an installation of the tool has to be replaced
+        replaces: bool,
+        installed_version: Option<String>,
+    },
+}
+
+/// The exact dependency trees of the tools of a [`Toolset`], kept next to it by `gpkg sync`
+/// in a JSON file with a `.lock` extension, like `gpkg.lock` for `gpkg.toml`
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolsetLock {
+    #[serde(default)]
+    pub tools: BTreeMap<String, LockedInstallation>,
+}
+
+/// Reads the packages installed in `installation_dir` from the metadata of their binaries
+pub fn installed_tools<InstallationDir: AsRef<Path>, BinDir: AsRef<Path>>(
+    installation_dir: InstallationDir,
+    bin_dir: BinDir,
+) -> std::io::Result<Vec<InstalledTool>> {
+    let mut tools: BTreeMap<String, InstalledTool> = BTreeMap::new();
+    // the main installation of a package describes it, when it has binaries
+    let (main, side_by_side): (Vec<_>, Vec<_>) = Index::load(&installation_dir, bin_dir)?
+        .into_binaries()
+        .into_iter()
+        .partition(|metadata| metadata.installation.is_none());
+    for metadata in main.into_iter().chain(side_by_side) {
+        if tools.contains_key(&metadata.package_name) {
+            continue;
+        }
+        let installation_path = metadata.installation_path(&installation_dir);
+        let lock = if metadata.linked {
+            None
+        } else {
+            LockedInstallation::read(
+                &installation_path,
+                &metadata.package_name,
+                metadata.package_manager,
+            )
+            .ok()
+        };
+        let tool = InstalledTool {
+            version: package_version_in(&installation_path, &metadata.package_name),
+            name: metadata.package_name.clone(),
+            node_version: metadata.node_version,
+            node_path: metadata.node_path,
+            package_manager: metadata.package_manager,
+            source: metadata.source,
+            linked: metadata.linked,
+            lock,
+        };
+        tools.insert(metadata.package_name, tool);
+    }
+    Ok(tools.into_values().collect())
+}
+
+impl Toolset {
+    /// Reads a toolset from a TOML file, or a JSON file when its extension is `.json`
+    pub fn read(path: &Path) -> Result<Self, Errors> {
+        let contents = std::fs::read_to_string(path).map_err(|source| Errors::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let invalid = |message: String| Errors::InvalidManifest {
+            path: path.to_path_buf(),
+            message,
+        };
+        if is_json(path) {
+            serde_json::from_str(&contents).map_err(|error| invalid(error.to_string()))
+        } else {
+            toml::from_str(&contents).map_err(|error| invalid(error.to_string()))
+        }
+    }
+
+    /// Serializes the toolset as TOML, or as JSON when `path` has a `.json` extension
+    pub fn to_string_for(&self, path: &Path) -> String {
+        if is_json(path) {
+            serde_json::to_string_pretty(self).expect("Can't serialize toolset")
+        } else {
+            // going through `toml::Value` puts plain versions before the tables of detailed tools
+            toml::Value::try_from(self)
+                .and_then(|value| toml::to_string(&value))
+                .expect("Can't serialize toolset")
+        }
+    }
+
+    /// A toolset describing the installed tools, pinned to their exact versions.
+    /// Linked packages are left out, as they can't be installed anywhere else.
+    pub fn from_installed(installed: &[InstalledTool]) -> Self {
+        let tools = installed
+            .iter()
+            .filter(|tool| !tool.linked)
+            .map(|tool| {
+                let version = tool
+                    .source
+                    .clone()
+                    .or_else(|| tool.version.clone())
+                    .unwrap_or_else(|| "latest".to_string());
+                let package_manager = Some(tool.package_manager)
+                    .filter(|package_manager| *package_manager != PackageManagerKind::default());
+                let tool_entry = Tool {
+                    version,
+                    node: Some(tool.node_version.clone()),
+                    package_manager,
+                };
+                (tool.name.clone(), tool_entry)
+            })
+            .collect();
+        Self { node: None, tools }
+    }
+
+    /// Parses the tools into package requests
+    pub fn requests(&self) -> Result<Vec<ToolRequest>, Errors> {
+        self.tools
+            .iter()
+            .map(|(name, tool)| {
+                let package = if tool.version.is_empty() {
+                    name.parse()
+                } else {
+                    format!("{}@{}", name, tool.version).parse()
+                }
+                .map_err(|source| Errors::InvalidTool {
+                    name: name.clone(),
+                    source: Box::new(source),
+                })?;
+                Ok(ToolRequest {
+                    package,
+                    node: tool.node.clone().or_else(|| self.node.clone()),
+                    package_manager: tool.package_manager,
+                })
+            })
+            .collect()
+    }
+
+    /// The actions that bring `installed` in line with this toolset.
+    /// Linked packages are never touched.
+    ///
+    /// Tools requested by dist-tag are upgraded when the tag points to another version
+    /// in `registries`. When the registry can't be reached, or the `cache` is offline,
+    /// the installed versions are kept.
+    pub fn plan(
+        &self,
+        installed: &[InstalledTool],
+        prune: bool,
+        registries: &RegistryConfig,
+        cache: Option<&TarballCache>,
+    ) -> Result<Vec<SyncAction>, Errors> {
+        self.plan_resolving(installed, prune, |package, tag| {
+            resolve_tag(package, tag, registries, cache)
+        })
+    }
+
+    /// The actions of [`Toolset::plan`], with dist-tags resolved by `resolve_tag`
+    fn plan_resolving(
+        &self,
+        installed: &[InstalledTool],
+        prune: bool,
+        resolve_tag: impl Fn(&NodePackageVersion, &str) -> Option<String>,
+    ) -> Result<Vec<SyncAction>, Errors> {
+        let requests = self.requests()?;
+        let mut actions = vec![];
+
+        for request in &requests {
+            let name = request.package.name();
+            let installed_tool = match installed.iter().find(|tool| tool.name == name) {
+                None => {
+                    actions.push(SyncAction::Install(request.clone()));
+                    continue;
+                }
+                Some(tool) if tool.linked => continue,
+                Some(tool) => tool,
+            };
+
+            if !is_satisfied(&request.package, installed_tool, &resolve_tag) {
+                actions.push(SyncAction::Upgrade {
+                    request: request.clone(),
+                    installed_version: installed_tool.version.clone(),
+                });
+            } else if let Some(node) = &request.node {
+                if !is_pinned_to(installed_tool, node) {
+                    actions.push(SyncAction::Rebind {
+                        name: name.to_string(),
+                        node: node.clone(),
+                        installed_node: installed_tool.node_version.clone(),
+                    });
+                }
+            }
+        }
+
+        if prune {
+            for tool in installed {
+                let is_requested = requests
+                    .iter()
+                    .any(|request| request.package.name() == tool.name);
+                if !is_requested && !tool.linked {
+                    actions.push(SyncAction::Uninstall {
+                        name: tool.name.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(actions)
+    }
+
+    /// The actions that bring `installed` in line with the exact trees of the tools in `lock`,
+    /// read from `lock_path`. Fails when a tool isn't locked, or is locked to another version
+    /// than the toolset asks for.
+    pub fn plan_frozen(
+        &self,
+        installed: &[InstalledTool],
+        lock: &ToolsetLock,
+        lock_path: &Path,
+        prune: bool,
+    ) -> Result<Vec<SyncAction>, Errors> {
+        let requests = self.requests()?;
+        let mut locked_requests = vec![];
+        for request in requests {
+            let name = request.package.name();
+            let locked = lock.tools.get(name).ok_or_else(|| Errors::NotLocked {
+                name: name.to_string(),
+                path: lock_path.to_path_buf(),
+            })?;
+            if !is_locked_for(&request.package, locked) {
+                return Err(Errors::LockOutdated {
+                    name: name.to_string(),
+                    version: locked.version.clone().unwrap_or_default(),
+                    path: lock_path.to_path_buf(),
+                });
+            }
+            locked_requests.push((request, locked));
+        }
+
+        // the lock has what tags resolved to when it was written, so the registry isn't asked
+        let mut actions: Vec<_> = self
+            .plan_resolving(installed, prune, |_, _| None)?
+            .into_iter()
+            .filter(|action| !matches!(action, SyncAction::Install(_) | SyncAction::Upgrade { .. }))
+            .collect();
+        for (request, locked) in locked_requests {
+            let installed_tool = installed
+                .iter()
+                .find(|tool| tool.name == request.package.name());
+            if installed_tool.is_some_and(|tool| tool.linked || tool.lock.as_ref() == Some(locked))
+            {
+                continue;
+            }
+            // installing the locked tree pins it to the requested node already
+            actions.retain(|action| {
+                !matches!(action, SyncAction::Rebind { name, .. } if name == request.package.name())
+            });
+            actions.push(SyncAction::InstallLocked {
+                request,
+                locked: Box::new(locked.clone()),
+                replaces: installed_tool.is_some(),
+                installed_version: installed_tool.and_then(|tool| tool.version.clone()),
+            });
+        }
+
+        Ok(actions)
+    }
+}
+
+impl ToolsetLock {
+    /// Where the lock of the toolset at `toolset_path` is kept
+    pub fn path_for(toolset_path: &Path) -> PathBuf {
+        toolset_path.with_extension("lock")
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Errors> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Errors::MissingLock {
+                    path: path.to_path_buf(),
+                })
+            }
+            Err(source) => {
+                return Err(Errors::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+        serde_json::from_str(&contents).map_err(|error| Errors::InvalidLock {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Errors> {
+        let contents = serde_json::to_string_pretty(self).expect("Can't serialize the lock");
+        std::fs::write(path, contents).map_err(|source| Errors::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// The lockfiles of the installed tools of `toolset`.
+    /// Tools installed without a lockfile are left out.
+    pub fn from_installed(toolset: &Toolset, installed: &[InstalledTool]) -> Self {
+        let tools = installed
+            .iter()
+            .filter(|tool| toolset.tools.contains_key(&tool.name))
+            .filter_map(|tool| {
+                if tool.lock.is_none() && !tool.linked {
+                    warn!("{} has no lockfile, so it can't be locked", tool.name);
+                }
+                Some((tool.name.clone(), tool.lock.clone()?))
+            })
+            .collect();
+        Self { tools }
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "json")
+}
+
+/// Whether the installed tool matches the requested package.
+/// Dist-tags match the version `resolve_tag` resolves them to, or any version when it can't.
+fn is_satisfied(
+    package: &NodePackageVersion,
+    installed: &InstalledTool,
+    resolve_tag: impl Fn(&NodePackageVersion, &str) -> Option<String>,
+) -> bool {
+    let spec = match package.source() {
+        PackageSource::Registry(spec) | PackageSource::Alias { spec, .. } => spec,
+        _ => return installed.source.as_deref() == Some(package.version().as_str()),
+    };
+    if installed.source.is_some() {
+        // installed from git or local files, but now requested from the registry
+        return false;
+    }
+    match spec {
+        RegistrySpec::Tag(tag) => match resolve_tag(package, tag) {
+            Some(version) => installed.version.as_deref() == Some(version.as_str()),
+            None => true,
+        },
+        RegistrySpec::Version(range) | RegistrySpec::Range(range) => {
+            range_matches(range, installed.version.as_deref())
+        }
+    }
+}
+
+/// Whether the tree locked in `locked` can be installed for the requested package.
+/// Packages from git or local files can't be compared, and are trusted to match,
+/// as are dist-tags, which the lock resolved when it was written.
+fn is_locked_for(package: &NodePackageVersion, locked: &LockedInstallation) -> bool {
+    match package.source() {
+        PackageSource::Registry(RegistrySpec::Version(range) | RegistrySpec::Range(range))
+        | PackageSource::Alias {
+            spec: RegistrySpec::Version(range) | RegistrySpec::Range(range),
+            ..
+        } => range_matches(range, locked.version.as_deref()),
+        _ => true,
+    }
+}
+
+fn range_matches(range: &str, version: Option<&str>) -> bool {
+    let version = version.and_then(|version| semver::Version::parse(version).ok());
+    match (VersionRange::parse(range), version) {
+        (Some(range), Some(version)) => range.matches(&version),
+        _ => false,
+    }
+}
+
+/// The version `tag` of `package` points to in its registry.
+/// `None` when the registry can't be asked, so the installed version is kept.
+fn resolve_tag(
+    package: &NodePackageVersion,
+    tag: &str,
+    registries: &RegistryConfig,
+    cache: Option<&TarballCache>,
+) -> Option<String> {
+    if cache.is_some_and(TarballCache::is_offline) {
+        return None;
+    }
+    let name = package.registry_name().unwrap_or_else(|| package.name());
+    match registries.resolve(name, tag) {
+        Ok(version) => Some(version),
+        Err(error) => {
+            warn!(
+                "Can't resolve {}@{}, keeping the installed version: {}",
+                name, tag, error
+            );
+            None
+        }
+    }
+}
+
+/// Whether the installed tool is pinned to the requested node version or binary
+fn is_pinned_to(installed: &InstalledTool, node: &str) -> bool {
+    if node.contains('/') || node.contains(std::path::MAIN_SEPARATOR) {
+        installed.node_path.as_deref() == Some(Path::new(node))
+    } else {
+        version_matches(node, &installed.node_version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_registry::{TestPackage, TestRegistry};
+    use pretty_assertions::assert_eq;
+
+    fn installed(name: &str, version: &str, node_version: &str) -> InstalledTool {
+        InstalledTool {
+            name: name.to_string(),
+            version: Some(version.to_string()),
+            node_version: node_version.to_string(),
+            node_path: None,
+            package_manager: PackageManagerKind::Npm,
+            source: None,
+            linked: false,
+            lock: None,
+        }
+    }
+
+    fn toolset(toml: &str) -> Toolset {
+        toml::from_str(toml).expect("Can't parse toolset")
+    }
+
+    #[test]
+    fn parses_short_and_detailed_tools() {
+        let toolset = toolset(
+            r#"
+            node = "18"
+
+            [tools]
+            typescript = "^4.9"
+            eslint = { version = "8", node = "16", package-manager = "pnpm" }
+            "#,
+        );
+        assert_eq!(toolset.node.as_deref(), Some("18"));
+        assert_eq!(
+            toolset.tools["typescript"],
+            Tool {
+                version: "^4.9".into(),
+                node: None,
+                package_manager: None,
+            }
+        );
+        assert_eq!(
+            toolset.tools["eslint"],
+            Tool {
+                version: "8".into(),
+                node: Some("16".into()),
+                package_manager: Some(PackageManagerKind::Pnpm),
+            }
+        );
+    }
+
+    #[test]
+    fn plans_installs_upgrades_rebinds_and_prunes() {
+        let toolset = toolset(
+            r#"
+            node = "18"
+
+            [tools]
+            typescript = "^4.9"
+            eslint = "8"
+            prettier = "2"
+            qnm = { version = "latest", node = "16" }
+            "#,
+        );
+        let installed = vec![
+            installed("eslint", "7.32.0", "v18.1.0"),
+            installed("prettier", "2.8.0", "v18.1.0"),
+            installed("qnm", "1.0.1", "v18.1.0"),
+            installed("left-pad", "1.3.0", "v18.1.0"),
+        ];
+        let registry = TestRegistry::new();
+        registry.publish(TestPackage::new("qnm", "1.0.1"));
+
+        let actions = toolset
+            .plan(&installed, true, &registry.config(), None)
+            .unwrap();
+        assert_eq!(
+            summary(&actions),
+            vec![
+                "upgrade eslint@8",
+                "rebind qnm 16",
+                "install typescript@^4.9",
+                "uninstall left-pad",
+            ]
+        );
+    }
+
+    fn summary(actions: &[SyncAction]) -> Vec<String> {
+        actions
+            .iter()
+            .map(|action| match action {
+                SyncAction::Install(request) => format!("install {}", request.package),
+                SyncAction::Upgrade { request, .. } => format!("upgrade {}", request.package),
+                SyncAction::Rebind { name, node, .. } => format!("rebind {} {}", name, node),
+                SyncAction::Uninstall { name } => format!("uninstall {}", name),
+                SyncAction::InstallLocked { request, .. } => {
+                    format!("install locked {}", request.package)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn upgrades_tools_whose_tags_moved() {
+        let toolset = toolset(
+            r#"
+            [tools]
+            qnm = "latest"
+            tool = "next"
+            "#,
+        );
+        let installed = vec![
+            installed("qnm", "1.0.1", "v18.1.0"),
+            installed("tool", "2.0.0-beta.1", "v18.1.0"),
+        ];
+        let registry = TestRegistry::new();
+        registry
+            .publish(TestPackage::new("qnm", "1.0.1"))
+            .publish(TestPackage::new("tool", "1.0.0"))
+            .publish(TestPackage::new("tool", "2.0.0-beta.1"))
+            .tag("tool", "next", "2.0.0-beta.1")
+            .tag("tool", "latest", "1.0.0");
+        let plan = |cache: Option<&TarballCache>| {
+            summary(
+                &toolset
+                    .plan(&installed, false, &registry.config(), cache)
+                    .unwrap(),
+            )
+        };
+        assert_eq!(plan(None), Vec::<String>::new());
+
+        registry
+            .publish(TestPackage::new("qnm", "1.1.0"))
+            .publish(TestPackage::new("tool", "2.0.0-beta.2"))
+            .tag("tool", "next", "2.0.0-beta.2")
+            .tag("tool", "latest", "1.0.0");
+        assert_eq!(plan(None), vec!["upgrade qnm@latest", "upgrade tool@next"]);
+
+        // without the registry, the installed versions are kept
+        let cache_dir = tempdir::TempDir::new("cache").unwrap();
+        let offline = TarballCache::new(cache_dir.path()).offline();
+        assert_eq!(plan(Some(&offline)), Vec::<String>::new());
+        let unreachable = RegistryConfig::with_registry(cache_dir.path().display().to_string());
+        assert_eq!(
+            toolset.plan(&installed, false, &unreachable, None).unwrap(),
+            vec![]
+        );
+    }
+
+    fn locked(version: &str) -> LockedInstallation {
+        LockedInstallation {
+            package_manager: PackageManagerKind::Npm,
+            version: Some(version.to_string()),
+            package_json: "{}".to_string(),
+            lockfile: "package-lock.json".to_string(),
+            lockfile_contents: format!("{{\"version\": \"{}\"}}", version),
+        }
+    }
+
+    #[test]
+    fn plans_frozen_installs_from_the_lock() {
+        let toolset = toolset(
+            r#"
+            [tools]
+            typescript = "^4.9"
+            eslint = "8"
+            prettier = { version = "2", node = "16" }
+            "#,
+        );
+        let mut eslint = installed("eslint", "8.1.0", "v18.1.0");
+        eslint.lock = Some(locked("8.1.0"));
+        let mut prettier = installed("prettier", "2.8.0", "v18.1.0");
+        prettier.lock = Some(locked("2.8.0"));
+        let installed = vec![eslint, prettier];
+        let mut lock = ToolsetLock::default();
+        lock.tools.insert("eslint".into(), locked("8.1.0"));
+        lock.tools.insert("prettier".into(), locked("2.8.1"));
+        let lock_path = Path::new("gpkg.lock");
+
+        assert!(matches!(
+            toolset.plan_frozen(&installed, &lock, lock_path, false),
+            Err(Errors::NotLocked { name, .. }) if name == "typescript"
+        ));
+
+        lock.tools.insert("typescript".into(), locked("5.0.0"));
+        assert!(matches!(
+            toolset.plan_frozen(&installed, &lock, lock_path, false),
+            Err(Errors::LockOutdated { name, .. }) if name == "typescript"
+        ));
+
+        lock.tools.insert("typescript".into(), locked("4.9.5"));
+        let actions = toolset
+            .plan_frozen(&installed, &lock, lock_path, false)
+            .unwrap();
+        let summary: Vec<_> = actions
+            .iter()
+            .map(|action| match action {
+                SyncAction::InstallLocked {
+                    request,
+                    locked,
+                    replaces,
+                    ..
+                } => format!(
+                    "{} {}@{}",
+                    if *replaces { "replace" } else { "install" },
+                    request.package.name(),
+                    locked.version.as_deref().unwrap_or_default()
+                ),
+                action => format!("{:?}", action),
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec!["replace prettier@2.8.1", "install typescript@4.9.5"]
+        );
+
+        assert_eq!(
+            ToolsetLock::from_installed(&toolset, &installed)
+                .tools
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn exported_toolsets_are_in_sync() {
+        let mut from_git = installed("my-tool", "0.1.0", "v16.3.0");
+        from_git.source = Some("github:acme/my-tool".into());
+        let mut linked = installed("my-cli", "0.0.0", "v16.3.0");
+        linked.linked = true;
+        let mut pnpm = installed("prettier", "2.8.0", "v18.1.0");
+        pnpm.package_manager = PackageManagerKind::Pnpm;
+        let installed = vec![
+            installed("eslint", "8.1.0", "v16.3.0"),
+            from_git,
+            linked,
+            pnpm,
+        ];
+
+        let exported = Toolset::from_installed(&installed);
+        assert_eq!(exported.tools.len(), 3);
+        assert_eq!(exported.tools["my-tool"].version, "github:acme/my-tool");
+
+        let toml = exported.to_string_for(Path::new("gpkg.toml"));
+        let toolset: Toolset = toml::from_str(&toml).unwrap();
+        assert_eq!(toolset, exported);
+
+        let mut mixed = toolset.clone();
+        mixed.tools.get_mut("eslint").unwrap().node = None;
+        let toml = mixed.to_string_for(Path::new("gpkg.toml"));
+        assert_eq!(toml::from_str::<Toolset>(&toml).unwrap(), mixed);
+        assert_eq!(
+            toolset
+                .plan(&installed, true, &RegistryConfig::default(), None)
+                .unwrap(),
+            vec![]
+        );
+    }
+}
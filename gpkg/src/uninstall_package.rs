@@ -0,0 +1,109 @@
+use crate::binary::remove_binary;
+use crate::file_manifest::{manifest_path, remove_manifest};
+use crate::index::{update_index, Index};
+use crate::install_package::package_installations;
+use crate::version_range::VersionRange;
+use miette::Diagnostic;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Errors {
+    #[error("Can't read metadata files")]
+    #[diagnostic()]
+    ReadingMetadata {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Can't read the installations directory")]
+    #[diagnostic()]
+    ReadingInstallations {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Can't update the index of installed binaries")]
+    #[diagnostic(help("Run `gpkg reindex` to rebuild it"))]
+    UpdatingIndex {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Can't delete file {binary_path:?}")]
+    #[diagnostic()]
+    RemovingFile {
+        binary_path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Can't delete directory {package_path:?}")]
+    #[diagnostic()]
+    RemovingDirectory {
+        package_path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// What was removed when uninstalling a package
+#[derive(Debug)]
+pub struct UninstalledPackage {
+    pub binaries: Vec<String>,
+    /// The installation directories that were removed
+    pub installation_paths: Vec<PathBuf>,
+}
+
+/// Removes the binaries and the installation directories of a package.
+///
+/// When a `version` range is given, only the installations of versions in the range
+/// and the binaries that run them are removed, otherwise every version of the package is.
+pub fn uninstall_package<InstallationDir: AsRef<Path>, BinDir: AsRef<Path>>(
+    package_name: &str,
+    version: Option<&VersionRange>,
+    installation_dir: InstallationDir,
+    bin_dir: BinDir,
+) -> Result<UninstalledPackage, Errors> {
+    let index = Index::load(&installation_dir, &bin_dir)
+        .map_err(|source| Errors::ReadingMetadata { source })?;
+    let installation_paths: Vec<_> = package_installations(&installation_dir, package_name)
+        .map_err(|source| Errors::ReadingInstallations { source })?
+        .into_iter()
+        .filter(|installation| version.is_none_or(|range| installation.matches(range)))
+        .map(|installation| installation.path)
+        .collect();
+    let binaries = index.package_binaries(package_name).filter(|metadata| {
+        version.is_none()
+            || installation_paths.contains(&metadata.installation_path(&installation_dir))
+    });
+
+    let mut removed_binaries = vec![];
+    for binary_metadata in binaries {
+        let binary_path = bin_dir.as_ref().join(&binary_metadata.binary_name);
+        remove_binary(&binary_path).map_err(|source| Errors::RemovingFile {
+            binary_path,
+            source,
+        })?;
+        removed_binaries.push(binary_metadata.binary_name.clone());
+    }
+
+    for package_path in &installation_paths {
+        std::fs::remove_dir_all(package_path).map_err(|source| Errors::RemovingDirectory {
+            package_path: package_path.clone(),
+            source,
+        })?;
+        remove_manifest(package_path).map_err(|source| Errors::RemovingFile {
+            binary_path: manifest_path(package_path),
+            source,
+        })?;
+    }
+
+    update_index(installation_dir, bin_dir, &removed_binaries)
+        .map_err(|source| Errors::UpdatingIndex { source })?;
+
+    Ok(UninstalledPackage {
+        binaries: removed_binaries,
+        installation_paths,
+    })
+}
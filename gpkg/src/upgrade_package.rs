@@ -0,0 +1,274 @@
+use crate::binary::StagedBinaries;
+use crate::collision::{resolve_collisions, CollisionPolicy};
+use crate::directory_portal::DirectoryPortal;
+use crate::failpoint::failpoint;
+use crate::file_manifest::{record_manifest, remove_manifest, FileManifest};
+use crate::index::{update_index, Index};
+use crate::install_package::{
+    binary_script, installation_path, package_manager_for, prepare_installation,
+    resolve_local_package, Errors, InstallationInfo,
+};
+use crate::node::NodeBinary;
+use crate::node_package_version::NodePackageVersion;
+use crate::package_manager::PackageManagerKind;
+use crate::registry_config::RegistryConfig;
+use crate::tarball_cache::TarballCache;
+use log::*;
+use std::path::Path;
+
+/// Upgrades an installed package to the version requested in `requested_package`.
+///
+/// The new version is installed next to the current one and swapped into place
+/// only when it was installed successfully. If anything fails along the way,
+/// the previous installation and its binaries are restored.
+///
+/// When no `package_manager` is given, the one that installed the package is used.
+/// The package is downloaded from the registry it was installed from, when that was recorded,
+/// and from the one configured in `registries` otherwise.
+/// Only the main installation is upgraded, versions installed side by side are kept,
+/// and the upgraded version takes the binaries of the package that are not aliased.
+pub fn upgrade_package<InstallationDir: AsRef<Path>, BinDir: AsRef<Path>>(
+    requested_package: &NodePackageVersion,
+    node: &NodeBinary,
+    installation_dir: InstallationDir,
+    bin_dir: BinDir,
+    package_manager: Option<PackageManagerKind>,
+    registries: &RegistryConfig,
+    cache: Option<&TarballCache>,
+) -> Result<(), Errors> {
+    let (requested_package, local_package) = resolve_local_package(requested_package)?;
+    let requested_package = &requested_package;
+    let target_path = installation_path(&installation_dir, requested_package.name());
+    let index = Index::load(&installation_dir, &bin_dir)?;
+    let is_linked = index
+        .package_binaries(requested_package.name())
+        .any(|metadata| metadata.linked);
+    if is_linked {
+        return Err(Errors::PackageLinked(requested_package.name().to_string()));
+    }
+    if !target_path.exists() {
+        return Err(Errors::PackageNotInstalled(
+            requested_package.name().to_string(),
+        ));
+    }
+
+    debug!("Using node {} from {}", node.version, node.path.display());
+
+    let previous_binaries = PreviousBinaries::read(&index, requested_package.name());
+    let package_manager = package_manager_for(
+        package_manager
+            .or(previous_binaries.package_manager)
+            .unwrap_or_default(),
+        cache,
+    );
+    let registries = match (
+        &previous_binaries.registry,
+        requested_package.registry_name(),
+    ) {
+        (Some(registry), Some(registry_name)) => registries.pinned(registry_name, registry),
+        _ => registries.clone(),
+    };
+
+    let portal = DirectoryPortal::new_in(&installation_dir, &target_path);
+    let installed_package = prepare_installation(
+        requested_package,
+        local_package.as_ref(),
+        node,
+        package_manager,
+        &registries,
+        cache,
+        &portal,
+    )?;
+    let binaries = resolve_collisions(
+        requested_package.name(),
+        installed_package.binaries(),
+        &bin_dir,
+        &CollisionPolicy::Keep,
+    )?;
+
+    let installation = InstallationInfo::new(
+        requested_package,
+        &installed_package,
+        local_package.as_ref(),
+        node,
+        package_manager,
+        &registries,
+    );
+    let mut staged = StagedBinaries::new(&bin_dir)?;
+    let mut binary_names: Vec<_> = binaries.keys().collect();
+    binary_names.sort();
+    for binary_name in binary_names {
+        staged.stage(binary_script(
+            binary_name,
+            &installation,
+            portal.target(),
+            &bin_dir,
+        ))?;
+    }
+    for binary_name in &previous_binaries.binary_names {
+        if !binaries.contains_key(binary_name) {
+            debug!(
+                "Removing binary {:?} which is gone in the new version",
+                binary_name
+            );
+            staged.remove(binary_name);
+        }
+    }
+
+    let backup_dir = tempfile::Builder::new()
+        .prefix(".upgrade")
+        .tempdir_in(&installation_dir)?;
+    let backup_path = backup_dir.path().join("previous");
+    debug!("Moving {:?} aside to {:?}", &target_path, &backup_path);
+    let previous_manifest = FileManifest::read(&target_path).ok();
+    std::fs::rename(&target_path, &backup_path)?;
+
+    let result = (|| -> Result<(), Errors> {
+        failpoint("teleport")?;
+        let teleport_path = portal.teleport()?;
+        failpoint("manifest")?;
+        record_manifest(teleport_path, package_manager)?;
+        staged.commit()?;
+
+        let changed_binaries = previous_binaries.binary_names.iter().chain(binaries.keys());
+        failpoint("index")?;
+        update_index(&installation_dir, &bin_dir, changed_binaries)?;
+        Ok(())
+    })();
+
+    if let Err(error) = result {
+        warn!(
+            "Upgrade failed, restoring the previous installation: {}",
+            error
+        );
+        if target_path.exists() {
+            std::fs::remove_dir_all(&target_path)?;
+        }
+        std::fs::rename(&backup_path, &target_path)?;
+        match previous_manifest {
+            Some(manifest) => manifest.write(&target_path)?,
+            None => remove_manifest(&target_path)?,
+        }
+        staged.rollback()?;
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+/// The binaries of a package before the upgrade, and how they were installed
+struct PreviousBinaries {
+    binary_names: Vec<String>,
+    package_manager: Option<PackageManagerKind>,
+    registry: Option<String>,
+}
+
+impl PreviousBinaries {
+    fn read(index: &Index, package_name: &str) -> Self {
+        let mut binary_names = vec![];
+        let mut package_manager = None;
+        let mut registry = None;
+        // aliased binaries of versions installed side by side are left alone
+        let binaries = index
+            .package_binaries(package_name)
+            .filter(|metadata| metadata.package_binary.is_none());
+        for metadata in binaries {
+            package_manager = Some(metadata.package_manager);
+            registry = metadata.registry.clone();
+            binary_names.push(metadata.binary_name.clone());
+        }
+        Self {
+            binary_names,
+            package_manager,
+            registry,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failpoint::{fail_at, snapshot};
+    use crate::install_package::{install_package, package_version_in, InstallOptions};
+    use crate::storage::Metadata;
+    use crate::test_registry::{TestPackage, TestRegistry};
+    use pretty_assertions::assert_eq;
+    use std::str::FromStr;
+
+    #[test]
+    fn restores_the_previous_installation_when_failing() {
+        let registry = TestRegistry::new();
+        let bin_v1 = [
+            ("a", "#!/usr/bin/env node\nconsole.log('a1')"),
+            ("b", "#!/usr/bin/env node\nconsole.log('b1')"),
+        ];
+        let bin_v2 = [
+            ("a", "#!/usr/bin/env node\nconsole.log('a2')"),
+            ("c", "#!/usr/bin/env node\nconsole.log('c2')"),
+        ];
+        registry.publish(TestPackage {
+            bin: &bin_v1,
+            ..TestPackage::new("tool", "1.0.0")
+        });
+        let installation_dir = tempdir::TempDir::new("installations").unwrap();
+        let bin_dir = tempdir::TempDir::new("bin").unwrap();
+        let node = NodeBinary::current().expect("Can't find node");
+        install_package(
+            &NodePackageVersion::from_str("tool@1").unwrap(),
+            &node,
+            installation_dir.path(),
+            bin_dir.path(),
+            &InstallOptions {
+                package_manager: PackageManagerKind::Native,
+                registries: registry.config(),
+                ..InstallOptions::default()
+            },
+        )
+        .expect("Can't install");
+        registry.publish(TestPackage {
+            bin: &bin_v2,
+            ..TestPackage::new("tool", "2.0.0")
+        });
+        let upgrade = || {
+            upgrade_package(
+                &NodePackageVersion::from_str("tool@2").unwrap(),
+                &node,
+                installation_dir.path(),
+                bin_dir.path(),
+                None,
+                &registry.config(),
+                None,
+            )
+        };
+        // scripts of other platforms are replaced and restored along with the ones written here
+        std::fs::write(bin_dir.path().join("a.cmd"), "@node a1").unwrap();
+        std::fs::write(bin_dir.path().join("b.ps1"), "node b1").unwrap();
+        let installations = snapshot(installation_dir.path());
+        let binaries = snapshot(bin_dir.path());
+
+        for stage in [
+            "stage a", "stage c", "teleport", "manifest", "commit a", "commit c", "commit b",
+            "index",
+        ] {
+            fail_at(Some(stage));
+            assert!(upgrade().is_err(), "{} didn't fail", stage);
+            assert_eq!(
+                snapshot(installation_dir.path()),
+                installations,
+                "{}",
+                stage
+            );
+            assert_eq!(snapshot(bin_dir.path()), binaries, "{}", stage);
+        }
+
+        fail_at(None);
+        upgrade().expect("Can't upgrade after the failures");
+        let path = installation_path(installation_dir.path(), "tool");
+        assert_eq!(package_version_in(&path, "tool").as_deref(), Some("2.0.0"));
+        assert!(!bin_dir.path().join("b").exists());
+        assert!(!bin_dir.path().join("b.ps1").exists());
+        assert!(!bin_dir.path().join("a.cmd").exists());
+        let metadata = Metadata::read(bin_dir.path().join("c")).unwrap().latest();
+        assert_eq!(metadata.package_version.as_deref(), Some("2.0.0"));
+    }
+}
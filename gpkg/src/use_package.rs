@@ -0,0 +1,222 @@
+use crate::binary::remove_binary;
+use crate::collision::{resolve_collisions, CollisionPolicy};
+use crate::index::{update_index, Index};
+use crate::install_package::{
+    create_binaries, package_installations, Errors, InstallationInfo, InstalledPackage,
+};
+use crate::node::NodeBinary;
+use crate::node_package_version::NodePackageVersion;
+use log::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The installed version that was chosen with [`use_package`]
+#[derive(Debug)]
+pub struct UsedPackage {
+    pub name: String,
+    pub version: Option<String>,
+    pub binaries: Vec<String>,
+}
+
+/// Points the binaries of a package that are not aliased to the installed version
+/// matching `requested_package`, the highest one when several versions match.
+///
+/// The binaries stay pinned to the node binary the package uses.
+/// Binaries that the chosen version does not provide are removed.
+pub fn use_package<InstallationDir: AsRef<Path>, BinDir: AsRef<Path>>(
+    requested_package: &NodePackageVersion,
+    installation_dir: InstallationDir,
+    bin_dir: BinDir,
+) -> Result<UsedPackage, Errors> {
+    let package_name = requested_package.name();
+    let index = Index::load(&installation_dir, &bin_dir)?;
+    let package_binaries: Vec<_> = index.package_binaries(package_name).collect();
+    if package_binaries.iter().any(|metadata| metadata.linked) {
+        return Err(Errors::PackageLinked(package_name.to_string()));
+    }
+
+    let range = requested_package.version_range();
+    let installation = package_installations(&installation_dir, package_name)?
+        .into_iter()
+        .filter(|installation| {
+            range
+                .as_ref()
+                .is_none_or(|range| installation.matches(range))
+        })
+        .max_by_key(|installation| installation.semver())
+        .ok_or_else(|| Errors::PackageNotInstalled(requested_package.to_string()))?;
+
+    // the binaries of the chosen version tell where it came from,
+    // otherwise only the node binary of the package is kept
+    let (metadata, is_same_installation) = match package_binaries
+        .iter()
+        .find(|metadata| metadata.installation_path(&installation_dir) == installation.path)
+    {
+        Some(metadata) => (*metadata, true),
+        None => match package_binaries.first() {
+            Some(metadata) => (*metadata, false),
+            None => return Err(Errors::PackageNotInstalled(package_name.to_string())),
+        },
+    };
+    let node = NodeBinary {
+        path: metadata.node_path.clone().unwrap_or_default(),
+        version: metadata.node_version.clone(),
+    };
+    let mut info = InstallationInfo::from_metadata(metadata, &node);
+    info.package_version = installation.version.clone();
+    info.installation = installation.directory_name();
+    info.aliases = HashMap::new();
+    if !is_same_installation {
+        info.requested = None;
+        info.source = None;
+        info.registry = None;
+        info.integrity = None;
+    }
+
+    let package =
+        InstalledPackage::read(installation.path.join("node_modules").join(package_name))?;
+    let binaries = resolve_collisions(
+        package_name,
+        package.binaries(),
+        &bin_dir,
+        &CollisionPolicy::Fail,
+    )?;
+
+    // binaries of the previous version that the chosen one does not provide
+    let mut stale = vec![];
+    for metadata in &package_binaries {
+        if metadata.package_binary.is_none() && !binaries.contains_key(&metadata.binary_name) {
+            debug!(
+                "Removing binary {} of another version",
+                metadata.binary_name
+            );
+            remove_binary(bin_dir.as_ref().join(&metadata.binary_name))?;
+            stale.push(metadata.binary_name.clone());
+        }
+    }
+
+    debug!("Using {:?} for {}", installation.path, package_name);
+    create_binaries(&info, &binaries, &installation.path, &bin_dir)?;
+    let mut used: Vec<_> = binaries.into_keys().collect();
+    used.sort();
+    update_index(installation_dir, bin_dir, used.iter().chain(&stale))?;
+
+    Ok(UsedPackage {
+        name: package_name.to_string(),
+        version: installation.version,
+        binaries: used,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install_package::{create_binary, installation_path, versioned_installation_path};
+    use crate::package_manager::PackageManagerKind;
+    use crate::storage::{LatestMetadata, Metadata};
+    use crate::uninstall_package::uninstall_package;
+    use crate::version_range::VersionRange;
+    use pretty_assertions::assert_eq;
+    use std::path::PathBuf;
+    use tempdir::TempDir;
+
+    fn node() -> NodeBinary {
+        NodeBinary {
+            path: PathBuf::from("/usr/bin/node"),
+            version: "v14.0.0".to_string(),
+        }
+    }
+
+    /// Writes an installation of `tool` at `path`, with a binary named `binary_name`
+    fn install(path: &Path, version: &str, binary_name: &str, bin_dir: &Path) {
+        let package_dir = path.join("node_modules/tool");
+        std::fs::create_dir_all(&package_dir).unwrap();
+        std::fs::write(
+            package_dir.join("package.json"),
+            format!(
+                r#"{{"name": "tool", "version": "{}", "bin": "cli.js"}}"#,
+                version
+            ),
+        )
+        .unwrap();
+        let node = node();
+        let mut info = InstallationInfo {
+            package_name: "tool",
+            node: &node,
+            package_manager: PackageManagerKind::Npm,
+            package_version: Some(version.to_string()),
+            installation: None,
+            aliases: HashMap::new(),
+            requested: None,
+            source: None,
+            registry: None,
+            integrity: None,
+        };
+        if binary_name != "tool" {
+            info.installation = Some(path.file_name().unwrap().to_string_lossy().to_string());
+            info.aliases
+                .insert(binary_name.to_string(), "tool".to_string());
+        }
+        create_binary(binary_name, &info, path, bin_dir).unwrap();
+        update_index(path.parent().unwrap(), bin_dir, [binary_name]).unwrap();
+    }
+
+    fn read(bin_dir: &Path, binary_name: &str) -> LatestMetadata {
+        Metadata::read(bin_dir.join(binary_name)).unwrap().latest()
+    }
+
+    #[test]
+    fn switches_and_uninstalls_versions() {
+        let installation_dir = TempDir::new("installations").unwrap();
+        let bin_dir = TempDir::new("bin").unwrap();
+        let main_path = installation_path(installation_dir.path(), "tool");
+        let v1_path = versioned_installation_path(installation_dir.path(), "tool", "1.0.0");
+        install(&main_path, "2.0.0", "tool", bin_dir.path());
+        install(&v1_path, "1.0.0", "tool1", bin_dir.path());
+
+        let used = use_package(
+            &"tool@1".parse().unwrap(),
+            installation_dir.path(),
+            bin_dir.path(),
+        )
+        .unwrap();
+        assert_eq!(used.version.as_deref(), Some("1.0.0"));
+        assert_eq!(used.binaries, vec!["tool"]);
+        let metadata = read(bin_dir.path(), "tool");
+        assert_eq!(metadata.installation.as_deref(), Some("tool@1.0.0"));
+        assert_eq!(metadata.package_version.as_deref(), Some("1.0.0"));
+        assert_eq!(metadata.installation_path(installation_dir.path()), v1_path);
+        assert_eq!(read(bin_dir.path(), "tool1").package_binary(), "tool");
+
+        assert!(matches!(
+            use_package(
+                &"tool@3".parse().unwrap(),
+                installation_dir.path(),
+                bin_dir.path()
+            ),
+            Err(Errors::PackageNotInstalled(_))
+        ));
+
+        // uninstalling version 1 removes the binaries that run it, and keeps the main installation
+        let uninstalled = uninstall_package(
+            "tool",
+            VersionRange::parse("1").as_ref(),
+            installation_dir.path(),
+            bin_dir.path(),
+        )
+        .unwrap();
+        assert_eq!(uninstalled.binaries, vec!["tool", "tool1"]);
+        assert_eq!(uninstalled.installation_paths, vec![v1_path]);
+        assert!(main_path.exists());
+
+        install(&main_path, "2.0.0", "tool", bin_dir.path());
+        let used = use_package(
+            &"tool".parse().unwrap(),
+            installation_dir.path(),
+            bin_dir.path(),
+        )
+        .unwrap();
+        assert_eq!(used.version.as_deref(), Some("2.0.0"));
+        assert_eq!(read(bin_dir.path(), "tool").installation, None);
+    }
+}
@@ -0,0 +1,111 @@
+use semver::{Version, VersionReq};
+
+/// A version range in npm's syntax, like `^1.2.0`, `>=1 <2`, `1.x || 2` or `1.0.0 - 1.5.0`
+#[derive(Debug, Clone)]
+pub struct VersionRange {
+    alternatives: Vec<VersionReq>,
+}
+
+impl VersionRange {
+    pub fn parse(range: &str) -> Option<Self> {
+        let alternatives = range
+            .split("||")
+            .map(|alternative| VersionReq::parse(&to_semver_req(alternative)).ok())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self { alternatives })
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.alternatives.iter().any(|req| req.matches(version))
+    }
+
+    /// The highest of `versions` that is in this range
+    pub fn max_satisfying<'a>(
+        &self,
+        versions: impl IntoIterator<Item = &'a Version>,
+    ) -> Option<&'a Version> {
+        versions
+            .into_iter()
+            .filter(|version| self.matches(version))
+            .max()
+    }
+}
+
+/// Converts a single npm comparator set into the comma separated syntax of the `semver` crate.
+/// Bare versions are exact in npm, while `semver` treats them as caret requirements.
+fn to_semver_req(range: &str) -> String {
+    let range = range.trim();
+    if range.is_empty() || range == "latest" {
+        return "*".to_string();
+    }
+
+    if let Some((from, to)) = range.split_once(" - ") {
+        return format!(">={}, <={}", from.trim(), to.trim());
+    }
+
+    let mut comparators = vec![];
+    let mut operator = String::new();
+    for part in range.split_whitespace() {
+        if part.chars().all(|c| "<>=~^".contains(c)) {
+            // an operator separated from its version, like `>= 1.0.0`
+            operator.push_str(part);
+            continue;
+        }
+        let part = format!("{}{}", std::mem::take(&mut operator), part);
+        let starts_with_operator = part.starts_with(|c: char| "<>=~^".contains(c));
+        let is_wildcard = part == "*" || part == "x" || part == "X";
+        if starts_with_operator || is_wildcard {
+            comparators.push(part);
+        } else {
+            comparators.push(format!("={}", part.trim_start_matches('v')));
+        }
+    }
+    comparators.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(range: &str, version: &str) -> bool {
+        VersionRange::parse(range)
+            .unwrap_or_else(|| panic!("Can't parse {:?}", range))
+            .matches(&Version::parse(version).unwrap())
+    }
+
+    #[test]
+    fn bare_versions_are_exact() {
+        assert!(matches("1.2.3", "1.2.3"));
+        assert!(!matches("1.2.3", "1.2.4"));
+        assert!(matches("1.2", "1.2.9"));
+        assert!(!matches("1.2", "1.3.0"));
+    }
+
+    #[test]
+    fn space_separated_comparators() {
+        assert!(matches(">=1 <2", "1.5.0"));
+        assert!(!matches(">=1 <2", "2.0.0"));
+        assert!(matches(">= 1.2.0", "1.2.0"));
+    }
+
+    #[test]
+    fn alternatives_and_hyphen_ranges() {
+        assert!(matches("1.x || ^3.1.0", "3.2.0"));
+        assert!(!matches("1.x || ^3.1.0", "2.0.0"));
+        assert!(matches("1.0.0 - 1.5.0", "1.5.0"));
+        assert!(!matches("1.0.0 - 1.5.0", "1.5.1"));
+    }
+
+    #[test]
+    fn max_satisfying() {
+        let versions: Vec<_> = ["1.0.0", "1.4.2", "2.0.0"]
+            .iter()
+            .map(|v| Version::parse(v).unwrap())
+            .collect();
+        let range = VersionRange::parse("^1").unwrap();
+        assert_eq!(
+            range.max_satisfying(&versions),
+            Some(&Version::parse("1.4.2").unwrap())
+        );
+    }
+}
@@ -9,6 +9,14 @@ enum Commands {
     #[structopt(name = "install", alias = "add", alias = "i")]
     Install(commands::Install),
 
+    /// Run a binary of a package without installing it, from a cached installation
+    #[structopt(name = "x", alias = "run")]
+    Run(commands::Run),
+
+    /// Manage the cache of packages run with `gpkg x`
+    #[structopt(name = "cache")]
+    Cache(commands::Cache),
+
     /// Generate completions for your shell
     #[structopt(name = "completions")]
     Completions(commands::Completions),
@@ -17,18 +25,95 @@ enum Commands {
     #[structopt(name = "list", alias = "ls")]
     List(commands::List),
 
+    /// Show which package a binary belongs to, and what it runs
+    #[structopt(name = "which")]
+    Which(commands::Which),
+
+    /// Show the details of an installed package
+    #[structopt(name = "info")]
+    Info(commands::Info),
+
+    /// Show the installed packages that have newer versions in the registry
+    #[structopt(name = "outdated")]
+    Outdated(commands::Outdated),
+
+    /// Check the files of the installed packages against the hashes recorded when they were installed
+    #[structopt(name = "verify")]
+    Verify(commands::Verify),
+
+    /// Install a package again, exactly as locked in the lockfile kept in its installation
+    #[structopt(name = "reinstall")]
+    Reinstall(commands::Reinstall),
+
     /// Uninstall a package
     #[structopt(name = "uninstall", alias = "remove", alias = "rm")]
     Uninstall(commands::Uninstall),
+
+    /// Upgrade an installed package to a newer version
+    #[structopt(name = "upgrade", alias = "up")]
+    Upgrade(commands::Upgrade),
+
+    /// Choose which installed version of a package owns its binaries
+    #[structopt(name = "use")]
+    Use(commands::Use),
+
+    /// Pin the binaries of installed packages to another Node.js version
+    #[structopt(name = "rebind")]
+    Rebind(commands::Rebind),
+
+    /// Link the binaries of a package you're developing, pinned to a Node.js version
+    #[structopt(name = "link")]
+    Link(commands::Link),
+
+    /// Remove the binaries of a linked package
+    #[structopt(name = "unlink")]
+    Unlink(commands::Unlink),
+
+    /// Install, upgrade and rebind packages to match a toolset file
+    #[structopt(name = "sync")]
+    Sync(commands::Sync),
+
+    /// Print a toolset file describing the installed packages
+    #[structopt(name = "export")]
+    Export(commands::Export),
+
+    /// Check installed packages, their binaries and Node.js versions for problems
+    #[structopt(name = "doctor")]
+    Doctor(commands::Doctor),
+
+    /// Rewrite binaries installed by older gpkg versions with the latest metadata format
+    #[structopt(name = "migrate")]
+    Migrate(commands::Migrate),
+
+    /// Rebuild the index of installed binaries from the binaries themselves
+    #[structopt(name = "reindex")]
+    Reindex(commands::Reindex),
 }
 
 impl Commands {
     pub fn call(self, config: Config) -> Result<()> {
         match self {
             Self::Install(cmd) => cmd.apply(config)?,
+            Self::Run(cmd) => cmd.apply(config)?,
+            Self::Cache(cmd) => cmd.apply(config)?,
             Self::Completions(cmd) => cmd.apply(config)?,
             Self::List(cmd) => cmd.apply(config)?,
+            Self::Which(cmd) => cmd.apply(config)?,
+            Self::Info(cmd) => cmd.apply(config)?,
+            Self::Outdated(cmd) => cmd.apply(config)?,
+            Self::Verify(cmd) => cmd.apply(config)?,
+            Self::Reinstall(cmd) => cmd.apply(config)?,
             Self::Uninstall(cmd) => cmd.apply(config)?,
+            Self::Upgrade(cmd) => cmd.apply(config)?,
+            Self::Use(cmd) => cmd.apply(config)?,
+            Self::Rebind(cmd) => cmd.apply(config)?,
+            Self::Link(cmd) => cmd.apply(config)?,
+            Self::Unlink(cmd) => cmd.apply(config)?,
+            Self::Sync(cmd) => cmd.apply(config)?,
+            Self::Export(cmd) => cmd.apply(config)?,
+            Self::Doctor(cmd) => cmd.apply(config)?,
+            Self::Migrate(cmd) => cmd.apply(config)?,
+            Self::Reindex(cmd) => cmd.apply(config)?,
         };
         Ok(())
     }
@@ -0,0 +1,154 @@
+use super::Command;
+use crate::config::Config;
+use crate::print_format::PrintFormat;
+use colored::*;
+use gpkg::run_package::clean_cache;
+use gpkg::tarball_cache::CachedTarball;
+use std::time::Duration;
+use structopt::StructOpt;
+use thiserror::Error;
+
+#[derive(StructOpt, Debug)]
+pub enum Cache {
+    /// List the tarballs in the cache
+    #[structopt(name = "ls", alias = "list")]
+    Ls {
+        #[structopt(long, default_value = "list", possible_values = PrintFormat::variants())]
+        format: PrintFormat,
+    },
+
+    /// Check the cached tarballs against their integrity, removing the corrupt ones
+    #[structopt(name = "verify")]
+    Verify,
+
+    /// Remove cached tarballs and the cached installations of packages run with `gpkg x`
+    #[structopt(name = "clean")]
+    Clean {
+        /// Only remove what wasn't used in this long, like `30days` or `12h`
+        #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+        older_than: Option<Duration>,
+
+        /// Only remove cached tarballs
+        #[structopt(long, conflicts_with = "runs")]
+        tarballs: bool,
+
+        /// Only remove the cached installations of packages run with `gpkg x`
+        #[structopt(long)]
+        runs: bool,
+    },
+}
+
+#[derive(Debug, Error, miette::Diagnostic)]
+pub enum Errors {
+    #[error("Can't read the cache")]
+    #[diagnostic()]
+    Reading {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Can't clean the cache")]
+    #[diagnostic()]
+    Cleaning {
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl Command for Cache {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        match self {
+            Self::Ls { format } => {
+                let tarballs = config
+                    .tarball_cache()
+                    .entries()
+                    .map_err(|source| Errors::Reading { source })?;
+                match format {
+                    PrintFormat::List => print_pretty_list(&tarballs),
+                    PrintFormat::Table => print_pretty_table(&tarballs),
+                    PrintFormat::Json => print_json(&tarballs),
+                }
+            }
+            Self::Verify => {
+                let verification = config
+                    .tarball_cache()
+                    .verify()
+                    .map_err(|source| Errors::Reading { source })?;
+                for path in &verification.removed {
+                    println!("Removed {}", path.display().to_string().yellow());
+                }
+                println!(
+                    "Verified {} tarball(s), removed {} corrupt file(s)",
+                    verification.verified,
+                    verification.removed.len()
+                );
+            }
+            Self::Clean {
+                older_than,
+                tarballs,
+                runs,
+            } => {
+                if !runs {
+                    let removed = config
+                        .tarball_cache()
+                        .clean(older_than)
+                        .map_err(|source| Errors::Cleaning { source })?;
+                    println!("Removed {} cached tarball(s)", removed.len());
+                }
+                if !tarballs {
+                    let removed = clean_cache(config.run_cache_dir(), older_than)
+                        .map_err(|source| Errors::Cleaning { source })?;
+                    for entry in &removed {
+                        println!("Removed {}", entry.name().cyan());
+                    }
+                    println!("Removed {} cached installation(s)", removed.len());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn print_pretty_list(tarballs: &[CachedTarball]) {
+    for tarball in tarballs {
+        println!(
+            "{}{} {}",
+            tarball.name,
+            format!("@{}", tarball.version).cyan(),
+            format!(
+                "{} bytes, last used {}",
+                tarball.size,
+                tarball.last_used.as_deref().unwrap_or("never")
+            )
+            .dimmed()
+            .italic()
+        );
+    }
+}
+
+fn print_pretty_table(tarballs: &[CachedTarball]) {
+    use prettytable::*;
+
+    let mut table = Table::new();
+
+    table.add_row(row![Fc => "package", "version", "size", "last used", "integrity"]);
+
+    for tarball in tarballs {
+        table.add_row(row![
+            tarball.name,
+            tarball.version,
+            tarball.size,
+            tarball.last_used.as_deref().unwrap_or_default(),
+            tarball.integrity,
+        ]);
+    }
+
+    table.printstd();
+}
+
+fn print_json(tarballs: &[CachedTarball]) {
+    let json = serde_json::to_string(tarballs).expect("Can't make into a JSON string");
+    println!("{}", json);
+}
@@ -0,0 +1,144 @@
+use super::Command;
+use crate::config::Config;
+use colored::*;
+use gpkg::doctor::{diagnose, repair};
+use gpkg::install_package;
+use miette::{Diagnostic, Severity};
+use structopt::StructOpt;
+use thiserror::Error;
+
+#[derive(StructOpt, Debug)]
+pub struct Doctor {
+    /// Fix the issues that can be fixed: regenerate outdated scripts, remove the binaries
+    /// of missing packages, reinstall broken packages and rebind packages to an available
+    /// Node.js version
+    #[structopt(long)]
+    repair: bool,
+
+    /// Also remove installations without binaries when repairing, including packages
+    /// whose binaries all went to other packages in collisions
+    #[structopt(long, requires = "repair")]
+    prune_orphans: bool,
+}
+
+#[derive(Debug, Error, miette::Diagnostic)]
+pub enum Errors {
+    #[error("Can't read the installed packages")]
+    #[diagnostic()]
+    Reading {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Repair(#[from] install_package::Errors),
+
+    #[error("Found {count} issue(s)")]
+    #[diagnostic(help("Run `gpkg doctor --repair` to fix what can be fixed automatically"))]
+    IssuesFound { count: usize },
+}
+
+impl Command for Doctor {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let read = || {
+            diagnose(config.installations_dir(), config.bin_dir())
+                .map_err(|source| Errors::Reading { source })
+        };
+        let mut issues = read()?;
+
+        if self.repair && !issues.is_empty() {
+            let registries = config.registries().map_err(install_package::Errors::from)?;
+            for fix in repair(
+                &issues,
+                config.installations_dir(),
+                config.bin_dir(),
+                &registries,
+                Some(&config.tarball_cache()),
+                self.prune_orphans,
+            )? {
+                println!("{} {}", "✓".green(), fix);
+            }
+            issues = read()?;
+        }
+
+        if issues.is_empty() {
+            println!("No issues found");
+            return Ok(());
+        }
+
+        // warnings, like files gpkg didn't create, are reported without failing
+        let count = issues
+            .iter()
+            .filter(|issue| {
+                issue
+                    .severity()
+                    .is_none_or(|severity| severity == Severity::Error)
+            })
+            .count();
+        for issue in issues {
+            eprintln!("{:?}", miette::Report::new(issue));
+        }
+        if count > 0 {
+            return Err(Errors::IssuesFound { count });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Install;
+    use crate::test_packages::write_package;
+    use tempdir::TempDir;
+
+    fn doctor(args: &[&str], config: &Config) -> Result<(), Errors> {
+        let args = std::iter::once(&"doctor").chain(args);
+        Doctor::from_iter(args).apply(config.clone())
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let doctor = Doctor::from_iter_safe(&["doctor", "--repair", "--prune-orphans"]).unwrap();
+        assert!(doctor.repair);
+        assert!(doctor.prune_orphans);
+        assert!(Doctor::from_iter_safe(&["doctor", "--prune-orphans"]).is_err());
+    }
+
+    #[test]
+    fn repairs_missing_installations_and_prunes_orphans() {
+        let sources = TempDir::new("sources").unwrap();
+        let config = Config::default();
+        for name in ["alpha", "beta"].iter() {
+            let package_dir = write_package(sources.path(), name, "1.0.0");
+            let package_dir = package_dir.display().to_string();
+            Install::from_iter(&["install", "--package-manager", "native", &package_dir])
+                .apply(config.clone())
+                .unwrap();
+        }
+        doctor(&[], &config).unwrap();
+
+        std::fs::remove_dir_all(config.installations_dir().join("alpha")).unwrap();
+        let orphan = config.installations_dir().join("orphan");
+        std::fs::create_dir_all(&orphan).unwrap();
+        let result = doctor(&[], &config);
+        assert!(
+            matches!(result, Err(Errors::IssuesFound { count: 1 })),
+            "{:?}",
+            result
+        );
+
+        doctor(&["--repair"], &config).unwrap();
+        assert!(!config.bin_dir().join("alpha").exists());
+        assert!(orphan.exists());
+        assert!(config.bin_dir().join("beta").exists());
+
+        doctor(&["--repair", "--prune-orphans"], &config).unwrap();
+        assert!(!orphan.exists());
+        assert!(config.bin_dir().join("beta").exists());
+        doctor(&[], &config).unwrap();
+    }
+}
@@ -0,0 +1,59 @@
+use super::Command;
+use crate::config::Config;
+use gpkg::toolset::{installed_tools, Toolset};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use thiserror::Error;
+
+#[derive(StructOpt, Debug)]
+pub struct Export {
+    /// Write the toolset into this file instead of printing it.
+    /// Files with a `.json` extension are written as JSON
+    #[structopt(long, short, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Print the toolset as JSON instead of TOML
+    #[structopt(long)]
+    json: bool,
+}
+
+#[derive(Debug, Error, miette::Diagnostic)]
+pub enum Errors {
+    #[error("Can't read metadata files")]
+    #[diagnostic()]
+    ReadingMetadata {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Can't write {path:?}")]
+    #[diagnostic()]
+    Writing {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl Command for Export {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let installed = installed_tools(config.installations_dir(), config.bin_dir())
+            .map_err(|source| Errors::ReadingMetadata { source })?;
+        let toolset = Toolset::from_installed(&installed);
+
+        match self.output {
+            Some(path) => {
+                let contents = toolset.to_string_for(&path);
+                std::fs::write(&path, contents)
+                    .map_err(|source| Errors::Writing { path, source })?;
+            }
+            None => {
+                let format = if self.json { "gpkg.json" } else { "gpkg.toml" };
+                print!("{}", toolset.to_string_for(Path::new(format)));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use super::Command;
+use crate::config::Config;
+use crate::print_format::PrintFormat;
+use colored::*;
+use gpkg::info::{package_info, Errors, PackageInfo};
+use gpkg::node_package_version::NodePackageVersion;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Info {
+    /// The installed package. Takes the format of `name[@version]`,
+    /// where a version or range only shows the installed versions in it
+    package: NodePackageVersion,
+
+    #[structopt(long, default_value = "list", possible_values = &["list", "json"])]
+    format: PrintFormat,
+}
+
+impl Command for Info {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let infos = package_info(
+            config.installations_dir(),
+            config.bin_dir(),
+            self.package.name(),
+            self.package.version_range().as_ref(),
+        )?;
+        match self.format {
+            PrintFormat::Json => {
+                let json = serde_json::to_string(&infos).expect("Can't make into a JSON string");
+                println!("{}", json);
+            }
+            PrintFormat::List | PrintFormat::Table => {
+                for (i, info) in infos.iter().enumerate() {
+                    if i > 0 {
+                        println!();
+                    }
+                    print_package_info(info);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn print_package_info(info: &PackageInfo) {
+    println!(
+        "{}@{}",
+        info.name.cyan(),
+        info.version.as_deref().unwrap_or("unknown").cyan()
+    );
+    let field = |name: &str, value: Option<&str>| {
+        if let Some(value) = value {
+            println!("  {:<13} {}", name.dimmed(), value);
+        }
+    };
+    field("description", info.description.as_deref());
+    field("homepage", info.homepage.as_deref());
+    field("license", info.license.as_deref());
+    let binaries = if info.binaries.is_empty() {
+        "(none, use `gpkg use` to switch to this version)".to_string()
+    } else {
+        info.binaries.join(", ")
+    };
+    field("binaries", Some(&binaries));
+    let path = info.path.display().to_string();
+    field(if info.linked { "linked to" } else { "path" }, Some(&path));
+    field("disk size", Some(&format_size(info.disk_size)));
+    let node = info
+        .node_version
+        .as_ref()
+        .map(|node_version| node_version.yellow().to_string());
+    field("node", node.as_deref());
+    let package_manager = info
+        .package_manager
+        .map(|package_manager| package_manager.to_string());
+    field("installed by", package_manager.as_deref());
+    field("source", info.source.as_deref());
+    field("installed at", info.installed_at.as_deref());
+    field("updated at", info.updated_at.as_deref());
+}
+
+/// Formats a number of bytes in the largest binary unit that keeps it above 1
+fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", bytes, units[0]),
+        _ => format!("{:.1} {}", size, units[unit]),
+    }
+}
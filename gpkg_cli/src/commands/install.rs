@@ -1,14 +1,57 @@
 use super::Command;
 use crate::config::Config;
-use gpkg::install_package;
+use gpkg::collision::CollisionPolicy;
+use gpkg::install_package::{self, BinaryNames, InstallOptions};
+use gpkg::node::NodeBinary;
 use gpkg::node_package_version::NodePackageVersion;
+use gpkg::package_manager::PackageManagerKind;
 use log::*;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 pub struct Install {
-    /// The npm package to install. Takes the format of `name[@version]`
+    /// The npm package to install. Takes the format of `name[@version]`,
+    /// or a path to a local directory or tarball, like `./tool` or `file:tool-1.2.0.tgz`
     package: NodePackageVersion,
+
+    /// The Node.js version to pin the package to: a version installed
+    /// using fnm, nvm or asdf, or a path to a node binary.
+    /// Defaults to the `node` in your PATH
+    #[structopt(long)]
+    node: Option<String>,
+
+    /// Overwrite binaries that are provided by other packages
+    #[structopt(long, conflicts_with = "prefer")]
+    force: bool,
+
+    /// When a binary is provided by another package,
+    /// let the given package own it
+    #[structopt(long)]
+    prefer: Option<String>,
+
+    /// The package manager used to install the package.
+    /// Defaults to the `package_manager` set in `config.toml`, or npm
+    #[structopt(
+        long,
+        env = "GPKG_PACKAGE_MANAGER",
+        possible_values = PackageManagerKind::variants()
+    )]
+    package_manager: Option<PackageManagerKind>,
+
+    /// Install from gpkg's tarball cache, without reaching the network.
+    /// Uses the native installer, which fills the cache on every install
+    #[structopt(long)]
+    offline: bool,
+
+    /// Install the version side by side with the other versions of the package,
+    /// exposing its only binary under the given name
+    #[structopt(long = "as", conflicts_with = "bin-suffix")]
+    alias: Option<String>,
+
+    /// Install the version side by side with the other versions of the package,
+    /// appending the given suffix to the names of its binaries
+    #[structopt(long)]
+    bin_suffix: Option<String>,
 }
 
 impl Command for Install {
@@ -16,10 +59,40 @@ impl Command for Install {
 
     fn apply(self, config: Config) -> Result<(), Self::Error> {
         debug!("Installing package {:?}", &self.package);
+        let node = match &self.node {
+            Some(node) => NodeBinary::resolve(node)?,
+            None => NodeBinary::current()?,
+        };
+        let collision_policy = match self.prefer {
+            Some(package) => CollisionPolicy::Prefer(package),
+            None if self.force => CollisionPolicy::Force,
+            None => CollisionPolicy::Fail,
+        };
+        let binary_names = match (self.alias, self.bin_suffix) {
+            (Some(alias), _) => BinaryNames::As(alias),
+            (None, Some(suffix)) => BinaryNames::Suffix(suffix),
+            (None, None) => BinaryNames::Unchanged,
+        };
+        let tarball_cache = config.tarball_cache();
+        let tarball_cache = if self.offline {
+            tarball_cache.offline()
+        } else {
+            tarball_cache
+        };
+        let registries = config.registries()?;
         install_package::install_package(
             &self.package,
+            &node,
             config.installations_dir(),
             config.bin_dir(),
+            &InstallOptions {
+                collision_policy,
+                package_manager: registries.package_manager_or(self.package_manager),
+                binary_names,
+                registries,
+                cache: Some(tarball_cache),
+                locked: None,
+            },
         )?;
         Ok(())
     }
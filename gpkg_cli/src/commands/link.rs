@@ -0,0 +1,56 @@
+use super::Command;
+use crate::config::Config;
+use colored::*;
+use gpkg::collision::CollisionPolicy;
+use gpkg::install_package;
+use gpkg::link_package::link_package;
+use gpkg::node::NodeBinary;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Link {
+    /// The directory of the package to link. Defaults to the current directory
+    #[structopt(default_value = ".", parse(from_os_str))]
+    path: PathBuf,
+
+    /// The Node.js version to pin the binaries to: a version installed
+    /// using fnm, nvm or asdf, or a path to a node binary.
+    /// Defaults to the `node` in your PATH
+    #[structopt(long)]
+    node: Option<String>,
+
+    /// Overwrite binaries that are provided by other packages
+    #[structopt(long)]
+    force: bool,
+}
+
+impl Command for Link {
+    type Error = install_package::Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let node = match &self.node {
+            Some(node) => NodeBinary::resolve(node)?,
+            None => NodeBinary::current()?,
+        };
+        let collision_policy = if self.force {
+            CollisionPolicy::Force
+        } else {
+            CollisionPolicy::Fail
+        };
+        let linked = link_package(
+            &self.path,
+            &node,
+            config.installations_dir(),
+            config.bin_dir(),
+            &collision_policy,
+        )?;
+        println!(
+            "Linked {} ({}) using node {}",
+            linked.name.cyan(),
+            linked.binaries.join(", "),
+            node.version.yellow()
+        );
+        Ok(())
+    }
+}
@@ -2,8 +2,8 @@ use super::Command;
 use crate::config::Config;
 use crate::print_format::PrintFormat;
 use colored::*;
-use gpkg::storage::{LatestMetadata, Metadata};
-use prettytable::*;
+use gpkg::index::Index;
+use gpkg::storage::LatestMetadata;
 use structopt::StructOpt;
 use thiserror::Error;
 
@@ -24,7 +24,9 @@ impl Command for List {
     type Error = Errors;
 
     fn apply(self, config: Config) -> Result<(), Self::Error> {
-        let binaries = Metadata::read_all(config.bin_dir())
+        let binaries = config
+            .index()
+            .map(Index::into_binaries)
             .map_err(|cause| Errors::CantReadFiles { cause })?;
         match self.format {
             PrintFormat::List => print_metadata_pretty_list(&binaries),
@@ -41,15 +43,30 @@ fn print_metadata_pretty_list(metadatas: impl AsRef<[LatestMetadata]>) {
         .iter()
         .map(|x| x.binary_name.len())
         .max()
-        .unwrap();
+        .unwrap_or_default();
     for metadata in metadatas.as_ref().iter() {
+        let version = match &metadata.package_version {
+            Some(version) => format!("@{}", version).cyan().to_string(),
+            None => String::new(),
+        };
+        let alias = match &metadata.package_binary {
+            Some(package_binary) => format!(", alias of {}", package_binary.cyan()),
+            None => String::new(),
+        };
+        let linked = match metadata.linked_dir() {
+            Some(dir) => format!(", linked to {}", dir.display().to_string().magenta()),
+            None => String::new(),
+        };
         println!(
             "{:<width$} {}",
             metadata.binary_name,
             format!(
-                "from {}, node {}",
+                "from {}{}{}, node {}{}",
                 metadata.package_name.cyan(),
-                metadata.node_version.yellow()
+                version,
+                alias,
+                metadata.node_version.yellow(),
+                linked
             )
             .dimmed()
             .italic(),
@@ -63,13 +80,22 @@ fn print_metadata_pretty_table(metadatas: impl AsRef<[LatestMetadata]>) {
 
     let mut table = Table::new();
 
-    table.add_row(row![Fc => "binary", "package", "node version"]);
+    table.add_row(
+        row![Fc => "binary", "package", "version", "alias of", "node version", "linked to"],
+    );
 
     for metadata in metadatas.as_ref().iter() {
+        let linked_dir = metadata
+            .linked_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default();
         table.add_row(row![
             metadata.binary_name,
             metadata.package_name,
+            metadata.package_version.as_deref().unwrap_or_default(),
+            metadata.package_binary.as_deref().unwrap_or_default(),
             metadata.node_version,
+            linked_dir,
         ]);
     }
 
@@ -0,0 +1,35 @@
+use super::Command;
+use crate::config::Config;
+use colored::*;
+use gpkg::migrate::migrate;
+use structopt::StructOpt;
+use thiserror::Error;
+
+#[derive(StructOpt, Debug)]
+pub struct Migrate {}
+
+#[derive(Debug, Error, miette::Diagnostic)]
+pub enum Errors {
+    #[error("Can't migrate the installed binaries")]
+    #[diagnostic()]
+    Migrating {
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl Command for Migrate {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let migrated = migrate(config.installations_dir(), config.bin_dir())
+            .map_err(|source| Errors::Migrating { source })?;
+        if migrated.is_empty() {
+            println!("All binaries are up to date");
+        }
+        for binary_name in migrated {
+            println!("Migrated binary {}", binary_name.cyan());
+        }
+        Ok(())
+    }
+}
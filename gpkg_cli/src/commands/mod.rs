@@ -1,11 +1,45 @@
+mod cache;
 mod command;
 mod completions;
+mod doctor;
+mod export;
+mod info;
 mod install;
+mod link;
 mod list;
+mod migrate;
+mod outdated;
+mod rebind;
+mod reindex;
+mod reinstall;
+mod run;
+mod sync;
 mod uninstall;
+mod unlink;
+mod upgrade;
+mod use_version;
+mod verify;
+mod which;
 
+pub use cache::Cache;
 pub use command::Command;
 pub use completions::Completions;
+pub use doctor::Doctor;
+pub use export::Export;
+pub use info::Info;
 pub use install::Install;
+pub use link::Link;
 pub use list::List;
+pub use migrate::Migrate;
+pub use outdated::Outdated;
+pub use rebind::Rebind;
+pub use reindex::Reindex;
+pub use reinstall::Reinstall;
+pub use run::Run;
+pub use sync::Sync;
 pub use uninstall::Uninstall;
+pub use unlink::Unlink;
+pub use upgrade::Upgrade;
+pub use use_version::Use;
+pub use verify::Verify;
+pub use which::Which;
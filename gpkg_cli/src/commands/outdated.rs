@@ -0,0 +1,134 @@
+use super::Command;
+use crate::config::Config;
+use crate::print_format::PrintFormat;
+use colored::*;
+use gpkg::outdated::{check_outdated, registry_installations, OutdatedPackage};
+use gpkg::registry_config;
+use structopt::StructOpt;
+use thiserror::Error;
+
+#[derive(Debug, StructOpt)]
+pub struct Outdated {
+    #[structopt(long, default_value = "list", possible_values = PrintFormat::variants())]
+    format: PrintFormat,
+
+    /// The registry to compare with, instead of the one each package was installed from.
+    /// Paths are read as directories of packument JSON files.
+    #[structopt(long)]
+    registry: Option<String>,
+
+    /// Show the packages that are up to date too
+    #[structopt(long)]
+    all: bool,
+}
+
+#[derive(Debug, Error, miette::Diagnostic)]
+pub enum Errors {
+    #[error("Can't read the installed packages")]
+    #[diagnostic()]
+    Reading {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    RegistryConfig(#[from] registry_config::Errors),
+
+    #[error("Can't check {count} package(s) against the registry")]
+    #[diagnostic(help("Use `--registry` to compare with another registry"))]
+    CheckFailed { count: usize },
+}
+
+impl Command for Outdated {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let installations = registry_installations(config.installations_dir(), config.bin_dir())
+            .map_err(|source| Errors::Reading { source })?;
+
+        let registries = config.registries()?;
+        let mut packages = vec![];
+        let mut failures = 0;
+        for installation in installations {
+            let registry = match (&self.registry, &installation.registry) {
+                (Some(url), _) | (None, Some(url)) => registries.registry_at(url),
+                (None, None) => registries.registry(&installation.registry_name),
+            };
+            match check_outdated(&installation, &registry) {
+                Ok(package) if self.all || package.is_outdated() => packages.push(package),
+                Ok(_) => {}
+                Err(error) => {
+                    eprintln!("{:?}", miette::Report::new(error));
+                    failures += 1;
+                }
+            }
+        }
+
+        match self.format {
+            PrintFormat::List if packages.is_empty() && failures == 0 => {
+                println!("All packages are up to date")
+            }
+            PrintFormat::List => print_pretty_list(&packages),
+            PrintFormat::Table => print_pretty_table(&packages),
+            PrintFormat::Json => print_json(&packages),
+        }
+        if failures > 0 {
+            return Err(Errors::CheckFailed { count: failures });
+        }
+        Ok(())
+    }
+}
+
+fn print_pretty_list(packages: &[OutdatedPackage]) {
+    let max_width = packages
+        .iter()
+        .map(|package| package.name.len())
+        .max()
+        .unwrap_or_default();
+    for package in packages {
+        println!(
+            "{:<width$} {} {}",
+            package.name,
+            display(&package.current).yellow(),
+            format!(
+                "wanted {}, latest {}",
+                display(&package.wanted).green(),
+                display(&package.latest).magenta()
+            )
+            .dimmed()
+            .italic(),
+            width = max_width
+        );
+    }
+}
+
+fn print_pretty_table(packages: &[OutdatedPackage]) {
+    use prettytable::*;
+
+    let mut table = Table::new();
+
+    table.add_row(row![Fc => "package", "current", "wanted", "latest", "requested", "location"]);
+
+    for package in packages {
+        table.add_row(row![
+            package.name,
+            display(&package.current),
+            display(&package.wanted),
+            display(&package.latest),
+            package.requested.as_deref().unwrap_or_default(),
+            package.path.display(),
+        ]);
+    }
+
+    table.printstd();
+}
+
+fn print_json(packages: &[OutdatedPackage]) {
+    let json = serde_json::to_string(packages).expect("Can't make into a JSON string");
+    println!("{}", json);
+}
+
+fn display(version: &Option<String>) -> &str {
+    version.as_deref().unwrap_or("-")
+}
@@ -0,0 +1,86 @@
+use super::Command;
+use crate::config::Config;
+use colored::*;
+use gpkg::index::Index;
+use gpkg::install_package;
+use gpkg::node::NodeBinary;
+use gpkg::rebind_package::{packages_pinned_to, rebind_package};
+use std::collections::BTreeSet;
+use structopt::StructOpt;
+use thiserror::Error;
+
+#[derive(StructOpt, Debug)]
+pub struct Rebind {
+    /// The name of the installed package to rebind
+    #[structopt(required_unless_one = &["all", "from"])]
+    package: Option<String>,
+
+    /// Rebind all installed packages
+    #[structopt(long, conflicts_with_all = &["package", "from"])]
+    all: bool,
+
+    /// Rebind all packages that are pinned to this Node.js version
+    #[structopt(long, conflicts_with = "package")]
+    from: Option<String>,
+
+    /// The Node.js version to pin the binaries to: a version installed
+    /// using fnm, nvm or asdf, or a path to a node binary
+    #[structopt(long, alias = "to")]
+    node: String,
+}
+
+#[derive(Debug, Error, miette::Diagnostic)]
+pub enum Errors {
+    #[error("Can't read metadata files")]
+    #[diagnostic()]
+    ReadingMetadata {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Rebind(#[from] install_package::Errors),
+}
+
+impl Command for Rebind {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let node = NodeBinary::resolve(&self.node).map_err(install_package::Errors::from)?;
+
+        let packages: BTreeSet<String> = match (self.package, &self.from) {
+            (Some(package), _) => std::iter::once(package).collect(),
+            (None, from) => {
+                let binaries = config
+                    .index()
+                    .map(Index::into_binaries)
+                    .map_err(|source| Errors::ReadingMetadata { source })?;
+                match from {
+                    Some(from) if !self.all => packages_pinned_to(&binaries, from),
+                    _ => binaries
+                        .into_iter()
+                        .map(|metadata| metadata.package_name)
+                        .collect(),
+                }
+            }
+        };
+
+        for package in packages {
+            let binaries = rebind_package(
+                &package,
+                &node,
+                config.installations_dir(),
+                config.bin_dir(),
+            )?;
+            println!(
+                "Rebound {} ({}) to node {}",
+                package.cyan(),
+                binaries.join(", "),
+                node.version.yellow()
+            );
+        }
+
+        Ok(())
+    }
+}
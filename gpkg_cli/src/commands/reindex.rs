@@ -0,0 +1,30 @@
+use super::Command;
+use crate::config::{warn_about_corrupt_binaries, Config};
+use gpkg::index::Index;
+use structopt::StructOpt;
+use thiserror::Error;
+
+#[derive(StructOpt, Debug)]
+pub struct Reindex {}
+
+#[derive(Debug, Error, miette::Diagnostic)]
+pub enum Errors {
+    #[error("Can't rebuild the index of installed binaries")]
+    #[diagnostic()]
+    Rebuilding {
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl Command for Reindex {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let index = Index::rebuild(config.installations_dir(), config.bin_dir())
+            .map_err(|source| Errors::Rebuilding { source })?;
+        warn_about_corrupt_binaries(&index);
+        println!("Indexed {} binaries", index.binaries().count());
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use super::Command;
+use crate::config::Config;
+use colored::*;
+use gpkg::install_package::Errors;
+use gpkg::node_package_version::NodePackageVersion;
+use gpkg::reinstall_package::reinstall_package;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Reinstall {
+    /// The installed package. Takes the format of `name[@version]`,
+    /// where a version or range only reinstalls the installed versions in it
+    package: NodePackageVersion,
+
+    /// Reinstall from gpkg's tarball cache, without reaching the network.
+    /// Uses the native installer, which can only install from `package-lock.json` lockfiles
+    #[structopt(long)]
+    offline: bool,
+}
+
+impl Command for Reinstall {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let registries = config.registries()?;
+        let tarball_cache = config.tarball_cache();
+        let tarball_cache = if self.offline {
+            tarball_cache.offline()
+        } else {
+            tarball_cache
+        };
+        let installations = reinstall_package(
+            self.package.name(),
+            self.package.version_range().as_ref(),
+            config.installations_dir(),
+            config.bin_dir(),
+            &registries,
+            Some(&tarball_cache),
+        )?;
+        for installation in installations {
+            println!(
+                "Reinstalled {}{} from its lockfile",
+                self.package.name().cyan(),
+                format!("@{}", installation.version.as_deref().unwrap_or("unknown")).cyan()
+            );
+        }
+        Ok(())
+    }
+}
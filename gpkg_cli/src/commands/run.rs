@@ -0,0 +1,95 @@
+use super::Command;
+use crate::config::Config;
+use gpkg::node::{self, NodeBinary};
+use gpkg::node_package_version::NodePackageVersion;
+use gpkg::package_manager::PackageManagerKind;
+use gpkg::registry_config;
+use gpkg::run_package::{self, prepare_cached_package};
+use log::*;
+use std::path::PathBuf;
+use structopt::StructOpt;
+use thiserror::Error;
+
+#[derive(StructOpt, Debug)]
+pub struct Run {
+    /// The npm package to run. Takes the format of `name[@version]`,
+    /// or any other form `gpkg install` accepts
+    package: NodePackageVersion,
+
+    /// The Node.js version to run the package with: a version installed
+    /// using fnm, nvm or asdf, or a path to a node binary.
+    /// Defaults to the `node` in your PATH
+    #[structopt(long)]
+    node: Option<String>,
+
+    /// The binary to run, for packages with several binaries.
+    /// Defaults to the one named after the package
+    #[structopt(long)]
+    bin: Option<String>,
+
+    /// The package manager used to install the package into the cache.
+    /// Defaults to the `package_manager` set in `config.toml`, or npm
+    #[structopt(
+        long,
+        env = "GPKG_PACKAGE_MANAGER",
+        possible_values = PackageManagerKind::variants()
+    )]
+    package_manager: Option<PackageManagerKind>,
+
+    /// The arguments to pass to the binary
+    #[structopt(last = true)]
+    args: Vec<String>,
+}
+
+#[derive(Debug, Error, miette::Diagnostic)]
+pub enum Errors {
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Node(#[from] node::Errors),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Run(#[from] run_package::Errors),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    RegistryConfig(#[from] registry_config::Errors),
+
+    #[error("Can't run {path:?}")]
+    #[diagnostic()]
+    Spawning {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl Command for Run {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let node = match &self.node {
+            Some(node) => NodeBinary::resolve(node)?,
+            None => NodeBinary::current()?,
+        };
+        let registries = config.registries()?;
+        let cached = prepare_cached_package(
+            &self.package,
+            &node,
+            config.run_cache_dir(),
+            registries.package_manager_or(self.package_manager),
+            &registries,
+            Some(&config.tarball_cache()),
+        )?;
+        let path = cached.binary_path(self.bin.as_deref())?;
+        debug!("Running {:?} with node {}", path, node.version);
+        let status = std::process::Command::new(&path)
+            .args(&self.args)
+            .status()
+            .map_err(|source| Errors::Spawning { path, source })?;
+        if !status.success() {
+            std::process::exit(status.code().unwrap_or(1));
+        }
+        Ok(())
+    }
+}
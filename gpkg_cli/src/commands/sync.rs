@@ -0,0 +1,348 @@
+use super::Command;
+use crate::config::Config;
+use colored::*;
+use gpkg::install_package::{self, install_package, InstallOptions};
+use gpkg::node::NodeBinary;
+use gpkg::package_manager::PackageManagerKind;
+use gpkg::rebind_package::rebind_package;
+use gpkg::registry_config::RegistryConfig;
+use gpkg::tarball_cache::TarballCache;
+use gpkg::toolset::{self, installed_tools, SyncAction, Toolset, ToolsetLock};
+use gpkg::uninstall_package::{self, uninstall_package};
+use gpkg::upgrade_package::upgrade_package;
+use std::path::PathBuf;
+use structopt::StructOpt;
+use thiserror::Error;
+
+#[derive(StructOpt, Debug)]
+pub struct Sync {
+    /// The toolset to sync with: a TOML file, or a JSON file when it has a `.json` extension
+    #[structopt(long, short, default_value = "gpkg.toml", parse(from_os_str))]
+    file: PathBuf,
+
+    /// Uninstall packages that are not in the toolset
+    #[structopt(long)]
+    prune: bool,
+
+    /// Only print what would change, and fail if the installed tools drifted from the toolset
+    #[structopt(long)]
+    check: bool,
+
+    /// The package manager used for tools that don't set one.
+    /// Defaults to the `package_manager` set in `config.toml`, or npm
+    #[structopt(
+        long,
+        env = "GPKG_PACKAGE_MANAGER",
+        possible_values = PackageManagerKind::variants()
+    )]
+    package_manager: Option<PackageManagerKind>,
+
+    /// Install and upgrade tools from gpkg's tarball cache, without reaching the network.
+    /// Uses the native installer, which fills the cache on every install
+    #[structopt(long)]
+    offline: bool,
+
+    /// Install exactly the dependency trees locked in the lock next to the toolset
+    /// (`gpkg.lock` for `gpkg.toml`), failing when a tool isn't locked or the lock doesn't match the toolset.
+    /// The lock is written by every `gpkg sync` without `--frozen`
+    #[structopt(long)]
+    frozen: bool,
+}
+
+#[derive(Debug, Error, miette::Diagnostic)]
+pub enum Errors {
+    #[error("Can't read metadata files")]
+    #[diagnostic()]
+    ReadingMetadata {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Toolset(#[from] toolset::Errors),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Install(#[from] install_package::Errors),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Uninstall(#[from] uninstall_package::Errors),
+
+    #[error("{count} tool(s) drifted from the toolset")]
+    #[diagnostic(help("Run `gpkg sync` to apply the changes"))]
+    Drift { count: usize },
+}
+
+impl Command for Sync {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let toolset = Toolset::read(&self.file)?;
+        let installed = installed_tools(config.installations_dir(), config.bin_dir())
+            .map_err(|source| Errors::ReadingMetadata { source })?;
+        let registries = config.registries().map_err(install_package::Errors::from)?;
+        let tarball_cache = config.tarball_cache();
+        let tarball_cache = if self.offline {
+            tarball_cache.offline()
+        } else {
+            tarball_cache
+        };
+        let lock_path = ToolsetLock::path_for(&self.file);
+        let actions = if self.frozen {
+            let lock = ToolsetLock::read(&lock_path)?;
+            toolset.plan_frozen(&installed, &lock, &lock_path, self.prune)?
+        } else {
+            toolset.plan(&installed, self.prune, &registries, Some(&tarball_cache))?
+        };
+
+        if actions.is_empty() {
+            println!("All tools are in sync with {}", self.file.display());
+        } else if self.check {
+            for action in &actions {
+                println!("{}", describe(action));
+            }
+            return Err(Errors::Drift {
+                count: actions.len(),
+            });
+        } else {
+            self.apply_actions(&config, &registries, &tarball_cache, actions)?;
+        }
+
+        if !self.frozen && !self.check {
+            let installed = installed_tools(config.installations_dir(), config.bin_dir())
+                .map_err(|source| Errors::ReadingMetadata { source })?;
+            let lock = ToolsetLock::from_installed(&toolset, &installed);
+            if ToolsetLock::read(&lock_path).ok().as_ref() != Some(&lock) {
+                lock.write(&lock_path)?;
+                println!(
+                    "Locked {} tool(s) in {}",
+                    lock.tools.len(),
+                    lock_path.display()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Sync {
+    fn apply_actions(
+        &self,
+        config: &Config,
+        registries: &RegistryConfig,
+        tarball_cache: &TarballCache,
+        actions: Vec<SyncAction>,
+    ) -> Result<(), Errors> {
+        for action in actions {
+            println!("{}", describe(&action));
+            match action {
+                SyncAction::Install(request) => {
+                    let node = resolve_node(request.node.as_deref())?;
+                    install_package(
+                        &request.package,
+                        &node,
+                        config.installations_dir(),
+                        config.bin_dir(),
+                        &InstallOptions {
+                            package_manager: registries.package_manager_or(
+                                request.package_manager.or(self.package_manager),
+                            ),
+                            registries: registries.clone(),
+                            cache: Some(tarball_cache.clone()),
+                            ..InstallOptions::default()
+                        },
+                    )?;
+                }
+                SyncAction::Upgrade { request, .. } => {
+                    let node = resolve_node(request.node.as_deref())?;
+                    upgrade_package(
+                        &request.package,
+                        &node,
+                        config.installations_dir(),
+                        config.bin_dir(),
+                        request.package_manager,
+                        registries,
+                        Some(tarball_cache),
+                    )?;
+                }
+                SyncAction::Rebind { name, node, .. } => {
+                    let node = resolve_node(Some(&node))?;
+                    rebind_package(&name, &node, config.installations_dir(), config.bin_dir())?;
+                }
+                SyncAction::Uninstall { name } => {
+                    uninstall_package(&name, None, config.installations_dir(), config.bin_dir())?;
+                }
+                SyncAction::InstallLocked {
+                    request,
+                    locked,
+                    replaces,
+                    ..
+                } => {
+                    let node = resolve_node(request.node.as_deref())?;
+                    if replaces {
+                        uninstall_package(
+                            request.package.name(),
+                            None,
+                            config.installations_dir(),
+                            config.bin_dir(),
+                        )?;
+                    }
+                    install_package(
+                        &request.package,
+                        &node,
+                        config.installations_dir(),
+                        config.bin_dir(),
+                        &InstallOptions {
+                            registries: registries.clone(),
+                            cache: Some(tarball_cache.clone()),
+                            locked: Some(*locked),
+                            ..InstallOptions::default()
+                        },
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn resolve_node(node: Option<&str>) -> Result<NodeBinary, install_package::Errors> {
+    let node = match node {
+        Some(node) => NodeBinary::resolve(node)?,
+        None => NodeBinary::current()?,
+    };
+    Ok(node)
+}
+
+fn describe(action: &SyncAction) -> String {
+    match action {
+        SyncAction::Install(request) => format!(
+            "{} install {}",
+            "+".green(),
+            request.package.to_string().cyan()
+        ),
+        SyncAction::Upgrade {
+            request,
+            installed_version,
+        } => format!(
+            "{} upgrade {} from {} to {}",
+            "~".yellow(),
+            request.package.name().cyan(),
+            installed_version.as_deref().unwrap_or("an unknown version"),
+            request.package.version()
+        ),
+        SyncAction::Rebind {
+            name,
+            node,
+            installed_node,
+        } => format!(
+            "{} rebind {} from node {} to {}",
+            "~".yellow(),
+            name.cyan(),
+            installed_node,
+            node.yellow()
+        ),
+        SyncAction::Uninstall { name } => format!("{} uninstall {}", "-".red(), name.cyan()),
+        SyncAction::InstallLocked {
+            request,
+            locked,
+            replaces: false,
+            ..
+        } => format!(
+            "{} install {} {} from the lock",
+            "+".green(),
+            request.package.name().cyan(),
+            locked.version.as_deref().unwrap_or("an unknown version")
+        ),
+        SyncAction::InstallLocked {
+            request,
+            locked,
+            installed_version,
+            ..
+        } => format!(
+            "{} reinstall {} from {} to the locked {}",
+            "~".yellow(),
+            request.package.name().cyan(),
+            installed_version.as_deref().unwrap_or("an unknown version"),
+            locked.version.as_deref().unwrap_or("tree")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Install;
+    use crate::test_packages::{installed_version, write_package};
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    #[test]
+    fn parses_arguments() {
+        let sync =
+            Sync::from_iter_safe(&["sync", "--prune", "--check", "-f", "tools.toml"]).unwrap();
+        assert!(sync.prune);
+        assert!(sync.check);
+        assert_eq!(sync.file, PathBuf::from("tools.toml"));
+
+        let sync = Sync::from_iter_safe(&["sync", "--package-manager", "native"]).unwrap();
+        assert_eq!(sync.file, PathBuf::from("gpkg.toml"));
+        assert_eq!(sync.package_manager, Some(PackageManagerKind::Native));
+        assert!(Sync::from_iter_safe(&["sync", "--package-manager", "cargo"]).is_err());
+    }
+
+    #[test]
+    fn prunes_packages_missing_from_the_toolset() {
+        let sources = TempDir::new("sources").unwrap();
+        let config = Config::default();
+        let alpha = write_package(sources.path(), "alpha", "1.0.0");
+        let beta = write_package(sources.path(), "beta", "1.0.0");
+        let beta = beta.display().to_string();
+        Install::from_iter(&["install", "--package-manager", "native", &beta])
+            .apply(config.clone())
+            .unwrap();
+
+        let toolset = sources.path().join("gpkg.toml");
+        std::fs::write(
+            &toolset,
+            format!(
+                "[tools]\nalpha = {:?}\n",
+                format!("file:{}", alpha.display())
+            ),
+        )
+        .unwrap();
+        let toolset = toolset.display().to_string();
+        let result =
+            Sync::from_iter(&["sync", "--check", "--prune", "-f", &toolset]).apply(config.clone());
+        assert!(
+            matches!(result, Err(Errors::Drift { count: 2 })),
+            "{:?}",
+            result
+        );
+
+        Sync::from_iter(&["sync", "--package-manager", "native", "-f", &toolset])
+            .apply(config.clone())
+            .unwrap();
+        assert_eq!(
+            installed_version(&config, "alpha").as_deref(),
+            Some("1.0.0")
+        );
+        assert_eq!(installed_version(&config, "beta").as_deref(), Some("1.0.0"));
+
+        Sync::from_iter(&["sync", "--prune", "-f", &toolset])
+            .apply(config.clone())
+            .unwrap();
+        assert_eq!(
+            installed_version(&config, "alpha").as_deref(),
+            Some("1.0.0")
+        );
+        assert_eq!(installed_version(&config, "beta"), None);
+        Sync::from_iter(&["sync", "--check", "--prune", "-f", &toolset])
+            .apply(config)
+            .unwrap();
+    }
+}
@@ -1,76 +1,52 @@
 use super::Command;
 use crate::config::Config;
 use colored::*;
+use gpkg::install_package::installation_path;
 use gpkg::node_package_version::NodePackageVersion;
-use gpkg::storage::Metadata;
+use gpkg::uninstall_package::{uninstall_package, Errors};
 use structopt::StructOpt;
-use thiserror::Error;
 
 #[derive(StructOpt, Debug)]
 pub struct Uninstall {
+    /// The package to uninstall. Takes the format of `name[@version]`:
+    /// with a version or range, only the installed versions in it are removed
     version: NodePackageVersion,
 }
 
-#[derive(Debug, Error, miette::Diagnostic)]
-pub enum Errors {
-    #[error("Can't read metadata files")]
-    #[diagnostic()]
-    ReadingMetadata {
-        #[source]
-        source: std::io::Error,
-    },
-
-    #[error("Can't delete file {binary_path:?}")]
-    #[diagnostic()]
-    RemovingFile {
-        binary_path: std::path::PathBuf,
-        #[source]
-        source: std::io::Error,
-    },
-
-    #[error("Can't delete directory {package_path:?}")]
-    #[diagnostic()]
-    RemovingDirectory {
-        package_path: std::path::PathBuf,
-        #[source]
-        source: std::io::Error,
-    },
-}
-
 impl Command for Uninstall {
     type Error = Errors;
     fn apply(self, config: Config) -> Result<(), Self::Error> {
-        let binaries = Metadata::read_all(config.bin_dir())
-            .map_err(|source| Errors::ReadingMetadata { source })?;
-        let binaries = binaries
-            .iter()
-            .filter(|metadata| metadata.package_name == self.version.name());
-
-        for binary_metadata in binaries {
-            let binary_name = &binary_metadata.binary_name;
-            let binary_path = config.bin_dir().join(binary_name);
-
-            std::fs::remove_file(&binary_path).map_err(|source| Errors::RemovingFile {
-                binary_path,
-                source,
-            })?;
-
-            println!("Deleted binary {}", binary_metadata.binary_name.cyan());
+        let range = self.version.version_range();
+        let uninstalled = uninstall_package(
+            self.version.name(),
+            range.as_ref(),
+            config.installations_dir(),
+            config.bin_dir(),
+        )?;
+
+        for binary_name in uninstalled.binaries {
+            println!("Deleted binary {}", binary_name.cyan());
         }
 
-        let package_path = config.installations_dir().join(self.version.name());
-        if package_path.exists() {
-            std::fs::remove_dir_all(&package_path).map_err(|source| Errors::RemovingDirectory {
-                package_path,
-                source,
-            })?;
-            println!("Removed package {}", self.version.name().cyan());
-        } else {
+        for package_path in &uninstalled.installation_paths {
             println!(
-                "Directory {} does not exist",
-                package_path.to_str().unwrap().cyan()
+                "Removed package {} from {}",
+                self.version.name().cyan(),
+                package_path.display().to_string().cyan()
             );
         }
+        if uninstalled.installation_paths.is_empty() {
+            if range.is_some() {
+                println!("No installed version of {} matches", self.version);
+            } else {
+                let package_path =
+                    installation_path(config.installations_dir(), self.version.name());
+                println!(
+                    "Directory {} does not exist",
+                    package_path.to_str().unwrap().cyan()
+                );
+            }
+        }
 
         Ok(())
     }
@@ -0,0 +1,31 @@
+use super::Command;
+use crate::config::Config;
+use colored::*;
+use gpkg::install_package::{self, InstalledPackage};
+use gpkg::link_package::unlink_package;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Unlink {
+    /// The name of the linked package.
+    /// Defaults to the package in the current directory
+    package: Option<String>,
+}
+
+impl Command for Unlink {
+    type Error = install_package::Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let package_name = match self.package {
+            Some(package) => package,
+            None => InstalledPackage::read(".")?.name().to_string(),
+        };
+        for binary_name in
+            unlink_package(&package_name, config.installations_dir(), config.bin_dir())?
+        {
+            println!("Deleted binary {}", binary_name.cyan());
+        }
+        println!("Unlinked package {}", package_name.cyan());
+        Ok(())
+    }
+}
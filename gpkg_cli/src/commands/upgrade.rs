@@ -0,0 +1,212 @@
+use super::Command;
+use crate::config::Config;
+use colored::*;
+use gpkg::index::Index;
+use gpkg::install_package;
+use gpkg::node::NodeBinary;
+use gpkg::node_package_version::{InvalidPackageSpec, NodePackageVersion};
+use gpkg::package_manager::PackageManagerKind;
+use gpkg::storage::LatestMetadata;
+use gpkg::upgrade_package::upgrade_package;
+use log::*;
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+use thiserror::Error;
+
+#[derive(StructOpt, Debug)]
+pub struct Upgrade {
+    /// The npm package to upgrade. Takes the format of `name[@version]`
+    #[structopt(required_unless = "all")]
+    package: Option<NodePackageVersion>,
+
+    /// Upgrade all installed packages to the latest versions in the ranges they were installed with,
+    /// going on when one fails
+    #[structopt(long, conflicts_with = "package")]
+    all: bool,
+
+    /// The Node.js version to pin the upgraded packages to: a version installed
+    /// using fnm, nvm or asdf, or a path to a node binary.
+    /// Defaults to the node each package is pinned to, or the `node` in your PATH
+    /// for packages installed before gpkg recorded it
+    #[structopt(long)]
+    node: Option<String>,
+
+    /// The package manager used to install the new version.
+    /// Defaults to the one that installed the package
+    #[structopt(long, possible_values = PackageManagerKind::variants())]
+    package_manager: Option<PackageManagerKind>,
+}
+
+#[derive(Debug, Error, miette::Diagnostic)]
+pub enum Errors {
+    #[error("Can't read metadata files")]
+    #[diagnostic()]
+    ReadingMetadata {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Upgrade(#[from] install_package::Errors),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    InvalidPackage(#[from] InvalidPackageSpec),
+
+    #[error("Can't upgrade {count} package(s)")]
+    #[diagnostic(help("Check the errors above for more information"))]
+    UpgradeFailed { count: usize },
+}
+
+impl Command for Upgrade {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let binaries = config
+            .index()
+            .map(Index::into_binaries)
+            .map_err(|source| Errors::ReadingMetadata { source })?;
+        let requested_node = match &self.node {
+            Some(node) => Some(NodeBinary::resolve(node).map_err(install_package::Errors::from)?),
+            None => None,
+        };
+
+        let registries = config.registries().map_err(install_package::Errors::from)?;
+        let upgrade = |package: &NodePackageVersion| -> Result<(), Errors> {
+            debug!("Upgrading package {:?}", package);
+            let node = match &requested_node {
+                Some(node) => node.clone(),
+                None => pinned_node(&binaries, package.name())?,
+            };
+            upgrade_package(
+                package,
+                &node,
+                config.installations_dir(),
+                config.bin_dir(),
+                self.package_manager,
+                &registries,
+                Some(&config.tarball_cache()),
+            )?;
+            println!("Upgraded package {}", package.name().cyan());
+            Ok(())
+        };
+
+        if !self.all {
+            return self.package.iter().try_for_each(upgrade);
+        }
+        let mut failures = 0;
+        for spec in upgradable_packages(&binaries) {
+            let result = spec
+                .parse()
+                .map_err(Errors::from)
+                .and_then(|package| upgrade(&package));
+            if let Err(error) = result {
+                eprintln!("{:?}", miette::Report::new(error));
+                failures += 1;
+            }
+        }
+        if failures > 0 {
+            return Err(Errors::UpgradeFailed { count: failures });
+        }
+        Ok(())
+    }
+}
+
+/// What every installed package is upgraded to with `--all`: packages from git or local files
+/// are upgraded from where they came from, and registry packages within the range they were
+/// requested with. Linked packages, and versions installed side by side, are left alone.
+fn upgradable_packages(binaries: &[LatestMetadata]) -> Vec<String> {
+    binaries
+        .iter()
+        .filter(|metadata| {
+            if metadata.linked {
+                debug!("Skipping linked package {}", metadata.package_name);
+            }
+            !metadata.linked && metadata.installation.is_none()
+        })
+        .map(|metadata| {
+            let name = &metadata.package_name;
+            let spec = match metadata.source.as_ref().or(metadata.requested.as_ref()) {
+                Some(spec) => format!("{}@{}", name, spec),
+                None => name.clone(),
+            };
+            (name, spec)
+        })
+        .collect::<BTreeMap<_, _>>()
+        .into_values()
+        .collect()
+}
+
+/// The node a package was pinned to when it was installed,
+/// or the current one when that wasn't recorded
+fn pinned_node(
+    binaries: &[LatestMetadata],
+    package_name: &str,
+) -> Result<NodeBinary, install_package::Errors> {
+    let node_path = binaries
+        .iter()
+        .filter(|metadata| metadata.package_name == package_name)
+        .filter(|metadata| metadata.package_binary.is_none())
+        .find_map(|metadata| metadata.node_path.as_ref());
+    let node = match node_path {
+        Some(node_path) => NodeBinary::from_path(node_path)?,
+        None => NodeBinary::current()?,
+    };
+    Ok(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Install;
+    use crate::test_packages::{installed_version, write_package};
+    use pretty_assertions::assert_eq;
+    use std::path::Path;
+    use tempdir::TempDir;
+
+    fn install(config: &Config, package_dir: &Path) {
+        let package_dir = package_dir.display().to_string();
+        Install::from_iter(&["install", "--package-manager", "native", &package_dir])
+            .apply(config.clone())
+            .unwrap();
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let upgrade = Upgrade::from_iter_safe(&["upgrade", "--all"]).unwrap();
+        assert!(upgrade.all);
+        assert!(upgrade.package.is_none());
+        assert!(Upgrade::from_iter_safe(&["upgrade"]).is_err());
+        assert!(Upgrade::from_iter_safe(&["upgrade", "--all", "tool"]).is_err());
+
+        let upgrade =
+            Upgrade::from_iter_safe(&["upgrade", "tool@^2", "--package-manager", "pnpm"]).unwrap();
+        assert_eq!(upgrade.package.unwrap().to_string(), "tool@^2");
+        assert_eq!(upgrade.package_manager, Some(PackageManagerKind::Pnpm));
+    }
+
+    #[test]
+    fn upgrades_all_packages_past_failures() {
+        let sources = TempDir::new("sources").unwrap();
+        let config = Config::default();
+        let alpha = write_package(sources.path(), "alpha", "1.0.0");
+        let beta = write_package(sources.path(), "beta", "1.0.0");
+        install(&config, &alpha);
+        install(&config, &beta);
+
+        write_package(sources.path(), "alpha", "2.0.0");
+        std::fs::remove_dir_all(&beta).unwrap();
+        let result = Upgrade::from_iter(&["upgrade", "--all"]).apply(config.clone());
+        assert!(
+            matches!(result, Err(Errors::UpgradeFailed { count: 1 })),
+            "{:?}",
+            result
+        );
+        assert_eq!(
+            installed_version(&config, "alpha").as_deref(),
+            Some("2.0.0")
+        );
+        assert_eq!(installed_version(&config, "beta").as_deref(), Some("1.0.0"));
+    }
+}
@@ -0,0 +1,31 @@
+use super::Command;
+use crate::config::Config;
+use colored::*;
+use gpkg::install_package;
+use gpkg::node_package_version::NodePackageVersion;
+use gpkg::use_package::use_package;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Use {
+    /// The installed version to use. Takes the format of `name[@version]`,
+    /// where the version can be a range. Defaults to the highest installed version
+    package: NodePackageVersion,
+}
+
+impl Command for Use {
+    type Error = install_package::Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let used = use_package(&self.package, config.installations_dir(), config.bin_dir())?;
+        for binary_name in &used.binaries {
+            println!("Updated binary {}", binary_name.cyan());
+        }
+        println!(
+            "Using {}@{}",
+            used.name.cyan(),
+            used.version.as_deref().unwrap_or("unknown").cyan()
+        );
+        Ok(())
+    }
+}
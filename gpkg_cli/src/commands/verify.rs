@@ -0,0 +1,103 @@
+use super::Command;
+use crate::config::Config;
+use colored::*;
+use gpkg::file_manifest::{
+    self, installations_to_verify, record_manifest, verify_installation, FileChange,
+};
+use structopt::StructOpt;
+use thiserror::Error;
+
+#[derive(Debug, StructOpt)]
+pub struct Verify {
+    /// The package to verify. Every installed package is verified when omitted
+    package: Option<String>,
+
+    /// Trust the current files of the installations, recording their hashes again
+    #[structopt(long)]
+    update: bool,
+}
+
+#[derive(Debug, Error, miette::Diagnostic)]
+pub enum Errors {
+    #[error("Can't read the installed packages")]
+    #[diagnostic()]
+    Reading {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Package {0:?} is not installed")]
+    #[diagnostic(help("Linked packages are not verified, as their files live in a working copy"))]
+    PackageNotInstalled(String),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    FileManifest(#[from] file_manifest::Errors),
+
+    #[error("{count} installation(s) changed since they were installed")]
+    #[diagnostic(help(
+        "Reinstall them from their lockfiles with `gpkg reinstall <package>`, \
+         or run `gpkg verify --update` if the changes are yours"
+    ))]
+    Modified { count: usize },
+}
+
+impl Command for Verify {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let installations = installations_to_verify(
+            config.installations_dir(),
+            config.bin_dir(),
+            self.package.as_deref(),
+        )
+        .map_err(|source| Errors::Reading { source })?;
+        if let (Some(package), true) = (&self.package, installations.is_empty()) {
+            return Err(Errors::PackageNotInstalled(package.clone()));
+        }
+
+        let mut failures = 0;
+        for package in installations {
+            let name = format!(
+                "{}@{}",
+                package.package_name,
+                package.installation.version.as_deref().unwrap_or("unknown")
+            );
+            let path = &package.installation.path;
+            if self.update {
+                record_manifest(path, package.package_manager)?;
+                println!("{} {} recorded", "✓".green(), name);
+                continue;
+            }
+            match verify_installation(path) {
+                Ok(changes) if changes.is_empty() => println!("{} {}", "✓".green(), name),
+                Ok(changes) => {
+                    println!("{} {}", "✗".red(), name);
+                    for change in &changes {
+                        print_change(change);
+                    }
+                    failures += 1;
+                }
+                Err(error) => {
+                    println!("{} {}", "✗".red(), name);
+                    eprintln!("{:?}", miette::Report::new(error));
+                    failures += 1;
+                }
+            }
+        }
+
+        if failures > 0 {
+            return Err(Errors::Modified { count: failures });
+        }
+        Ok(())
+    }
+}
+
+fn print_change(change: &FileChange) {
+    let (kind, path) = match change {
+        FileChange::Modified(path) => ("modified".yellow(), path),
+        FileChange::Added(path) => ("added".red(), path),
+        FileChange::Missing(path) => ("missing".red(), path),
+    };
+    println!("  {:<8} {}", kind, path);
+}
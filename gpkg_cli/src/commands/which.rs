@@ -0,0 +1,72 @@
+use super::Command;
+use crate::config::Config;
+use crate::print_format::PrintFormat;
+use colored::*;
+use gpkg::info::{binary_info, BinaryInfo, Errors};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Which {
+    /// The name of the binary
+    binary_name: String,
+
+    #[structopt(long, default_value = "list", possible_values = &["list", "json"])]
+    format: PrintFormat,
+}
+
+impl Command for Which {
+    type Error = Errors;
+
+    fn apply(self, config: Config) -> Result<(), Self::Error> {
+        let info = binary_info(
+            config.installations_dir(),
+            config.bin_dir(),
+            &self.binary_name,
+        )?;
+        match self.format {
+            PrintFormat::Json => {
+                let json = serde_json::to_string(&info).expect("Can't make into a JSON string");
+                println!("{}", json);
+            }
+            PrintFormat::List | PrintFormat::Table => print_binary_info(&info),
+        }
+        Ok(())
+    }
+}
+
+fn print_binary_info(info: &BinaryInfo) {
+    let shim = if info.native_shim {
+        "native shim"
+    } else {
+        "script"
+    };
+    println!("{} ({})", info.shim_path.display(), shim);
+    let target = match &info.target_path {
+        Some(target_path) => target_path.display().to_string(),
+        None => "(not found in the package)".to_string(),
+    };
+    println!("  {:<8} {}", "runs".dimmed(), target);
+    let node_path = match &info.node_path {
+        Some(node_path) => node_path.display().to_string(),
+        None => "(its path was never recorded)".to_string(),
+    };
+    println!(
+        "  {:<8} {} ({})",
+        "node".dimmed(),
+        info.node_version.yellow(),
+        node_path
+    );
+    let version = match &info.package_version {
+        Some(version) => format!("@{}", version),
+        None => String::new(),
+    };
+    let linked = if info.linked { ", linked" } else { "" };
+    println!(
+        "  {:<8} {}{} in {}{}",
+        "package".dimmed(),
+        info.package_name.cyan(),
+        version.cyan(),
+        info.package_path.display(),
+        linked
+    );
+}
@@ -1,7 +1,11 @@
+use colored::*;
+use gpkg::index::Index;
+use gpkg::registry_config::{self, RegistryConfig};
+use gpkg::tarball_cache::TarballCache;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
-#[derive(Debug, StructOpt)]
+#[derive(Debug, Clone, StructOpt)]
 #[structopt(rename_all = "kebab-case")]
 pub struct Config {
     /// Gpkg root directory.
@@ -33,6 +37,39 @@ impl Config {
         }
         path
     }
+
+    /// Where the packages run with `gpkg x` are installed
+    pub fn run_cache_dir(&self) -> PathBuf {
+        self.root_dir().join("cache").join("run")
+    }
+
+    /// Where the tarballs downloaded by the native installer are kept
+    pub fn tarball_cache(&self) -> TarballCache {
+        TarballCache::new(self.root_dir().join("cache").join("tarballs"))
+    }
+
+    /// The registries packages are installed from, configured in `config.toml` in the root directory
+    pub fn registries(&self) -> Result<RegistryConfig, registry_config::Errors> {
+        RegistryConfig::load(&self.root_dir().join("config.toml"))
+    }
+
+    /// The index of installed binaries, warning about the corrupt ones
+    pub fn index(&self) -> std::io::Result<Index> {
+        let index = Index::load(self.installations_dir(), self.bin_dir())?;
+        warn_about_corrupt_binaries(&index);
+        Ok(index)
+    }
+}
+
+pub fn warn_about_corrupt_binaries(index: &Index) {
+    for (binary_name, reason) in index.corrupt_binaries() {
+        eprintln!(
+            "{} skipping corrupt binary {}, run `gpkg doctor` for details: {}",
+            "warning:".yellow().bold(),
+            binary_name.cyan(),
+            reason
+        );
+    }
 }
 
 #[cfg(test)]
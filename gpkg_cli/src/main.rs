@@ -2,6 +2,8 @@ mod cli;
 mod commands;
 mod config;
 mod print_format;
+#[cfg(test)]
+mod test_packages;
 
 use cli::Cli;
 use miette::Result;
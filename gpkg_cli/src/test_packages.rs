@@ -0,0 +1,32 @@
+//! Local packages for the tests of the commands, installed with the native installer
+
+use crate::config::Config;
+use std::path::{Path, PathBuf};
+
+/// Writes a package named `name` into `dir`, with a binary of the same name printing `version`.
+/// Returns the package directory.
+pub fn write_package(dir: &Path, name: &str, version: &str) -> PathBuf {
+    let package_dir = dir.join(name);
+    std::fs::create_dir_all(&package_dir).unwrap();
+    let manifest = serde_json::json!({
+        "name": name,
+        "version": version,
+        "bin": { name: "cli.js" },
+    });
+    std::fs::write(package_dir.join("package.json"), manifest.to_string()).unwrap();
+    std::fs::write(
+        package_dir.join("cli.js"),
+        format!("#!/usr/bin/env node\nconsole.log('{}')", version),
+    )
+    .unwrap();
+    package_dir
+}
+
+/// The installed version of `package_name`, as recorded in its binaries
+pub fn installed_version(config: &Config, package_name: &str) -> Option<String> {
+    config
+        .index()
+        .unwrap()
+        .package_binaries(package_name)
+        .find_map(|metadata| metadata.package_version.clone())
+}
@@ -0,0 +1,193 @@
+//! A tiny executable that gpkg hard links (or copies) into the bin directory for every binary.
+//!
+//! It reads the node binary and the file to run from the sidecar file named after itself,
+//! `.shims/<name>.json`, and runs them directly, without going through a shell.
+
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The parts of the sidecar file the shim needs. gpkg also keeps the binary metadata in it.
+#[derive(Deserialize, Debug)]
+struct Sidecar {
+    node_path: PathBuf,
+    target_path: PathBuf,
+}
+
+fn sidecar_path(shim_path: &Path) -> PathBuf {
+    let file_name = shim_path.file_name().unwrap_or_default().to_string_lossy();
+    let binary_name = file_name
+        .strip_suffix(std::env::consts::EXE_SUFFIX)
+        .filter(|name| !name.is_empty())
+        .unwrap_or(&file_name);
+    shim_path
+        .with_file_name(".shims")
+        .join(format!("{}.json", binary_name))
+}
+
+/// Whether the target is a node script, and not an executable of its own
+fn is_node_script(target_path: &Path) -> bool {
+    let mut start = [0; 128];
+    let read = std::fs::File::open(target_path)
+        .and_then(|mut file| file.read(&mut start))
+        .unwrap_or(0);
+    let first_line = start[..read].split(|byte| *byte == b'\n').next();
+    match first_line {
+        Some(line) if line.starts_with(b"#!") => String::from_utf8_lossy(line).contains("node"),
+        _ => false,
+    }
+}
+
+/// The command the shim at `shim_path` runs, with the directory of its node binary
+/// first in `PATH` so scripts that run `node` get the same one
+fn command(
+    shim_path: &Path,
+    args: impl IntoIterator<Item = OsString>,
+    path_env: Option<OsString>,
+) -> Result<Command, String> {
+    let sidecar_path = sidecar_path(shim_path);
+    let sidecar = std::fs::read(&sidecar_path)
+        .map_err(|error| error.to_string())
+        .and_then(|contents| {
+            serde_json::from_slice::<Sidecar>(&contents).map_err(|error| error.to_string())
+        })
+        .map_err(|error| format!("can't read {}: {}", sidecar_path.display(), error))?;
+
+    let node_dir = sidecar.node_path.parent().map(Path::to_path_buf);
+    let paths = node_dir
+        .into_iter()
+        .chain(path_env.iter().flat_map(std::env::split_paths));
+    let path_env = std::env::join_paths(paths).map_err(|error| error.to_string())?;
+
+    let mut command = if is_node_script(&sidecar.target_path) {
+        let mut command = Command::new(&sidecar.node_path);
+        command.arg(&sidecar.target_path);
+        command
+    } else {
+        Command::new(&sidecar.target_path)
+    };
+    command.args(args).env("PATH", path_env);
+    Ok(command)
+}
+
+#[cfg(unix)]
+fn run(mut command: Command) -> i32 {
+    use std::os::unix::process::CommandExt;
+    let error = command.exec();
+    eprintln!(
+        "gpkg-shim: can't run {}: {}",
+        Path::new(command.get_program()).display(),
+        error
+    );
+    126
+}
+
+#[cfg(not(unix))]
+fn run(mut command: Command) -> i32 {
+    match command.status() {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(error) => {
+            eprintln!(
+                "gpkg-shim: can't run {}: {}",
+                Path::new(command.get_program()).display(),
+                error
+            );
+            126
+        }
+    }
+}
+
+fn main() {
+    let result = std::env::current_exe()
+        .map_err(|error| format!("can't find out where the shim is: {}", error))
+        .and_then(|shim_path| {
+            command(
+                &shim_path,
+                std::env::args_os().skip(1),
+                std::env::var_os("PATH"),
+            )
+        });
+    match result {
+        Ok(command) => std::process::exit(run(command)),
+        Err(error) => {
+            eprintln!("gpkg-shim: {}", error);
+            std::process::exit(127);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    fn setup(target_contents: &str) -> (TempDir, PathBuf) {
+        let dir = TempDir::new("gpkg-shim").unwrap();
+        let shims_dir = dir.path().join("bin/.shims");
+        std::fs::create_dir_all(&shims_dir).unwrap();
+        let target_path = dir.path().join("cli.js");
+        std::fs::write(&target_path, target_contents).unwrap();
+        std::fs::write(
+            shims_dir.join("tsc.json"),
+            serde_json::json!({
+                "node_path": "/opt/node/bin/node",
+                "target_path": target_path,
+                "metadata": {},
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let shim_path = dir.path().join("bin/tsc");
+        (dir, shim_path)
+    }
+
+    fn args(command: &Command) -> Vec<String> {
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn runs_node_scripts_with_node() {
+        let (dir, shim_path) = setup("#!/usr/bin/env node\nconsole.log('hi')");
+        let command = command(
+            &shim_path,
+            vec!["--version".into()],
+            Some("/usr/bin".into()),
+        )
+        .unwrap();
+        assert_eq!(command.get_program(), "/opt/node/bin/node");
+        assert_eq!(
+            args(&command),
+            vec![
+                dir.path().join("cli.js").display().to_string(),
+                "--version".to_string()
+            ]
+        );
+        let path_env = command
+            .get_envs()
+            .find(|(key, _)| *key == "PATH")
+            .and_then(|(_, value)| value)
+            .unwrap();
+        assert_eq!(path_env, "/opt/node/bin:/usr/bin");
+    }
+
+    #[test]
+    fn runs_other_executables_directly() {
+        let (dir, shim_path) = setup("#!/bin/sh\necho hi");
+        let command = command(&shim_path, vec![], None).unwrap();
+        assert_eq!(command.get_program(), dir.path().join("cli.js"));
+        assert!(args(&command).is_empty());
+    }
+
+    #[test]
+    fn fails_without_a_sidecar() {
+        let (dir, _) = setup("");
+        let error = command(&dir.path().join("bin/other"), vec![], None).unwrap_err();
+        assert!(error.contains("other.json"));
+    }
+}